@@ -1,36 +1,82 @@
 use anyhow::{anyhow, Context, Result};
 use cairo::{
     Antialias, Context as CairoContext, FontOptions, Format, HintMetrics, HintStyle, ImageSurface,
+    SubpixelOrder,
 };
 use memfd::MemfdOptions;
 use memmap2::MmapMut;
 use pangocairo::functions as pangocairo;
 use serde::{Deserialize, Serialize};
-use shell_words;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::fs::OpenOptions;
-use std::io::ErrorKind;
-use std::os::unix::io::{AsFd, AsRawFd};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::io::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use wayland_client::protocol::{
-    wl_buffer::WlBuffer, wl_compositor::WlCompositor, wl_output::WlOutput, wl_pointer::WlPointer,
-    wl_region::WlRegion, wl_registry::WlRegistry, wl_seat::WlSeat, wl_shm::WlShm,
-    wl_shm_pool::WlShmPool, wl_surface::WlSurface,
+    wl_buffer::WlBuffer,
+    wl_compositor::WlCompositor,
+    wl_keyboard::WlKeyboard,
+    wl_output::{Transform, WlOutput},
+    wl_pointer::{Axis as WlPointerAxis, WlPointer},
+    wl_region::WlRegion,
+    wl_registry::WlRegistry,
+    wl_seat::WlSeat,
+    wl_shm::WlShm,
+    wl_shm_pool::WlShmPool,
+    wl_surface::WlSurface,
 };
 use wayland_client::{
     backend::WaylandError,
-    globals::{registry_queue_init, GlobalListContents},
-    Connection, Dispatch, Proxy, QueueHandle,
+    globals::{registry_queue_init, BindError, GlobalListContents},
+    Connection, Dispatch, DispatchError, Proxy, QueueHandle,
+};
+use wayland_protocols::ext::idle_notify::v1::client::{
+    ext_idle_notification_v1::{self, ExtIdleNotificationV1},
+    ext_idle_notifier_v1::{self, ExtIdleNotifierV1},
+};
+use wayland_protocols::wp::cursor_shape::v1::client::{
+    wp_cursor_shape_device_v1::{self, WpCursorShapeDeviceV1},
+    wp_cursor_shape_manager_v1::{self, WpCursorShapeManagerV1},
+};
+use wayland_protocols::xdg::shell::client::{
+    xdg_surface::{self, XdgSurface},
+    xdg_toplevel::{self, XdgToplevel},
+    xdg_wm_base::{self, XdgWmBase},
 };
 use wayland_protocols_wlr::layer_shell::v1::client::{
     zwlr_layer_shell_v1::{self, ZwlrLayerShellV1},
     zwlr_layer_surface_v1::{self, ZwlrLayerSurfaceV1},
 };
 
-#[derive(Clone, Copy, Debug, Default)]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        log_write(LogLevel::Warn, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        log_write(LogLevel::Info, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        log_write(LogLevel::Debug, format_args!($($arg)*))
+    };
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 struct Margins {
     top: i32,
     right: i32,
@@ -50,27 +96,123 @@ enum Position {
     Bottom,
     BottomRight,
     Default,
+    Cursor,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct Config {
     font: String,
+    font_scale: f64,
+    fallback_fonts: Vec<String>,
+    line_spacing: f32,
+    letter_spacing: i32,
+    justify: bool,
+    strip_markup: bool,
+    template: bool,
+    template_strict: bool,
     width: i32,
-    padding: i32,
-    border_size: i32,
+    /// `--width N%`: resolved against the output; `width` holds N as a
+    /// pixel fallback until then.
+    width_percent: Option<f64>,
+    min_width: i32,
+    fit_width: bool,
+    padding: Margins,
+    border_size: Margins,
     border_radius: i32,
+    /// `--corner-smoothing`: 0 is a circular arc, 1 a full squircle.
+    corner_smoothing: f64,
+    tail: Option<Tail>,
+    show_time: Option<ShowTime>,
+    urgency: Urgency,
     timeout_ms: u64,
+    min_timeout_ms: u64,
+    max_timeout_ms: u64,
+    allow_persistent: bool,
+    min_duration_ms: u64,
+    double_buffer: bool,
+    bypass_dnd: bool,
+    on_right_click: Option<String>,
+    on_middle_click: Option<String>,
+    keep_open_on_right_click: bool,
+    keep_open_on_middle_click: bool,
+    scroll_dismiss: bool,
+    click_through: bool,
+    /// Which pointer button closes the alert; `None` for none.
+    dismiss_button: Option<DismissButton>,
+    sound: Option<String>,
+    bell: bool,
+    idle_exit_ms: u64,
+    defer_while_inhibited: bool,
+    timeout_bar: bool,
+    timeout_bar_color: Option<[f64; 4]>,
     background: [f64; 4],
+    background_gradient: Vec<[f64; 4]>,
+    background_image: Option<String>,
+    gradient_angle: f64,
+    opacity: f64,
     text: [f64; 4],
+    /// `--text auto`: black or white, whichever reads better on the background.
+    text_auto: bool,
+    /// `--text-outline`: stroke width around the glyphs, outside them.
+    text_outline: i32,
+    text_outline_color: [f64; 4],
     border: [f64; 4],
+    separator: bool,
+    /// `None` is the text color, dimmed like the app name.
+    separator_color: Option<[f64; 4]>,
+    blink: bool,
+    blink_color: [f64; 4],
     edge: i32,
     default_offset: i32,
+    /// `--offset`: extra distance from the anchored edge.
+    offset: i32,
     stack_gap: i32,
+    /// `--slot-height`: fixed stacking step; 0 stacks by content height.
+    slot_height: i32,
+    stack_order: StackOrder,
+    priority: i32,
+    max_stack: u32,
+    on_full: OnFull,
     stack: bool,
+    rate_limit: u32,
+    coalesce: bool,
+    group_by: Option<GroupBy>,
+    layer: zwlr_layer_shell_v1::Layer,
+    namespace: String,
+    keyboard: zwlr_layer_surface_v1::KeyboardInteractivity,
+    /// `--exclusive-zone`; `None` is `auto`, the alert's height.
+    exclusive_zone: Option<i32>,
+    fallback_window: bool,
+    all_outputs: bool,
+    /// `--output`: connector name, or text to find in make and model.
+    output: Option<String>,
+    output_index: Option<usize>,
     output_scale: i32,
     text_antialias: Option<Antialias>,
     text_hint: Option<HintStyle>,
     text_hint_metrics: Option<HintMetrics>,
+    subpixel_order: Option<SubpixelOrder>,
+    ellipsize: pango::EllipsizeMode,
+    /// `None` is `--wrap none`: one line per paragraph.
+    wrap: Option<pango::WrapMode>,
+    max_lines: u32,
+    history: bool,
+    history_max_bytes: u64,
+    summary_length: usize,
+    ellipsis: String,
+    lock_timeout_ms: u64,
+    reposition_interval_ms: u64,
+    poll_interval_ms: u64,
+    log_level: Option<LogLevel>,
+    log_file: Option<String>,
+    json_errors: bool,
+    dry_run: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct AlertAction {
+    id: String,
+    label: String,
 }
 
 #[derive(Debug)]
@@ -79,120 +221,659 @@ struct AlertArgs {
     message: String,
     name: Option<String>,
     class: Option<String>,
+    print_id: bool,
+    actions: Vec<AlertAction>,
+    /// The CLI tokens this alert came from, so a snooze can re-run it.
+    argv: Vec<String>,
+    /// Unix ms for `--show-time`; a re-shown snooze keeps the original.
+    created_at: Option<u64>,
+    app_name: Option<String>,
+    /// `--replace-id`: take over this live alert's slot and id.
+    replace_id: Option<u64>,
+}
+
+/// What one paint draws.
+struct Content<'a> {
+    text: &'a str,
+    /// `--app-name`, as a dimmed label above the text.
+    app_name: Option<&'a str>,
+    actions: &'a [AlertAction],
+}
+
+/// `--on-right-click`/`--on-middle-click`.
+#[derive(Clone, Debug)]
+struct ClickCommand {
+    command: String,
+    keep_open: bool,
+}
+
+const INHIBIT_PROBE_MS: u32 = 2000;
+
+/// Roughly three wheel notches, or a short flick on a touchpad.
+const SCROLL_DISMISS_DISTANCE: f64 = 40.0;
+
+/// linux/input-event-codes.h
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+const BTN_MIDDLE: u32 = 0x112;
+const KEY_1: u32 = 2;
+const KEY_9: u32 = 10;
+const KEY_TAB: u32 = 15;
+const KEY_ENTER: u32 = 28;
+const KEY_SPACE: u32 = 57;
+const KEY_KPENTER: u32 = 96;
+const KEY_LEFT: u32 = 105;
+const KEY_RIGHT: u32 = 106;
+
+/// What a key press does to the `--action` buttons.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ActionKey {
+    Select(usize),
+    Activate(usize),
+}
+
+#[derive(Clone, Debug)]
+struct ActionButton {
+    id: String,
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
 }
 
 #[derive(Debug)]
 enum Command {
     Help,
+    Version,
     Show(AlertArgs),
     ListActive,
     ClearByName(String),
     ClearByClass(String),
     ClearById(u64),
+    History,
+    Pause,
+    Resume,
+    Dnd(DndAction),
+    SnoozeById(u64, u64),
+    SnoozeByName(String, u64),
+    UpdateById(u64, String),
+    UpdateByName(String, String),
+    Tick,
+    Render {
+        alert: AlertArgs,
+        out: String,
+    },
+    Measure(AlertArgs),
+    Bench {
+        alert: AlertArgs,
+        iterations: u32,
+    },
+    Batch(String),
+    Serve,
+    /// `creak config check`, with the config file `--style` picks.
+    ConfigCheck(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TailEdge {
+    Top,
+    Right,
+    Bottom,
+    Left,
+}
+
+/// `--tail`: a callout notch on one edge, centered `offset` px from the
+/// edge's left (or top) end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Tail {
+    edge: TailEdge,
+    offset: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ShowTime {
+    Absolute,
+    Relative,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DismissButton {
+    Any,
+    Left,
+    Right,
+    Middle,
+}
+
+impl DismissButton {
+    fn matches(self, button: u32) -> bool {
+        match self {
+            DismissButton::Any => true,
+            DismissButton::Left => button == BTN_LEFT,
+            DismissButton::Right => button == BTN_RIGHT,
+            DismissButton::Middle => button == BTN_MIDDLE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StackOrder {
+    /// New alerts go after the ones already shown, away from the edge.
+    Fifo,
+    /// New alerts go nearest the edge and push the others out.
+    Lifo,
+}
+
+/// `--on-full`: what a new alert does when `--max-stack` are already shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OnFull {
+    Drop,
+    ExpireOldest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GroupBy {
+    Class,
+    Name,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DndAction {
+    On,
+    Off,
+    Toggle,
+    Status,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DismissReason {
+    Timeout,
+    PointerClick,
+    Signal,
+    Compositor,
+    Coalesced,
+    RateLimited,
+    DoNotDisturb,
+}
+
+impl DismissReason {
+    fn exit_code(self) -> i32 {
+        match self {
+            DismissReason::Timeout => 0,
+            DismissReason::PointerClick => 2,
+            DismissReason::Signal => 3,
+            DismissReason::Compositor => 4,
+            DismissReason::Coalesced => 0,
+            DismissReason::RateLimited => 5,
+            DismissReason::DoNotDisturb => 6,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Json,
+    Ndjson,
 }
 
 #[derive(Debug)]
 struct Args {
     command: Command,
     state_dir: Option<String>,
+    format: OutputFormat,
+    limit: usize,
+    concurrent: bool,
+    socket: Option<String>,
+}
+
+/// One JSON line sent back by `creak serve` for each forwarded request.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Reply {
+    Out(String),
+    Exit(i32),
+    Error(String),
 }
 
 #[derive(Clone, Debug)]
 struct StatePaths {
     state_path: String,
     lock_path: String,
+    history_path: String,
+    /// Snoozed alerts waiting to be shown again.
+    pending_path: String,
+    /// Default `creak serve` socket; lives next to the lock.
+    socket_path: String,
+    lock_timeout: Duration,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 struct StackEntry {
     id: u64,
     position: String,
     height: i32,
     gap: i32,
+    /// `--slot-height`; replaces `height + gap` as the step when set.
+    #[serde(default)]
+    slot_height: i32,
     expires_at: u64,
     #[serde(default)]
     created_at: u64,
     #[serde(default)]
     pid: u32,
+    /// `/proc/<pid>/stat` start time, so a recycled pid doesn't look alive.
+    #[serde(default)]
+    start_time: u64,
     #[serde(default)]
     name: Option<String>,
     #[serde(default)]
     class: Option<String>,
+    /// `--app-name` of the sender.
     #[serde(default)]
+    app_name: Option<String>,
+    /// `--priority`: higher stays nearer the edge.
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    summary: String,
+    /// Shown by `creak serve`: cleared by dropping the entry, not SIGTERM.
+    #[serde(default)]
+    served: bool,
+    /// Alerts merged into this one by `--coalesce`, itself included.
+    #[serde(default)]
+    count: u32,
+    /// Bumped whenever another process changes `message` or `expires_at`.
+    #[serde(default)]
+    revision: u64,
+    /// Replacement text for the live alert.
+    #[serde(default)]
+    message: Option<String>,
+    /// CLI tokens that re-create the alert after a snooze.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    argv: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct HistoryEntry {
+    created_at: u64,
     summary: String,
+    name: Option<String>,
+    class: Option<String>,
+    position: String,
+    pid: u32,
+    /// Recorded while do-not-disturb kept the alert off screen.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    suppressed: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct StackState {
+    /// Files from before versioning have none and count as version 1.
+    #[serde(default = "legacy_state_version")]
+    version: u32,
     next_id: u64,
     entries: Vec<StackEntry>,
+    /// Creation times within the last second, for `--rate-limit`.
+    #[serde(default)]
+    recent: Vec<u64>,
+    /// Set by `creak pause`; expiry is measured against this until `resume`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    paused_since: Option<u64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    dnd: bool,
 }
 
 impl Default for StackState {
     fn default() -> Self {
         Self {
+            version: STATE_VERSION,
             next_id: 1,
             entries: Vec::new(),
+            recent: Vec::new(),
+            paused_since: None,
+            dnd: false,
         }
     }
 }
 
+const STATE_VERSION: u32 = 2;
+
+fn legacy_state_version() -> u32 {
+    1
+}
+
+/// Everything `reserve_stack_slot` needs to know about a new alert.
+struct SlotRequest {
+    position: Position,
+    height: i32,
+    gap: i32,
+    slot_height: i32,
+    order: StackOrder,
+    priority: i32,
+    max_stack: u32,
+    on_full: OnFull,
+    timeout_ms: u64,
+    name: Option<String>,
+    class: Option<String>,
+    app_name: Option<String>,
+    message: String,
+    served: bool,
+    rate_limit: u32,
+    coalesce: bool,
+    group_by: Option<GroupBy>,
+    bypass_dnd: bool,
+    argv: Vec<String>,
+    replace_id: Option<u64>,
+    summary_length: usize,
+    ellipsis: String,
+}
+
+enum Reservation {
+    Slot(i32, StackGuard),
+    /// Merged into the live alert with this id.
+    Coalesced(u64),
+    Limited,
+    DoNotDisturb,
+}
+
 struct StackGuard {
     id: u64,
+    /// The entry's `created_at`; a `--replace-id` that takes over the id
+    /// changes it, so the old alert leaves the new entry alone.
+    created_at: u64,
+    served: bool,
     position: String,
     state_path: String,
     lock_path: String,
+    lock_timeout: Duration,
 }
 
 static SHOULD_CLOSE: AtomicBool = AtomicBool::new(false);
+/// Client connections and alerts the daemon is handling, for `--idle-exit-ms`.
+static DAEMON_BUSY: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts as daemon activity for as long as it is alive.
+struct BusyGuard;
+
+impl BusyGuard {
+    fn new() -> Self {
+        DAEMON_BUSY.fetch_add(1, Ordering::SeqCst);
+        BusyGuard
+    }
+}
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        DAEMON_BUSY.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Counts heap allocations made from Rust for `creak bench` (feature
+/// `bench-alloc`). Cairo and Pango allocate through libc directly, so their
+/// allocations are not included.
+#[cfg(feature = "bench-alloc")]
+struct CountingAlloc;
+
+#[cfg(feature = "bench-alloc")]
+static ALLOCATIONS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+#[cfg(feature = "bench-alloc")]
+unsafe impl std::alloc::GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        std::alloc::System.realloc(ptr, layout, size)
+    }
+}
+
+#[cfg(feature = "bench-alloc")]
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Rust heap allocations so far, when built with `bench-alloc`.
+fn allocation_count() -> Option<u64> {
+    #[cfg(feature = "bench-alloc")]
+    return Some(ALLOCATIONS.load(Ordering::Relaxed));
+    #[cfg(not(feature = "bench-alloc"))]
+    None
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+const BENCH_MESSAGE: &str = "creak bench\nThe quick brown fox jumps over the lazy dog";
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Off as u8);
+static LOG_FILE: Mutex<Option<fs::File>> = Mutex::new(None);
 const HELP_TEXT: &str = r#"creak
 
 Usage:
-  creak list active [--style <name|path>] [--state-dir <path>]
+  creak list active [--format json|ndjson] [--style <name|path>] [--state-dir <path>]
   creak clear by name <name> [--style <name|path>] [--state-dir <path>]
   creak clear by class <class> [--style <name|path>] [--state-dir <path>]
   creak clear by id <id> [--style <name|path>] [--state-dir <path>]
+  creak history [--limit <n>] [--state-dir <path>]
+  creak pause|resume [--state-dir <path>]
+  creak dnd on|off|toggle|status [--state-dir <path>]
+  creak snooze by id <id> <ms> | snooze by name <name> <ms> [--state-dir <path>]
+  creak tick [--state-dir <path>]
+  creak update by id <id> <message> | update by name <name> <message> [--state-dir <path>]
+  creak render --out <file.png|-> [--style <name|path>] [options] <title> [body...]
+  creak measure [--style <name|path>] [options] <title> [body...]
+  creak bench [--iterations <n>] [--style <name|path>] [options] [<title> [body...]]
+  creak batch <file.json> [--concurrent] [--style <name|path>] [--state-dir <path>]
+  creak config check [--style <name|path>]
+  creak serve [--socket <path>] [--idle-exit-ms <ms>] [--style <name|path>]
+  creak --socket <path> <any command above>
   creak [--style <name|path>] [--state-dir <path>] [--name <name>] [--class <class>] [options] <title> [body...]
 
 Alert options:
   --top-left | --top | --top-right
   --left | --center | --right
   --bottom-left | --bottom | --bottom-right
+  --at-cursor                Show next to the pointer (falls back to the default spot)
   --timeout <ms>             0 means no auto-dismiss
-  --width <px>
-  --font <font>
-  --padding <px>
-  --border-size <px>
+  --urgency low|normal|critical  Apply urgency.<level> from the config dir (critical
+                             defaults to a red border and no timeout)
+  --min-timeout <ms>         Raise shorter --timeout values to this
+  --max-timeout <ms>         Cap longer --timeout values (0 = no cap); --timeout 0
+                             becomes this too unless --allow-persistent
+  --allow-persistent | --no-allow-persistent  Keep --timeout 0 alerts open under --max-timeout
+  --min-duration-ms <ms>     Ignore clicks and hold off close requests until shown this long
+  --defer-while-inhibited | --no-defer-while-inhibited  Stop the timeout while an
+                             idle inhibitor (e.g. a video player) is active
+  --timeout-bar | --no-timeout-bar  Thin bar along the bottom that shrinks until timeout
+  --timeout-bar-color <#RRGGBB[AA]> Defaults to the border color
+  --width <px|N%>            N% is relative to the output's logical width
+  --fit-width | --no-fit-width  Shrink to the text's natural width (up to --width)
+  --min-width <px>           Smallest width --fit-width may shrink to
+  --font <font>              Pango font; families may be a list: "Noto Sans, Noto Color Emoji 14"
+  --font-scale <factor>      Multiply the --font size (default 1)
+  --line-spacing <factor>    Baseline distance as a multiple of the font height (default 1)
+  --letter-spacing <px>      Extra space between characters (default 0)
+  --justify | --no-justify   Stretch wrapped lines to the full width (last line stays centered)
+  --fallback-font <family>   Family to try after --font for missing glyphs (repeatable)
+  --padding <px>             1-4 values like CSS, same as --border-size
+  --padding-top | --padding-right | --padding-bottom | --padding-left <px>
+  --border-size <px>         1-4 values like CSS: "all", "v h", "t h b", "t r b l"
+  --border-top | --border-right | --border-bottom | --border-left <px>
   --border-radius <px>
-  --background <#RRGGBB[AA]>
-  --text <#RRGGBB[AA]>
+  --corner-smoothing <0.0-1.0>  Blend the corners from circular (0) to squircle (1)
+  --tail <edge>:<offset> | none  Callout notch on top|right|bottom|left, <offset> px
+                             from that edge's left/top end (pairs with --at-cursor)
+  --background <#RRGGBB[AA]>  Every color also takes rgb(), rgba(), hsl() or hsla(),
+                             and hex an alpha suffix: #222222/50% or #222222@0.5
+  --background-gradient "<color> <color>..."  Linear gradient (overrides --background)
+  --background-image <file.png> | --no-background-image  Cover the background with a PNG
+  --gradient-angle <deg>     0 = left to right, 90 = top to bottom (default)
+  --opacity <0.0-1.0>        Scale the alpha of every color (default 1.0)
+  --text <#RRGGBB[AA]|auto>  auto picks black or white for contrast with the background
+  --text-outline <px>        Stroke the text so it stays legible on busy backgrounds
+  --text-outline-color <#RRGGBB[AA]>  Outline color (default #000000)
   --border <#RRGGBB[AA]>
+  --separator | --no-separator  Rule between the first line and the rest
+  --separator-color <#RRGGBB[AA]>  Rule color (default: dimmed text color)
+  --blink | --no-blink       Flash the border every 500ms
+  --blink-color <#RRGGBB[AA]>  Border color while flashed (default #ff4040)
   --edge <px>
   --default-offset <px>
-  --stack-gap <px>
+  --offset <px>              Extra distance from the anchored edge, added to --edge
+                             (or to --default-offset without a position)
+  --stack-gap <px>           Space between stacked alerts; negative values overlap them
+  --slot-height <px>         Stack on a fixed grid of px per alert instead (0 = off)
   --stack | --no-stack
+  --stack-order fifo|lifo    Stack new alerts after the others (fifo, default) or
+                             nearest the edge, pushing the others out (lifo)
+  --priority <n>             Higher-priority alerts stack nearer the edge (default 0)
+  --max-stack <n>            At most n alerts per position (0 = unlimited)
+  --on-full drop|expire-oldest  When --max-stack is reached, drop the new alert
+                             (default) or close the oldest one to make room
+  --rate-limit <n>           Drop stacked alerts beyond n per second (0 = unlimited)
+  --coalesce | --no-coalesce Merge into the newest alert with the same --name,
+                             showing "(xN)" and restarting its timeout
+  --group-by class|name|none Merge into any live alert with the same --class or
+                             --name, wherever it is (same "(xN)" tag)
+  --reposition-interval-ms <ms>  How often to re-read the stack and move (default 100;
+                             with inotify at least 1000, as changes wake it anyway)
+  --poll-interval-ms <ms>    Longest wait for Wayland events per loop (default 10);
+                             higher saves CPU, lower reacts and animates sooner
+  --layer overlay|top|bottom|background
+  --namespace <str>          Layer-shell namespace (default creak)
+  --keyboard none|exclusive|on-demand  Keyboard focus (on-demand needs layer-shell v4;
+                             older compositors get none)
+  --exclusive-zone <px>|auto Reserve space so windows move out of the way (auto =
+                             the alert's height; needs an edge position like --top)
+  --fallback-window | --no-fallback-window  Use a regular xdg-shell window when
+                             wlr-layer-shell is missing (placed by the compositor)
+  --all-outputs | --no-all-outputs  Show a copy on every output; a click on any
+                             copy or the timeout closes them all
+  --output <name>            Show on this output: a connector name like DP-1, or
+                             text found in its make and model ("dell u2720")
+  --output-index <n>         Show on the nth output (from 0, in registry order)
   --scale <n>
+  --double-buffer | --no-double-buffer  Alternate two buffers so redraws never
+                             flicker (default on; off saves memory)
   --text-antialias default|none|gray|subpixel
   --text-hint default|none|slight|medium|full
   --text-hint-metrics default|on|off
+  --subpixel-order rgb|bgr|vrgb|vbgr|none  Panel layout for --text-antialias subpixel
+  --summary-length <n>       Characters of the first line kept for list/history (default 120)
+  --ellipsis <str>           Marker for summaries cut by --summary-length (default …)
+  --ellipsize none|start|middle|end
+  --strip-markup | --no-strip-markup  Remove <tags> and decode &entities before layout
+  --template | --no-template Expand {time}, {date}, {host} and {env:VAR} in the message
+                             ({{ and }} are literal braces)
+  --template-strict | --no-template-strict  Fail on unknown placeholders instead of
+                             leaving them as-is
+  --wrap word|char|word-char|none  How long lines break (default word-char)
+  --max-lines <n>            Clamp text to n lines, ellipsizing the last (0 = unlimited)
+  --action <id:label>        Add a button; clicking it prints <id> (repeatable). Unless
+                             --keyboard is none, Tab/arrows select a button, Enter
+                             presses it and 1-9 press the nth one
+  --on-right-click <cmd>     Run <cmd> with sh on right click instead of just closing
+  --on-middle-click <cmd>    Same for the middle button
+  --keep-open-on-right-click | --keep-open-on-middle-click  Leave the alert up
+                             after running the command
+  --scroll-dismiss | --no-scroll-dismiss  Close the alert by scrolling over it
+  --dismiss-button left|right|middle|any|none  Pointer button that closes the alert
+                             (default any; action buttons always work)
+  --click-through | --no-click-through  Let clicks pass to the window underneath
+  --sound <path> | --no-sound  Play an audio file (paplay, else pw-play) when shown
+  --bell | --no-bell         Ring the terminal bell (on stderr) when shown
+  --bypass-dnd | --no-bypass-dnd  Show even while do-not-disturb is on
+  --replace-id <id>          Take over the live alert with this id (its place and id)
+  --print-id                 Print the alert id (0 if not stacked) before showing
+  --dry-run                  Print what would be shown (size, stack slot, id) as JSON
+                             without connecting to Wayland or touching the stack
+  --app-name <str>           Source app, shown dimmed above the text and in list active
+  --show-time absolute|relative|none  Add when the alert was created as a last line
+  --created-at <unix-ms>     Creation time for --show-time (set on re-shown snoozes)
+  --history | --no-history   Append shown alerts to history.jsonl in the state dir
+  --history-max-bytes <n>    Rotate history.jsonl past this size (0 = never)
 
 Control commands:
   list active                Print active alerts as JSON
+  --format json|ndjson       Pretty JSON array (default) or one object per line
   clear by name <name>       SIGTERM + remove matching alerts
   clear by class <class>     SIGTERM + remove matching alerts
   clear by id <id>           SIGTERM + remove matching alert
+  snooze by id|name <v> <ms> Close matching alerts and queue them to return after <ms>
+  update by id|name <v> <message>  Replace a live alert's text in place
+  tick                       Re-show snoozed alerts that are due (creak serve does this itself)
+  history                    Print the last recorded alerts as JSON lines
+  pause                      Freeze every alert's countdown (alerts stay visible)
+  dnd on|off|toggle|status   Do-not-disturb: hold back new alerts; prints on/off
+  resume                     Restart the countdowns where they stopped
+  render --out <file.png|->  Draw the alert to a PNG without connecting to Wayland
+                             (- writes it to stdout)
+  measure                    Print the alert's logical width/height as JSON
+  bench                      Time measuring and drawing the alert offscreen, printing
+                             mean/median/p99 per run as JSON (plus allocations when
+                             built with the bench-alloc feature)
+  --iterations <n>           Number of bench runs (default 1000)
+  batch <file.json>          Show a JSON array of alerts, one after another
+  config check               Validate the config (and its includes), printing file:line errors
+  --concurrent               Show every batch alert at once (stacked)
+  serve [--socket <path>]    Run a daemon that shows forwarded alerts in-process
+                             (default socket: $XDG_RUNTIME_DIR/creak/creak.sock)
+  --idle-exit-ms <ms>        Stop the daemon after this long with nothing to do (0 = never)
+  --socket <path>            Forward this command to a running creak serve
+  --limit <n>                Number of history lines to print (default 20)
 
 Common:
-  --style <name|path>        Config file: name in $XDG_CONFIG_HOME/creak or file path
+  --style <name|path>        Config file: name in the config dir or file path
+  --config-dir <path>        Config dir (default $CREAK_CONFIG_DIR, else $XDG_CONFIG_HOME/creak)
+  --profile <name|path>      Options from profiles/<name> in the config dir, applied after
+                             the config and before the command line
   --state-dir <path>         Use a custom state directory
+  --lock-timeout-ms <ms>     Give up waiting for the state lock after this long (default 3000)
+  --log-level off|error|warn|info|debug  Also CREAK_LOG; CREAK_DEBUG=1 means debug
+  --json-errors | --no-json-errors  Print errors as {"error": ..., "kind": ...} JSON on stderr
+  --log-file <path>          Append log lines here instead of stderr
   --help, -h                 Show this help
+  version, --version, -V     Show the creak version
+
+Exit codes:
+  0                          Alert timed out (or control command succeeded)
+  1                          Error
+  2                          Dismissed by a pointer click
+  3                          Closed by a signal (e.g. creak clear)
+  4                          Closed by the compositor
+  5                          Dropped by --rate-limit or a full --max-stack
+  6                          Suppressed by do-not-disturb
 "#;
 
+impl StackGuard {
+    fn owns(&self, entry: &StackEntry) -> bool {
+        entry.id == self.id && entry.created_at == self.created_at
+    }
+}
+
 impl Drop for StackGuard {
     fn drop(&mut self) {
-        if let Ok(_lock) = lock_state(&self.lock_path) {
+        if let Ok(_lock) = lock_state(&self.lock_path, self.lock_timeout) {
             if let Ok(mut state) = load_state(&self.state_path) {
-                state.entries.retain(|entry| entry.id != self.id);
+                state.entries.retain(|entry| !self.owns(entry));
                 let _ = save_state(&self.state_path, &state);
             }
         }
@@ -201,27 +882,391 @@ impl Drop for StackGuard {
 
 struct State {
     configured: bool,
-    closed: bool,
+    dismissed: Option<DismissReason>,
     width: i32,
     height: i32,
     scale: i32,
-    outputs: HashMap<u32, i32>,
+    /// Transform of the output the surface is on; buffers are pre-rotated.
+    transform: Transform,
+    outputs: HashMap<u32, OutputInfo>,
+    output_globals: HashMap<u32, WlOutput>,
+    outputs_changed: bool,
+    surface_closed: bool,
+    entered: Vec<u32>,
     seat: Option<WlSeat>,
     pointer: Option<WlPointer>,
+    /// Only bound when `--keyboard` lets the alert take focus and it has actions.
+    keyboard: Option<WlKeyboard>,
+    keyboard_actions: bool,
+    /// Action button picked with the keyboard, drawn highlighted.
+    selected: Option<usize>,
+    cursor_shape_manager: Option<WpCursorShapeManagerV1>,
+    cursor_shape: Option<WpCursorShapeDeviceV1>,
+    /// Show a hand cursor: the alert has actions or click commands.
+    interactive: bool,
+    pointer_pos: (f64, f64),
+    pointer_seen: bool,
+    /// `--min-duration-ms`: clicks before this instant are ignored.
+    clicks_after: Option<Instant>,
+    right_click: Option<ClickCommand>,
+    middle_click: Option<ClickCommand>,
+    scroll_dismiss: bool,
+    dismiss_button: Option<DismissButton>,
+    /// Vertical scroll distance since the pointer entered, for `--scroll-dismiss`.
+    scrolled: f64,
+    /// `--defer-while-inhibited`: no input for `INHIBIT_PROBE_MS`, and the
+    /// same but honoring idle inhibitors.
+    input_idle: bool,
+    idle: bool,
+    buttons: Vec<ActionButton>,
+    action: Option<String>,
+    mirrors: Vec<Mirror>,
+}
+
+/// `--all-outputs`: the alert again on another output. Copies follow the main
+/// surface's size, scale and margins; clicks and the timeout go through the
+/// shared `State`, so any of them closes all.
+struct Mirror {
+    /// Registry name of the output it is on.
+    output: u32,
+    surface: WlSurface,
+    layer_surface: ZwlrLayerSurfaceV1,
+    buffers: Buffers,
+    /// Buffer scale and transform, from the output it is on.
+    scale: i32,
+    transform: Transform,
+    configured: bool,
+    closed: bool,
+}
+
+impl Mirror {
+    fn destroy(&self) {
+        self.layer_surface.destroy();
+        self.surface.destroy();
+    }
+}
+
+#[derive(Clone, Debug)]
+struct OutputInfo {
+    scale: i32,
+    transform: Transform,
+    /// Current mode in physical pixels.
+    mode: Option<(i32, i32)>,
+    /// Connector name like "DP-1" (wl_output v4).
+    name: Option<String>,
+    make: String,
+    model: String,
+}
+
+impl Default for OutputInfo {
+    fn default() -> Self {
+        Self {
+            scale: 1,
+            transform: Transform::Normal,
+            mode: None,
+            name: None,
+            make: String::new(),
+            model: String::new(),
+        }
+    }
+}
+
+impl OutputInfo {
+    fn logical_width(&self) -> Option<i32> {
+        let (width, height) = self.mode?;
+        let width = if quarter_turn(self.transform) {
+            height
+        } else {
+            width
+        };
+        Some(width / self.scale.max(1))
+    }
+}
+
+/// Per-redraw state that changes while the alert is on screen.
+#[derive(Clone, Copy, Debug, Default)]
+struct Frame {
+    /// Fraction of the timeout left, when the timeout bar is shown.
+    remaining: Option<f64>,
+    /// Draw the border in the blink color.
+    highlight: bool,
+    /// Action button picked with the keyboard.
+    selected: Option<usize>,
+}
+
+const BLINK_PERIOD_MS: u128 = 500;
+const DEFAULT_LOCK_TIMEOUT_MS: u64 = 3000;
+
+/// Every `--` option the parser accepts; the source of truth for typo
+/// suggestions (and anything else that needs the full list).
+const OPTIONS: &[&str] = &[
+    "--action",
+    "--all-outputs",
+    "--allow-persistent",
+    "--app-name",
+    "--at-cursor",
+    "--background",
+    "--background-gradient",
+    "--background-image",
+    "--bell",
+    "--blink",
+    "--blink-color",
+    "--border",
+    "--border-bottom",
+    "--border-left",
+    "--border-radius",
+    "--border-right",
+    "--border-size",
+    "--border-top",
+    "--bottom",
+    "--bottom-center",
+    "--bottom-left",
+    "--bottom-right",
+    "--bypass-dnd",
+    "--center",
+    "--class",
+    "--clear-by-class",
+    "--clear-by-id",
+    "--clear-by-name",
+    "--click-through",
+    "--coalesce",
+    "--concurrent",
+    "--config-dir",
+    "--corner-smoothing",
+    "--created-at",
+    "--default-offset",
+    "--defer-while-inhibited",
+    "--dismiss-button",
+    "--dry-run",
+    "--double-buffer",
+    "--edge",
+    "--ellipsis",
+    "--ellipsize",
+    "--exclusive-zone",
+    "--fallback-font",
+    "--fallback-window",
+    "--fit-width",
+    "--font",
+    "--font-scale",
+    "--format",
+    "--gradient-angle",
+    "--group-by",
+    "--help",
+    "--history",
+    "--history-max-bytes",
+    "--idle-exit-ms",
+    "--iterations",
+    "--json-errors",
+    "--justify",
+    "--keep-open-on-middle-click",
+    "--keep-open-on-right-click",
+    "--keyboard",
+    "--layer",
+    "--left",
+    "--letter-spacing",
+    "--limit",
+    "--line-spacing",
+    "--list-active",
+    "--lock-timeout-ms",
+    "--log-file",
+    "--log-level",
+    "--max-lines",
+    "--max-stack",
+    "--max-timeout",
+    "--min-duration-ms",
+    "--min-timeout",
+    "--min-width",
+    "--name",
+    "--namespace",
+    "--no-all-outputs",
+    "--no-allow-persistent",
+    "--no-background-image",
+    "--no-bell",
+    "--no-blink",
+    "--no-bypass-dnd",
+    "--no-click-through",
+    "--no-coalesce",
+    "--no-defer-while-inhibited",
+    "--no-double-buffer",
+    "--no-fallback-window",
+    "--no-fit-width",
+    "--no-history",
+    "--no-json-errors",
+    "--no-justify",
+    "--no-keep-open-on-middle-click",
+    "--no-keep-open-on-right-click",
+    "--no-scroll-dismiss",
+    "--no-separator",
+    "--no-sound",
+    "--no-stack",
+    "--no-strip-markup",
+    "--no-template",
+    "--no-template-strict",
+    "--no-timeout-bar",
+    "--offset",
+    "--on-full",
+    "--on-middle-click",
+    "--on-right-click",
+    "--opacity",
+    "--out",
+    "--output",
+    "--output-index",
+    "--padding",
+    "--padding-bottom",
+    "--padding-left",
+    "--padding-right",
+    "--padding-top",
+    "--poll-interval-ms",
+    "--print-id",
+    "--priority",
+    "--profile",
+    "--rate-limit",
+    "--replace-id",
+    "--reposition-interval-ms",
+    "--right",
+    "--scale",
+    "--scroll-dismiss",
+    "--separator",
+    "--separator-color",
+    "--show-time",
+    "--slot-height",
+    "--socket",
+    "--sound",
+    "--stack",
+    "--stack-gap",
+    "--stack-order",
+    "--state-dir",
+    "--strip-markup",
+    "--style",
+    "--subpixel-order",
+    "--summary-length",
+    "--tail",
+    "--template",
+    "--template-strict",
+    "--text",
+    "--text-antialias",
+    "--text-hint",
+    "--text-hint-metrics",
+    "--text-outline",
+    "--text-outline-color",
+    "--timeout",
+    "--timeout-bar",
+    "--timeout-bar-color",
+    "--top",
+    "--top-center",
+    "--top-left",
+    "--top-right",
+    "--urgency",
+    "--version",
+    "--width",
+    "--wrap",
+];
+
+const TIMEOUT_BAR_HEIGHT: i32 = 3;
+const RECONNECT_ATTEMPTS: u32 = 6;
+const RECONNECT_FIRST_DELAY_MS: u64 = 250;
+const APP_NAME_FONT_SCALE: f64 = 0.75;
+const APP_NAME_ALPHA: f64 = 0.6;
+/// Space above and below the 1px `--separator` rule.
+const SEPARATOR_GAP: i32 = 6;
+/// How far a `--tail` sticks out; its base is twice as wide.
+const TAIL_SIZE: i32 = 10;
+
+#[derive(Clone, Copy, Debug)]
+struct PointerProbe {
+    x: f64,
+    y: f64,
+    output_width: i32,
+    output_height: i32,
 }
 
 impl Default for State {
     fn default() -> Self {
         Self {
             configured: false,
-            closed: false,
+            dismissed: None,
             width: 0,
             height: 0,
             scale: 1,
+            transform: Transform::Normal,
             outputs: HashMap::new(),
+            output_globals: HashMap::new(),
+            outputs_changed: false,
+            surface_closed: false,
+            entered: Vec::new(),
             seat: None,
             pointer: None,
+            keyboard: None,
+            keyboard_actions: false,
+            selected: None,
+            cursor_shape_manager: None,
+            cursor_shape: None,
+            interactive: false,
+            pointer_pos: (0.0, 0.0),
+            pointer_seen: false,
+            clicks_after: None,
+            right_click: None,
+            middle_click: None,
+            scroll_dismiss: false,
+            dismiss_button: Some(DismissButton::Any),
+            scrolled: 0.0,
+            input_idle: false,
+            idle: false,
+            buttons: Vec::new(),
+            action: None,
+            mirrors: Vec::new(),
+        }
+    }
+}
+
+impl State {
+    /// Drops every proxy from a connection that is gone, before binding on a
+    /// new one.
+    fn forget_globals(&mut self) {
+        self.configured = false;
+        self.surface_closed = false;
+        self.outputs.clear();
+        self.output_globals.clear();
+        self.outputs_changed = false;
+        self.entered.clear();
+        self.seat = None;
+        self.pointer = None;
+        self.keyboard = None;
+        self.cursor_shape_manager = None;
+        self.cursor_shape = None;
+        self.pointer_seen = false;
+        self.input_idle = false;
+        self.idle = false;
+        self.mirrors.clear();
+    }
+
+    /// Follow the highest scale among the outputs the surface is on, and the
+    /// transform of the first of them (a buffer can only have one).
+    fn update_outputs(&mut self) {
+        let entered = || self.entered.iter().filter_map(|id| self.outputs.get(id));
+        if let Some(scale) = entered().map(|info| info.scale).max() {
+            self.scale = scale.max(1);
         }
+        self.transform = entered()
+            .next()
+            .map(|info| info.transform)
+            .unwrap_or(Transform::Normal);
+    }
+
+    /// Logical width of the output we're on, or of the lowest-numbered known
+    /// output before the surface has entered one.
+    fn output_width(&self) -> Option<i32> {
+        let width = if self.entered.is_empty() {
+            self.outputs
+                .get(self.outputs.keys().min()?)?
+                .logical_width()
+        } else {
+            self.entered
+                .iter()
+                .filter_map(|id| self.outputs.get(id))
+                .find_map(OutputInfo::logical_width)
+        };
+        width.filter(|width| *width > 0)
     }
 }
 
@@ -250,13 +1295,57 @@ impl Dispatch<ZwlrLayerSurfaceV1, ()> for State {
                 }
             }
             zwlr_layer_surface_v1::Event::Closed => {
-                state.closed = true;
+                state.surface_closed = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `--all-outputs` copies; user data is the output's registry name.
+impl Dispatch<ZwlrLayerSurfaceV1, u32> for State {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        output: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let mirror = state
+            .mirrors
+            .iter_mut()
+            .find(|mirror| mirror.output == *output);
+        match event {
+            zwlr_layer_surface_v1::Event::Configure { serial, .. } => {
+                proxy.ack_configure(serial);
+                if let Some(mirror) = mirror {
+                    mirror.configured = true;
+                }
+            }
+            zwlr_layer_surface_v1::Event::Closed => {
+                if let Some(mirror) = mirror {
+                    mirror.closed = true;
+                }
             }
             _ => {}
         }
     }
 }
 
+/// Copies never drive the scale or transform; the main surface does.
+impl Dispatch<WlSurface, u32> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlSurface,
+        _: wayland_client::protocol::wl_surface::Event,
+        _: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
 impl Dispatch<WlSurface, ()> for State {
     fn event(
         state: &mut Self,
@@ -266,11 +1355,20 @@ impl Dispatch<WlSurface, ()> for State {
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
-        if let wayland_client::protocol::wl_surface::Event::Enter { output } = event {
-            let id = output.id().protocol_id();
-            if let Some(scale) = state.outputs.get(&id) {
-                state.scale = (*scale).max(1);
+        match event {
+            wayland_client::protocol::wl_surface::Event::Enter { output } => {
+                let id = output.id().protocol_id();
+                if !state.entered.contains(&id) {
+                    state.entered.push(id);
+                }
+                state.update_outputs();
+            }
+            wayland_client::protocol::wl_surface::Event::Leave { output } => {
+                let id = output.id().protocol_id();
+                state.entered.retain(|entered| *entered != id);
+                state.update_outputs();
             }
+            _ => {}
         }
     }
 }
@@ -308,21 +1406,69 @@ impl Dispatch<WlSeat, ()> for State {
         _: &Connection,
         qh: &QueueHandle<Self>,
     ) {
-        if let wayland_client::protocol::wl_seat::Event::Capabilities { capabilities } = event {
-            if let wayland_client::WEnum::Value(caps) = capabilities {
-                if env::var("CREAK_DEBUG").is_ok() {
-                    eprintln!("creak seat capabilities: {:?}", caps);
+        if let wayland_client::protocol::wl_seat::Event::Capabilities {
+            capabilities: wayland_client::WEnum::Value(caps),
+        } = event
+        {
+            log_debug!("seat capabilities: {:?}", caps);
+            if caps.contains(wayland_client::protocol::wl_seat::Capability::Pointer) {
+                if state.pointer.is_none() {
+                    log_debug!("creating pointer");
+                    let pointer = seat.get_pointer(qh, ());
+                    state.cursor_shape = state
+                        .cursor_shape_manager
+                        .as_ref()
+                        .map(|manager| manager.get_pointer(&pointer, qh, ()));
+                    state.pointer = Some(pointer);
                 }
-                if caps.contains(wayland_client::protocol::wl_seat::Capability::Pointer) {
-                    if state.pointer.is_none() {
-                        if env::var("CREAK_DEBUG").is_ok() {
-                            eprintln!("creak creating pointer");
-                        }
-                        state.pointer = Some(seat.get_pointer(qh, ()));
-                    }
-                } else {
-                    state.pointer = None;
+            } else {
+                state.pointer = None;
+                state.cursor_shape = None;
+            }
+            if caps.contains(wayland_client::protocol::wl_seat::Capability::Keyboard) {
+                if state.keyboard_actions && state.keyboard.is_none() {
+                    log_debug!("creating keyboard");
+                    state.keyboard = Some(seat.get_keyboard(qh, ()));
+                }
+            } else {
+                state.keyboard = None;
+            }
+        }
+    }
+}
+
+impl Dispatch<WlKeyboard, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &WlKeyboard,
+        event: wayland_client::protocol::wl_keyboard::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // Keys are matched by evdev code, so no keymap is needed; the keymap
+        // fd is closed when the event drops.
+        if let wayland_client::protocol::wl_keyboard::Event::Key {
+            key,
+            state:
+                wayland_client::WEnum::Value(wayland_client::protocol::wl_keyboard::KeyState::Pressed),
+            ..
+        } = event
+        {
+            if state
+                .clicks_after
+                .is_some_and(|after| Instant::now() < after)
+            {
+                log_debug!("key ignored before --min-duration-ms");
+                return;
+            }
+            match action_key(key, state.selected, state.buttons.len()) {
+                Some(ActionKey::Select(index)) => state.selected = Some(index),
+                Some(ActionKey::Activate(index)) => {
+                    state.action = Some(state.buttons[index].id.clone());
+                    state.dismissed = Some(DismissReason::PointerClick);
                 }
+                None => {}
             }
         }
     }
@@ -339,25 +1485,82 @@ impl Dispatch<WlPointer, ()> for State {
     ) {
         match event {
             wayland_client::protocol::wl_pointer::Event::Button {
-                state: button_state,
+                button,
+                state:
+                    wayland_client::WEnum::Value(
+                        wayland_client::protocol::wl_pointer::ButtonState::Pressed,
+                    ),
                 ..
             } => {
-                if button_state
-                    == wayland_client::WEnum::Value(
-                        wayland_client::protocol::wl_pointer::ButtonState::Pressed,
-                    )
+                if state
+                    .clicks_after
+                    .is_some_and(|after| Instant::now() < after)
                 {
-                    if env::var("CREAK_DEBUG").is_ok() {
-                        eprintln!("creak pointer button pressed");
+                    log_debug!("pointer button ignored before --min-duration-ms");
+                    return;
+                }
+                log_debug!("pointer button {:#x} pressed", button);
+                let click = match button {
+                    BTN_RIGHT => state.right_click.as_ref(),
+                    BTN_MIDDLE => state.middle_click.as_ref(),
+                    _ => None,
+                };
+                if let Some(click) = click {
+                    spawn_shell_command(&click.command);
+                    if !click.keep_open {
+                        state.dismissed = Some(DismissReason::PointerClick);
                     }
-                    state.closed = true;
+                    return;
+                }
+                let (x, y) = state.pointer_pos;
+                state.action = action_at(&state.buttons, x, y).map(|id| id.to_string());
+                // Action buttons are an explicit answer, whatever --dismiss-button says.
+                if state.action.is_some() || state.dismiss_button.is_some_and(|b| b.matches(button))
+                {
+                    state.dismissed = Some(DismissReason::PointerClick);
+                }
+            }
+            wayland_client::protocol::wl_pointer::Event::Enter {
+                serial,
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                log_debug!("pointer enter");
+                // The compositor restores its own cursor once the pointer leaves.
+                if let (true, Some(device)) = (state.interactive, state.cursor_shape.as_ref()) {
+                    device.set_shape(serial, wp_cursor_shape_device_v1::Shape::Pointer);
                 }
+                state.pointer_pos = (surface_x, surface_y);
+                state.pointer_seen = true;
             }
-            wayland_client::protocol::wl_pointer::Event::Enter { .. } => {
-                if env::var("CREAK_DEBUG").is_ok() {
-                    eprintln!("creak pointer enter");
+            wayland_client::protocol::wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                state.pointer_pos = (surface_x, surface_y);
+            }
+            wayland_client::protocol::wl_pointer::Event::Axis {
+                axis: wayland_client::WEnum::Value(WlPointerAxis::VerticalScroll),
+                value,
+                ..
+            } if state.scroll_dismiss => {
+                if state
+                    .clicks_after
+                    .is_some_and(|after| Instant::now() < after)
+                {
+                    return;
+                }
+                state.scrolled += value.abs();
+                if state.scrolled >= SCROLL_DISMISS_DISTANCE {
+                    log_debug!("scrolled away");
+                    state.dismissed = Some(DismissReason::PointerClick);
                 }
             }
+            wayland_client::protocol::wl_pointer::Event::Leave { .. } => {
+                state.scrolled = 0.0;
+            }
             _ => {}
         }
     }
@@ -375,35 +1578,169 @@ impl Dispatch<ZwlrLayerShellV1, ()> for State {
     }
 }
 
-impl Dispatch<WlRegistry, GlobalListContents> for State {
+impl Dispatch<XdgWmBase, ()> for State {
     fn event(
         _: &mut Self,
-        _: &WlRegistry,
-        _: wayland_client::protocol::wl_registry::Event,
-        _: &GlobalListContents,
+        proxy: &XdgWmBase,
+        event: xdg_wm_base::Event,
+        _: &(),
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            proxy.pong(serial);
+        }
     }
 }
 
-impl Dispatch<WlBuffer, ()> for State {
+impl Dispatch<XdgSurface, ()> for State {
     fn event(
-        _: &mut Self,
-        _: &WlBuffer,
-        _: wayland_client::protocol::wl_buffer::Event,
+        state: &mut Self,
+        proxy: &XdgSurface,
+        event: xdg_surface::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            proxy.ack_configure(serial);
+            state.configured = true;
+        }
     }
 }
 
-impl Dispatch<WlShmPool, ()> for State {
+impl Dispatch<XdgToplevel, ()> for State {
     fn event(
-        _: &mut Self,
-        _: &WlShmPool,
-        _: wayland_client::protocol::wl_shm_pool::Event,
+        state: &mut Self,
+        _: &XdgToplevel,
+        event: xdg_toplevel::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // Size hints are ignored: the window is pinned to the measured size.
+        if let xdg_toplevel::Event::Close = event {
+            state.dismissed = Some(DismissReason::Compositor);
+        }
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        state: &mut Self,
+        registry: &WlRegistry,
+        event: wayland_client::protocol::wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wayland_client::protocol::wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } if interface == WlOutput::interface().name => {
+                let output = registry.bind(name, version.min(4), qh, ());
+                state.output_globals.insert(name, output);
+                state.outputs_changed = true;
+            }
+            wayland_client::protocol::wl_registry::Event::GlobalRemove { name } => {
+                if let Some(output) = state.output_globals.remove(&name) {
+                    log_info!("output removed: {}", name);
+                    let id = output.id().protocol_id();
+                    state.outputs.remove(&id);
+                    state.entered.retain(|entered| *entered != id);
+                    state.update_outputs();
+                    if output.version() >= 3 {
+                        output.release();
+                    }
+                    state.outputs_changed = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, Arc<AtomicBool>> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlBuffer,
+        event: wayland_client::protocol::wl_buffer::Event,
+        busy: &Arc<AtomicBool>,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wayland_client::protocol::wl_buffer::Event::Release = event {
+            busy.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Dispatch<WpCursorShapeManagerV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &WpCursorShapeManagerV1,
+        _: wp_cursor_shape_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WpCursorShapeDeviceV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &WpCursorShapeDeviceV1,
+        _: wp_cursor_shape_device_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ExtIdleNotifierV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &ExtIdleNotifierV1,
+        _: ext_idle_notifier_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+/// User data is true for the input-only notification.
+impl Dispatch<ExtIdleNotificationV1, bool> for State {
+    fn event(
+        state: &mut Self,
+        _: &ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        input_only: &bool,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let idle = match event {
+            ext_idle_notification_v1::Event::Idled => true,
+            ext_idle_notification_v1::Event::Resumed => false,
+            _ => return,
+        };
+        if *input_only {
+            state.input_idle = idle;
+        } else {
+            state.idle = idle;
+        }
+    }
+}
+
+impl Dispatch<WlShmPool, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlShmPool,
+        _: wayland_client::protocol::wl_shm_pool::Event,
         _: &(),
         _: &Connection,
         _: &QueueHandle<Self>,
@@ -420,10 +1757,41 @@ impl Dispatch<WlOutput, ()> for State {
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
-        if let wayland_client::protocol::wl_output::Event::Scale { factor } = event {
-            let id = output.id().protocol_id();
-            state.outputs.insert(id, factor);
-            state.scale = factor.max(1);
+        let id = output.id().protocol_id();
+        match event {
+            wayland_client::protocol::wl_output::Event::Scale { factor } => {
+                state.outputs.entry(id).or_default().scale = factor;
+                if state.entered.is_empty() {
+                    state.scale = factor.max(1);
+                } else {
+                    state.update_outputs();
+                }
+            }
+            wayland_client::protocol::wl_output::Event::Mode {
+                flags: wayland_client::WEnum::Value(flags),
+                width,
+                height,
+                ..
+            } if flags.contains(wayland_client::protocol::wl_output::Mode::Current) => {
+                state.outputs.entry(id).or_default().mode = Some((width, height));
+            }
+            wayland_client::protocol::wl_output::Event::Geometry {
+                make,
+                model,
+                transform,
+                ..
+            } => {
+                let info = state.outputs.entry(id).or_default();
+                (info.make, info.model) = (make, model);
+                if let wayland_client::WEnum::Value(transform) = transform {
+                    info.transform = transform;
+                    state.update_outputs();
+                }
+            }
+            wayland_client::protocol::wl_output::Event::Name { name } => {
+                state.outputs.entry(id).or_default().name = Some(name);
+            }
+            _ => {}
         }
     }
 }
@@ -441,1189 +1809,7313 @@ impl Dispatch<WlRegion, ()> for State {
 }
 
 fn main() -> Result<()> {
-    let (args, mut cfg) = parse_args()?;
-    if matches!(args.command, Command::Help) {
-        println!("{}", HELP_TEXT);
-        return Ok(());
+    init_logging_from_env();
+    // Looked for up front too, so errors in the arguments themselves are
+    // reported as JSON.
+    let json_errors = env::args().skip(1).any(|arg| arg == "--json-errors");
+    let (args, cfg) = match parse_args() {
+        Ok(parsed) => parsed,
+        Err(err) if json_errors => exit_with_json_error("usage", &err),
+        Err(err) => return Err(err),
+    };
+    let json_errors = json_errors || cfg.json_errors;
+    match dispatch(args, cfg) {
+        Ok(0) => Ok(()),
+        Ok(code) => std::process::exit(code),
+        Err(err) if json_errors => exit_with_json_error(error_kind(&err), &err),
+        Err(err) => Err(err),
+    }
+}
+
+fn dispatch(args: Args, cfg: Config) -> Result<i32> {
+    configure_logging(&cfg)?;
+    let code = match (&args.command, args.socket.as_deref()) {
+        (Command::Serve, socket) => {
+            let mut paths = state_paths(args.state_dir.as_deref())?;
+            paths.lock_timeout = Duration::from_millis(cfg.lock_timeout_ms);
+            let socket = socket.unwrap_or(&paths.socket_path).to_string();
+            serve(&socket, &paths, Duration::from_millis(cfg.idle_exit_ms))?;
+            0
+        }
+        (_, Some(socket)) => forward_to_socket(socket, env::args().skip(1).collect())?,
+        (_, None) => run_command(args, cfg, &mut std::io::stdout(), None)?,
+    };
+    Ok(code)
+}
+
+/// `--json-errors`: one `{"error": ..., "kind": ...}` line on stderr.
+fn exit_with_json_error(kind: &str, err: &anyhow::Error) -> ! {
+    eprintln!("{}", json_error(kind, err));
+    std::process::exit(1);
+}
+
+fn json_error(kind: &str, err: &anyhow::Error) -> String {
+    serde_json::json!({ "error": format!("{:#}", err), "kind": kind }).to_string()
+}
+
+/// A coarse class for scripts: `wayland`, `io`, `json` or `other`.
+/// Argument errors are `usage`, decided by the caller.
+fn error_kind(err: &anyhow::Error) -> &'static str {
+    for cause in err.chain() {
+        if cause.is::<wayland_client::ConnectError>()
+            || cause.is::<wayland_client::DispatchError>()
+            || cause.is::<wayland_client::backend::WaylandError>()
+        {
+            return "wayland";
+        }
+        if cause.is::<std::io::Error>() {
+            return "io";
+        }
+        if cause.is::<serde_json::Error>() {
+            return "json";
+        }
     }
-    let state_paths = state_paths(args.state_dir.as_deref())?;
+    "other"
+}
+
+/// Runs one parsed command, writing what it prints to `out`, and returns the
+/// exit code. `conn` is the daemon's shared connection under `creak serve`.
+fn run_command(
+    args: Args,
+    mut cfg: Config,
+    out: &mut dyn Write,
+    conn: Option<&Connection>,
+) -> Result<i32> {
+    let state_dir = args.state_dir.as_deref();
+    let lock_timeout = Duration::from_millis(cfg.lock_timeout_ms);
+    let resolve_paths = || -> Result<StatePaths> {
+        let mut paths = state_paths(state_dir)?;
+        paths.lock_timeout = lock_timeout;
+        Ok(paths)
+    };
     match args.command {
-        Command::Help => return Ok(()),
+        Command::Help => writeln!(out, "{}", HELP_TEXT)?,
+        Command::Version => writeln!(out, "{}", version_string())?,
+        Command::Render { alert, out: path } => {
+            if path == "-" && conn.is_some() {
+                return Err(anyhow!("render --out - is not supported through --socket"));
+            }
+            render_png(&alert, &mut cfg, &path, out)?
+        }
+        Command::Measure(alert) => {
+            let (width, height) = measure_notification(&cfg, &alert)?;
+            writeln!(
+                out,
+                "{}",
+                serde_json::json!({ "width": width, "height": height })
+            )?;
+        }
+        Command::Bench { alert, iterations } => {
+            let report = run_bench(&alert, &mut cfg, iterations)?;
+            writeln!(out, "{}", report)?;
+        }
+        Command::Batch(path) => {
+            let alerts = load_batch(&path, &cfg)?;
+            let paths = resolve_paths()?;
+            match conn {
+                Some(conn) => run_batch(alerts, args.concurrent, &paths, conn, out)?,
+                None => {
+                    install_signal_handlers();
+                    let conn = Connection::connect_to_env().context("connect to wayland")?;
+                    run_batch(alerts, args.concurrent, &paths, &conn, out)?
+                }
+            }
+        }
+        Command::Serve => return Err(anyhow!("serve cannot be forwarded to a socket")),
         Command::ListActive => {
-            let entries = list_active_entries(&state_paths)?;
-            println!("{}", serde_json::to_string_pretty(&entries)?);
-            return Ok(());
+            let entries = list_active_entries(&resolve_paths()?)?;
+            match args.format {
+                OutputFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&entries)?)?,
+                OutputFormat::Ndjson => {
+                    for entry in entries.iter() {
+                        writeln!(out, "{}", serde_json::to_string(entry)?)?;
+                    }
+                }
+            }
         }
         Command::ClearByName(name) => {
-            let count = clear_active_entries(&state_paths, ClearSelector::Name(name))?;
-            println!("{}", count);
-            return Ok(());
+            let count = clear_active_entries(&resolve_paths()?, ClearSelector::Name(name))?;
+            writeln!(out, "{}", count)?;
         }
         Command::ClearByClass(class) => {
-            let count = clear_active_entries(&state_paths, ClearSelector::Class(class))?;
-            println!("{}", count);
-            return Ok(());
+            let count = clear_active_entries(&resolve_paths()?, ClearSelector::Class(class))?;
+            writeln!(out, "{}", count)?;
         }
         Command::ClearById(id) => {
-            let count = clear_active_entries(&state_paths, ClearSelector::Id(id))?;
-            println!("{}", count);
-            return Ok(());
+            let count = clear_active_entries(&resolve_paths()?, ClearSelector::Id(id))?;
+            writeln!(out, "{}", count)?;
+        }
+        Command::SnoozeById(id, delay_ms) => {
+            let count = snooze_entries(&resolve_paths()?, ClearSelector::Id(id), delay_ms)?;
+            writeln!(out, "{}", count)?;
+        }
+        Command::SnoozeByName(name, delay_ms) => {
+            let count = snooze_entries(&resolve_paths()?, ClearSelector::Name(name), delay_ms)?;
+            writeln!(out, "{}", count)?;
+        }
+        Command::UpdateById(id, message) => {
+            let count = update_entries(
+                &resolve_paths()?,
+                ClearSelector::Id(id),
+                &message,
+                cfg.summary_length,
+                &cfg.ellipsis,
+            )?;
+            writeln!(out, "{}", count)?;
+        }
+        Command::UpdateByName(name, message) => {
+            let count = update_entries(
+                &resolve_paths()?,
+                ClearSelector::Name(name),
+                &message,
+                cfg.summary_length,
+                &cfg.ellipsis,
+            )?;
+            writeln!(out, "{}", count)?;
+        }
+        Command::Tick => {
+            let due = take_due_snoozes(&resolve_paths()?)?;
+            for alert in due.iter() {
+                reshow(alert.reshow_argv(), conn)?;
+            }
+            writeln!(out, "{}", due.len())?;
+        }
+        Command::ConfigCheck(path) => {
+            let problems = check_config(&path);
+            for problem in problems.iter() {
+                writeln!(out, "{}", problem)?;
+            }
+            if !problems.is_empty() {
+                return Ok(1);
+            }
+            writeln!(out, "{}: ok", path)?;
+        }
+        Command::Pause => set_paused(&resolve_paths()?, true)?,
+        Command::Resume => set_paused(&resolve_paths()?, false)?,
+        Command::Dnd(action) => {
+            let on = set_dnd(&resolve_paths()?, action)?;
+            writeln!(out, "{}", if on { "on" } else { "off" })?;
+        }
+        Command::History => {
+            for line in read_history(&resolve_paths()?, args.limit)? {
+                writeln!(out, "{}", line)?;
+            }
         }
+        Command::Show(alert) if cfg.dry_run => dry_run(alert, &mut cfg, &resolve_paths()?, out)?,
         Command::Show(alert) => {
-            run_alert(alert, &mut cfg, &state_paths)?;
+            let paths = resolve_paths()?;
+            let reason = match conn {
+                Some(conn) => run_alert(alert, &mut cfg, &paths, conn, out, true)?,
+                None => {
+                    install_signal_handlers();
+                    let conn = Connection::connect_to_env().context("connect to wayland")?;
+                    run_alert(alert, &mut cfg, &paths, &conn, out, false)?
+                }
+            };
+            log_info!("dismissed: {:?}", reason);
+            return Ok(reason.exit_code());
         }
     }
-    Ok(())
+    Ok(0)
 }
 
-fn run_alert(args: AlertArgs, cfg: &mut Config, state_paths: &StatePaths) -> Result<()> {
+/// Listens on `socket` and runs each forwarded command on its own thread,
+/// sharing one Wayland connection; every alert gets its own event queue.
+/// Snoozed alerts in `paths` come back once they are due. With a nonzero
+/// `idle_exit`, exits once nothing has been shown, connected or snoozed for
+/// that long.
+fn serve(socket: &str, paths: &StatePaths, idle_exit: Duration) -> Result<()> {
     install_signal_handlers();
-    SHOULD_CLOSE.store(false, Ordering::Relaxed);
+    let conn = Connection::connect_to_env().context("connect to wayland")?;
+    if UnixStream::connect(socket).is_ok() {
+        return Err(anyhow!("creak serve is already listening on {}", socket));
+    }
+    let _ = fs::remove_file(socket);
+    let listener = UnixListener::bind(socket).with_context(|| format!("bind {}", socket))?;
+    listener.set_nonblocking(true)?;
+    let mut last_tick = Instant::now();
+    let mut idle_since = Instant::now();
+    let mut snoozes_waiting = false;
+    while !SHOULD_CLOSE.load(Ordering::Relaxed) {
+        if last_tick.elapsed() >= Duration::from_secs(1) {
+            last_tick = Instant::now();
+            match take_due_snoozes(paths) {
+                Ok(due) => {
+                    for alert in due {
+                        reshow(alert.reshow_argv(), Some(&conn))?;
+                    }
+                }
+                Err(err) => log_warn!("snooze check failed: {:#}", err),
+            }
+            snoozes_waiting = load_pending(&paths.pending_path)
+                .map(|pending| !pending.is_empty())
+                .unwrap_or(false);
+        }
+        if DAEMON_BUSY.load(Ordering::SeqCst) > 0 || snoozes_waiting {
+            idle_since = Instant::now();
+        } else if !idle_exit.is_zero() && idle_since.elapsed() >= idle_exit {
+            log_info!("idle for {:?}, exiting", idle_exit);
+            break;
+        }
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let conn = conn.clone();
+                let busy = BusyGuard::new();
+                std::thread::spawn(move || {
+                    let _busy = busy;
+                    if let Err(err) = serve_client(stream, &conn) {
+                        log_warn!("serve client failed: {:#}", err);
+                    }
+                });
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(err) => {
+                let _ = fs::remove_file(socket);
+                return Err(err.into());
+            }
+        }
+    }
+    let _ = fs::remove_file(socket);
+    Ok(())
+}
+
+fn serve_client(stream: UnixStream, conn: &Connection) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut out = ReplyWriter {
+            stream: &stream,
+            pending: Vec::new(),
+        };
+        let result = shell_words::split(&line)
+            .context("parse request")
+            .and_then(parse_cli)
+            .and_then(|(args, cfg)| run_command(args, cfg, &mut out, Some(conn)));
+        out.flush()?;
+        let reply = match result {
+            Ok(code) => Reply::Exit(code),
+            Err(err) => Reply::Error(format!("{:#}", err)),
+        };
+        send_reply(&stream, &reply)?;
+    }
+    Ok(())
+}
+
+fn send_reply(mut stream: &UnixStream, reply: &Reply) -> Result<()> {
+    let mut line = serde_json::to_string(reply)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Turns whatever a command prints into `Reply::Out` lines.
+struct ReplyWriter<'a> {
+    stream: &'a UnixStream,
+    pending: Vec<u8>,
+}
+
+impl Write for ReplyWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let text = String::from_utf8_lossy(&line[..end]).into_owned();
+            send_reply(self.stream, &Reply::Out(text)).map_err(std::io::Error::other)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.pending.is_empty() {
+            let text = String::from_utf8_lossy(&self.pending).into_owned();
+            self.pending.clear();
+            send_reply(self.stream, &Reply::Out(text)).map_err(std::io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+/// Thin client side of `--socket`: the daemon re-parses our CLI tokens in its
+/// own working directory and environment, so relative paths and variables
+/// like `XDG_CONFIG_HOME` resolve as they would for the daemon, not for us.
+fn forward_to_socket(socket: &str, cli_tokens: Vec<String>) -> Result<i32> {
+    let mut tokens = Vec::with_capacity(cli_tokens.len());
+    let mut iter = cli_tokens.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--socket" {
+            iter.next();
+        } else if !arg.starts_with("--socket=") {
+            tokens.push(arg);
+        }
+    }
+    let mut stream =
+        UnixStream::connect(socket).with_context(|| format!("connect to {}", socket))?;
+    let mut line = shell_words::join(&tokens);
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut stdout = std::io::stdout().lock();
+    for line in BufReader::new(stream).lines() {
+        match serde_json::from_str(&line?).context("parse reply")? {
+            Reply::Out(text) => {
+                writeln!(stdout, "{}", text)?;
+                stdout.flush()?;
+            }
+            Reply::Exit(code) => return Ok(code),
+            Reply::Error(message) => return Err(anyhow!(message)),
+        }
+    }
+    Err(anyhow!("creak serve closed the connection"))
+}
+
+/// One alert in a `creak batch` file; unset fields keep the configured defaults.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchItem {
+    summary: String,
+    body: Option<String>,
+    position: Option<String>,
+    timeout: Option<u64>,
+    name: Option<String>,
+    class: Option<String>,
+    background: Option<String>,
+    text: Option<String>,
+    border: Option<String>,
+}
+
+fn load_batch(path: &str, base: &Config) -> Result<Vec<(AlertArgs, Config)>> {
+    let data = fs::read_to_string(path).with_context(|| format!("read {}", path))?;
+    let items: Vec<BatchItem> =
+        serde_json::from_str(&data).with_context(|| format!("parse {}", path))?;
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            batch_alert(item, base).with_context(|| format!("batch item {}", index))
+        })
+        .collect()
+}
+
+fn batch_alert(item: BatchItem, base: &Config) -> Result<(AlertArgs, Config)> {
+    let mut cfg = base.clone();
+    let color = |key: &str, value: &str| {
+        parse_color(value).ok_or_else(|| anyhow!("invalid color for {}: {}", key, value))
+    };
+    if let Some(value) = item.background.as_deref() {
+        cfg.background = color("background", value)?;
+    }
+    if let Some(value) = item.text.as_deref() {
+        set_text_color(&mut cfg, value)?;
+    }
+    if let Some(value) = item.border.as_deref() {
+        cfg.border = color("border", value)?;
+    }
+    if let Some(timeout) = item.timeout {
+        cfg.timeout_ms = timeout;
+    }
+    let position = match item.position.as_deref() {
+        Some(key) => parse_position(key)?,
+        None => Position::Default,
+    };
+    let message = match item.body {
+        Some(body) => format!("{}\n{}", item.summary, body),
+        None => item.summary,
+    };
+    let alert = AlertArgs {
+        position,
+        message,
+        name: item.name,
+        class: item.class,
+        print_id: false,
+        actions: Vec::new(),
+        argv: Vec::new(),
+        created_at: None,
+        app_name: None,
+        replace_id: None,
+    };
+    Ok((alert, cfg))
+}
+
+/// Batch alerts run as served alerts, so `creak clear` drops one of them
+/// instead of signalling the whole batch.
+fn run_batch(
+    alerts: Vec<(AlertArgs, Config)>,
+    concurrent: bool,
+    paths: &StatePaths,
+    conn: &Connection,
+    out: &mut dyn Write,
+) -> Result<()> {
+    if !concurrent {
+        for (alert, mut cfg) in alerts {
+            if SHOULD_CLOSE.load(Ordering::Relaxed) {
+                break;
+            }
+            let reason = run_alert(alert, &mut cfg, paths, conn, out, true)?;
+            log_info!("batch alert dismissed: {:?}", reason);
+        }
+        return Ok(());
+    }
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = alerts
+            .into_iter()
+            .map(|(alert, mut cfg)| {
+                scope.spawn(move || {
+                    run_alert(alert, &mut cfg, paths, conn, &mut std::io::sink(), true)
+                })
+            })
+            .collect();
+        for handle in handles {
+            let reason = handle
+                .join()
+                .map_err(|_| anyhow!("batch alert thread panicked"))??;
+            log_info!("batch alert dismissed: {:?}", reason);
+        }
+        Ok(())
+    })
+}
 
-    let (width, height) = measure_text(cfg, &args.message)?;
-    let width = cfg.width.max(width);
-    let height = height.max(cfg.padding * 2 + cfg.border_size * 2 + 1);
+fn run_alert(
+    mut args: AlertArgs,
+    cfg: &mut Config,
+    state_paths: &StatePaths,
+    conn: &Connection,
+    out: &mut dyn Write,
+    served: bool,
+) -> Result<DismissReason> {
+    if cfg.template {
+        args.message = expand_template(&args.message, cfg.template_strict)?;
+    }
+    args.created_at.get_or_insert_with(now_millis);
+    cfg.timeout_ms = cfg.effective_timeout_ms();
+    if !cfg.bypass_dnd && dnd_enabled(state_paths) {
+        log_info!("do-not-disturb is on, not showing alert");
+        record_history(&args, cfg, state_paths, true);
+        return Ok(DismissReason::DoNotDisturb);
+    }
+    let (mut width, mut height) = measure_notification(cfg, &args)?;
+    // Replaced if the compositor goes away under a persistent alert.
+    let mut conn = conn.clone();
 
     let mut state = State {
         configured: false,
-        closed: false,
+        dismissed: None,
         width,
         height,
         scale: cfg.output_scale.max(1),
+        transform: Transform::Normal,
         outputs: HashMap::new(),
+        output_globals: HashMap::new(),
+        outputs_changed: false,
+        surface_closed: false,
+        entered: Vec::new(),
         seat: None,
         pointer: None,
+        keyboard: None,
+        keyboard_actions: !args.actions.is_empty()
+            && cfg.keyboard != zwlr_layer_surface_v1::KeyboardInteractivity::None,
+        selected: None,
+        cursor_shape_manager: None,
+        cursor_shape: None,
+        interactive: !args.actions.is_empty()
+            || cfg.on_right_click.is_some()
+            || cfg.on_middle_click.is_some(),
+        pointer_pos: (0.0, 0.0),
+        pointer_seen: false,
+        clicks_after: None,
+        right_click: cfg.on_right_click.clone().map(|command| ClickCommand {
+            command,
+            keep_open: cfg.keep_open_on_right_click,
+        }),
+        middle_click: cfg.on_middle_click.clone().map(|command| ClickCommand {
+            command,
+            keep_open: cfg.keep_open_on_middle_click,
+        }),
+        scroll_dismiss: cfg.scroll_dismiss,
+        dismiss_button: cfg.dismiss_button,
+        scrolled: 0.0,
+        input_idle: false,
+        idle: false,
+        buttons: Vec::new(),
+        action: None,
+        mirrors: Vec::new(),
     };
 
-    let conn = Connection::connect_to_env().context("connect to wayland")?;
-    let (globals, mut event_queue) = registry_queue_init(&conn).context("init registry")?;
-    let qh = event_queue.handle();
-
-    let compositor: WlCompositor = globals.bind(&qh, 4..=5, ()).context("bind wl_compositor")?;
-    let shm: WlShm = globals.bind(&qh, 1..=1, ()).context("bind wl_shm")?;
-    let layer_shell: ZwlrLayerShellV1 = globals
-        .bind(&qh, 1..=4, ())
-        .context("bind zwlr_layer_shell_v1")?;
-    state.seat = globals.bind(&qh, 1..=7, ()).ok();
-
-    let surface = compositor.create_surface(&qh, ());
-    let layer_surface = layer_shell.get_layer_surface(
-        &surface,
-        None,
-        zwlr_layer_shell_v1::Layer::Overlay,
-        "creak".to_string(),
-        &qh,
-        (),
-    );
+    let (mut event_queue, mut globals) = bind_globals(&conn, cfg, &mut state)?;
+    let mut qh = event_queue.handle();
 
-    event_queue.roundtrip(&mut state)?;
+    // Output details are only needed up front to resolve a percent width,
+    // pick an --output or place at the cursor. The scale arrives ahead of the
+    // first configure (and the loop follows wl_surface.enter anyway), so the
+    // common case skips the roundtrip.
+    let need_outputs = cfg.width_percent.is_some()
+        || cfg.output.is_some()
+        || cfg.output_index.is_some()
+        || matches!(args.position, Position::Cursor);
+    if need_outputs {
+        event_queue.roundtrip(&mut state)?;
+    }
     if state.scale <= 0 {
         state.scale = 1;
     }
+    // Best guess until the surface enters an output; the loop corrects it.
+    if let (Some(percent), Some(output_width)) = (cfg.width_percent, state.output_width()) {
+        cfg.width = percent_of(percent, output_width);
+        (width, height) = measure_notification(cfg, &args)?;
+        (state.width, state.height) = (width, height);
+    }
 
-    let (position, base_margins) = position_to_anchor(cfg, args.position);
+    let (mut position, mut base_margins) = position_to_anchor(cfg, args.position);
+    let mut offset_position = args.position;
+    if let (Position::Cursor, Shell::Layer(layer_shell)) = (args.position, &globals.shell) {
+        match probe_pointer(
+            &mut event_queue,
+            &mut state,
+            &globals.compositor,
+            layer_shell,
+            &globals.shm,
+            &qh,
+        )? {
+            Some(probe) => {
+                (position, base_margins) = cursor_anchor(cfg, probe, width, height);
+                offset_position = if position.contains(zwlr_layer_surface_v1::Anchor::Bottom) {
+                    Position::BottomLeft
+                } else {
+                    Position::TopLeft
+                };
+            }
+            None => {
+                log_debug!("pointer position unknown, using default placement");
+            }
+        }
+    }
+    if let Some(reason) = state.dismissed {
+        log_debug!("clicked during the pointer probe");
+        return Ok(reason);
+    }
     let mut stack_offset = 0;
     let mut stack_guard: Option<StackGuard> = None;
+    let mut coalesced_into = None;
     if cfg.stack {
-        if let Ok((offset, guard)) = reserve_stack_slot(
-            state_paths,
-            args.position,
-            height,
-            cfg.stack_gap,
-            cfg.timeout_ms,
-            args.name.clone(),
-            args.class.clone(),
-            message_summary(&args.message),
-        ) {
-            stack_offset = offset;
-            stack_guard = Some(guard);
+        let request = slot_request(&args, cfg, height, served);
+        match reserve_stack_slot(state_paths, request) {
+            Ok(Reservation::Slot(offset, guard)) => {
+                stack_offset = offset;
+                stack_guard = Some(guard);
+            }
+            Ok(Reservation::Coalesced(id)) => coalesced_into = Some(id),
+            Ok(Reservation::Limited) => return Ok(DismissReason::RateLimited),
+            Ok(Reservation::DoNotDisturb) => {
+                record_history(&args, cfg, state_paths, true);
+                return Ok(DismissReason::DoNotDisturb);
+            }
+            Err(err) => {
+                log_warn!("stack reservation failed: {}", err);
+            }
         }
     }
 
-    let mut margins = apply_stack_offset(base_margins, args.position, stack_offset);
+    record_history(&args, cfg, state_paths, false);
+    announce(cfg);
 
-    layer_surface.set_anchor(position);
-    layer_surface.set_margin(margins.top, margins.right, margins.bottom, margins.left);
-    layer_surface.set_size(width as u32, height as u32);
-    layer_surface.set_keyboard_interactivity(zwlr_layer_surface_v1::KeyboardInteractivity::None);
-    layer_surface.set_exclusive_zone(0);
+    if args.print_id {
+        let id = stack_guard.as_ref().map(|guard| guard.id);
+        writeln!(out, "{}", id.or(coalesced_into).unwrap_or(0))?;
+        out.flush()?;
+    }
+    if coalesced_into.is_some() {
+        return Ok(DismissReason::Coalesced);
+    }
 
-    surface.commit();
+    let mut margins = apply_stack_offset(base_margins, offset_position, stack_offset);
+
+    let (mut surface, mut shell_surface) = create_shell_surface(
+        &globals.compositor,
+        &globals.shell,
+        &qh,
+        cfg,
+        main_output(&state, cfg).as_ref(),
+        position,
+        margins,
+        (width, height),
+    );
     conn.flush()?;
 
-    event_queue.roundtrip(&mut state)?;
+    wait_for_configure(&mut event_queue, &mut state)?;
+    state.outputs_changed = false;
     if state.width <= 0 || state.height <= 0 {
         state.width = width;
         state.height = height;
     }
 
-    if cfg.output_scale <= 0 {
+    let follow_output_scale = cfg.output_scale <= 0;
+    if follow_output_scale {
         cfg.output_scale = state.scale;
     }
-    let scale = cfg.output_scale.max(1);
-    let pixel_width = state.width * scale;
-    let pixel_height = state.height * scale;
+    let mut scale = cfg.output_scale.max(1);
     state.scale = scale;
-    surface.set_buffer_scale(state.scale);
-    let region = compositor.create_region(&qh, ());
-    region.add(0, 0, state.width, state.height);
-    surface.set_input_region(Some(&region));
-
-    let mut buffer = create_buffer(&shm, &qh, pixel_width, pixel_height)?;
-    draw_notification(
-        &mut buffer,
-        pixel_width,
-        pixel_height,
+    // Copies on other outputs follow their own output's scale unless --scale is set.
+    let fixed_scale = (!follow_output_scale).then_some(scale);
+    let mut transform = state.transform;
+    surface.set_buffer_scale(scale);
+    surface.set_buffer_transform(transform);
+    set_input_region(
+        &globals.compositor,
+        &qh,
+        &surface,
+        cfg,
         state.width,
         state.height,
-        cfg,
-        &args.message,
-    )?;
+    );
 
-    surface.attach(Some(&buffer.wl_buffer), 0, 0);
-    surface.damage_buffer(0, 0, pixel_width, pixel_height);
-    surface.commit();
+    let timeout = Duration::from_millis(cfg.timeout_ms);
+    let mut frame = Frame {
+        remaining: (cfg.timeout_bar_height() > 0).then_some(1.0),
+        ..Frame::default()
+    };
+    let mut buffers = Buffers::new(
+        &globals.shm,
+        &qh,
+        state.width * scale,
+        state.height * scale,
+        transform,
+        if cfg.double_buffer { 2 } else { 1 },
+    )?;
+    if cfg.all_outputs {
+        if let Shell::Window(_) = globals.shell {
+            log_info!("--all-outputs needs wlr-layer-shell, showing one window");
+        }
+        if sync_mirrors(
+            &mut state,
+            &globals,
+            &qh,
+            cfg,
+            position,
+            margins,
+            fixed_scale,
+        )? {
+            event_queue.roundtrip(&mut state)?;
+        }
+    }
+    paint(
+        &surface,
+        &mut buffers,
+        &mut event_queue,
+        &mut state,
+        cfg,
+        &args,
+        &frame,
+    )?;
     conn.flush()?;
 
     let forever = cfg.timeout_ms == 0;
-    let deadline = if forever {
+    let mut deadline = if forever {
         None
     } else {
-        Some(Instant::now() + Duration::from_millis(cfg.timeout_ms))
+        Some(Instant::now() + timeout)
     };
-    let mut last_check = Instant::now();
+    let mut bar_pixels = timeout_bar_pixels(&frame, state.width, scale);
+    let shown_at = Instant::now();
+    let mut shown_text = display_message(cfg, &args).into_owned();
+    let min_duration = Duration::from_millis(cfg.min_duration_ms);
+    state.clicks_after = Some(shown_at + min_duration);
+    // Close requests that arrive before --min-duration-ms are held until it passes.
+    let mut close_requested = false;
+    // With inotify the timer only backs up missed events.
+    let state_watch = StateWatch::new(&state_paths.state_path);
+    let check_interval = Duration::from_millis(if state_watch.is_some() {
+        cfg.reposition_interval_ms.max(1000)
+    } else {
+        cfg.reposition_interval_ms
+    });
+    let poll = i32::try_from(cfg.poll_interval_ms).unwrap_or(i32::MAX);
+    // Check right away so an alert shown during `creak pause` starts frozen.
+    let mut last_check = Instant::now()
+        .checked_sub(check_interval)
+        .unwrap_or_else(Instant::now);
+    let mut command_paused = false;
+    let mut paused = false;
+    let mut paused_left = None;
     let mut last_offset = stack_offset;
-    while !state.closed
-        && !SHOULD_CLOSE.load(Ordering::Relaxed)
-        && deadline.map(|d| Instant::now() < d).unwrap_or(true)
-    {
-        dispatch_with_timeout(&mut event_queue, &mut state, 10)?;
+    let mut revision = 0;
+    let mut relayout = false;
+    while state.dismissed.is_none() {
+        close_requested |= SHOULD_CLOSE.load(Ordering::Relaxed);
+        if close_requested && shown_at.elapsed() >= min_duration {
+            state.dismissed = Some(DismissReason::Signal);
+            break;
+        }
+        if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+            state.dismissed = Some(DismissReason::Timeout);
+            break;
+        }
+        let state_changed =
+            match dispatch_with_timeout(&mut event_queue, &mut state, poll, state_watch.as_ref()) {
+                Ok(changed) => changed,
+                // The daemon's connection is shared, so only standalone
+                // alerts that would otherwise stay up forever reconnect.
+                Err(err) if forever && !served && is_disconnect(&err) => {
+                    log_warn!("lost the wayland connection: {:#}", err);
+                    conn = reconnect()?;
+                    state.forget_globals();
+                    (event_queue, globals) = bind_globals(&conn, cfg, &mut state)?;
+                    qh = event_queue.handle();
+                    (surface, shell_surface) = create_shell_surface(
+                        &globals.compositor,
+                        &globals.shell,
+                        &qh,
+                        cfg,
+                        main_output(&state, cfg).as_ref(),
+                        position,
+                        margins,
+                        (width, height),
+                    );
+                    conn.flush()?;
+                    wait_for_configure(&mut event_queue, &mut state)?;
+                    if state.width <= 0 || state.height <= 0 {
+                        (state.width, state.height) = (width, height);
+                    }
+                    surface.set_buffer_scale(scale);
+                    surface.set_buffer_transform(transform);
+                    set_input_region(
+                        &globals.compositor,
+                        &qh,
+                        &surface,
+                        cfg,
+                        state.width,
+                        state.height,
+                    );
+                    buffers = Buffers::new(
+                        &globals.shm,
+                        &qh,
+                        state.width * scale,
+                        state.height * scale,
+                        transform,
+                        if cfg.double_buffer { 2 } else { 1 },
+                    )?;
+                    if cfg.all_outputs
+                        && sync_mirrors(
+                            &mut state,
+                            &globals,
+                            &qh,
+                            cfg,
+                            position,
+                            margins,
+                            fixed_scale,
+                        )?
+                    {
+                        event_queue.roundtrip(&mut state)?;
+                    }
+                    paint(
+                        &surface,
+                        &mut buffers,
+                        &mut event_queue,
+                        &mut state,
+                        cfg,
+                        &args,
+                        &frame,
+                    )?;
+                    conn.flush()?;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
         conn.flush()?;
-        if let Some(guard) = stack_guard.as_ref() {
-            if last_check.elapsed() >= Duration::from_millis(100) {
-                if let Ok(offset) = stack_offset_for_id(guard) {
-                    if offset != last_offset {
-                        margins = apply_stack_offset(base_margins, args.position, offset);
-                        layer_surface.set_margin(
-                            margins.top,
-                            margins.right,
-                            margins.bottom,
-                            margins.left,
-                        );
-                        surface.commit();
-                        let _ = conn.flush();
-                        last_offset = offset;
+        if state.surface_closed {
+            // Pick up a pending output removal that explains the close.
+            event_queue.roundtrip(&mut state)?;
+            if !state.outputs_changed || state.output_globals.is_empty() {
+                state.dismissed = Some(DismissReason::Compositor);
+                break;
+            }
+            log_info!("surface closed after output change, recreating");
+            shell_surface.destroy();
+            surface.destroy();
+            state.surface_closed = false;
+            state.entered.clear();
+            (surface, shell_surface) = create_shell_surface(
+                &globals.compositor,
+                &globals.shell,
+                &qh,
+                cfg,
+                main_output(&state, cfg).as_ref(),
+                position,
+                margins,
+                (width, height),
+            );
+            conn.flush()?;
+            state.configured = false;
+            wait_for_configure(&mut event_queue, &mut state)?;
+            surface.set_buffer_scale(scale);
+            surface.set_buffer_transform(transform);
+            set_input_region(
+                &globals.compositor,
+                &qh,
+                &surface,
+                cfg,
+                state.width,
+                state.height,
+            );
+            buffers.resize(&qh, state.width * scale, state.height * scale, transform)?;
+            paint(
+                &surface,
+                &mut buffers,
+                &mut event_queue,
+                &mut state,
+                cfg,
+                &args,
+                &frame,
+            )?;
+            conn.flush()?;
+        } else if state.outputs_changed && state.entered.is_empty() {
+            // Our output went away; a fresh commit lets the compositor place
+            // the surface on one that is still connected.
+            surface.commit();
+            conn.flush()?;
+        }
+        if cfg.all_outputs {
+            state.mirrors.retain(|mirror| !mirror.closed);
+            if state.outputs_changed
+                && sync_mirrors(
+                    &mut state,
+                    &globals,
+                    &qh,
+                    cfg,
+                    position,
+                    margins,
+                    fixed_scale,
+                )?
+            {
+                event_queue.roundtrip(&mut state)?;
+                paint(
+                    &surface,
+                    &mut buffers,
+                    &mut event_queue,
+                    &mut state,
+                    cfg,
+                    &args,
+                    &frame,
+                )?;
+                conn.flush()?;
+            }
+        }
+        state.outputs_changed = false;
+        if state.selected != frame.selected {
+            frame.selected = state.selected;
+            paint(
+                &surface,
+                &mut buffers,
+                &mut event_queue,
+                &mut state,
+                cfg,
+                &args,
+                &frame,
+            )?;
+            conn.flush()?;
+        }
+        if cfg.blink {
+            let highlight = blink_phase(shown_at.elapsed());
+            if highlight != frame.highlight {
+                frame.highlight = highlight;
+                paint(
+                    &surface,
+                    &mut buffers,
+                    &mut event_queue,
+                    &mut state,
+                    cfg,
+                    &args,
+                    &frame,
+                )?;
+                conn.flush()?;
+            }
+        }
+        if let (Some(deadline), Some(_)) = (deadline, frame.remaining) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            frame.remaining = Some(left.as_secs_f64() / timeout.as_secs_f64());
+            let pixels = timeout_bar_pixels(&frame, state.width, scale);
+            if pixels != bar_pixels {
+                paint(
+                    &surface,
+                    &mut buffers,
+                    &mut event_queue,
+                    &mut state,
+                    cfg,
+                    &args,
+                    &frame,
+                )?;
+                conn.flush()?;
+                bar_pixels = pixels;
+            }
+        }
+        if cfg.show_time == Some(ShowTime::Relative) {
+            let label = display_message(cfg, &args).into_owned();
+            if label != shown_text {
+                paint(
+                    &surface,
+                    &mut buffers,
+                    &mut event_queue,
+                    &mut state,
+                    cfg,
+                    &args,
+                    &frame,
+                )?;
+                conn.flush()?;
+                shown_text = label;
+            }
+        }
+        let rescaled = follow_output_scale && state.scale != scale;
+        if rescaled || state.transform != transform {
+            if rescaled {
+                log_info!("output scale changed: {} -> {}", scale, state.scale);
+                scale = state.scale;
+                cfg.output_scale = scale;
+            }
+            transform = state.transform;
+            buffers.resize(&qh, state.width * scale, state.height * scale, transform)?;
+            surface.set_buffer_scale(scale);
+            surface.set_buffer_transform(transform);
+            resize_mirrors(&mut state, &globals.compositor, &qh, cfg, fixed_scale)?;
+            paint(
+                &surface,
+                &mut buffers,
+                &mut event_queue,
+                &mut state,
+                cfg,
+                &args,
+                &frame,
+            )?;
+            conn.flush()?;
+        }
+        if state_changed || last_check.elapsed() >= check_interval {
+            if let Some(guard) = stack_guard.as_ref() {
+                match stack_offset_for_id(guard) {
+                    Ok(Some((offset, entry))) => {
+                        if offset != last_offset {
+                            margins = apply_stack_offset(base_margins, offset_position, offset);
+                            shell_surface.set_margin(margins);
+                            surface.commit();
+                            for mirror in &state.mirrors {
+                                mirror.layer_surface.set_margin(
+                                    margins.top,
+                                    margins.right,
+                                    margins.bottom,
+                                    margins.left,
+                                );
+                                mirror.surface.commit();
+                            }
+                            let _ = conn.flush();
+                            last_offset = offset;
+                        }
+                        if entry.revision != revision {
+                            revision = entry.revision;
+                            if let Some(message) = entry.message.as_deref() {
+                                args.message = coalesced_message(message, entry.count);
+                            }
+                            deadline = (entry.expires_at > 0).then(|| {
+                                let left = entry.expires_at.saturating_sub(now_millis());
+                                Instant::now() + Duration::from_millis(left)
+                            });
+                            relayout = true;
+                        }
+                    }
+                    Ok(None) if guard.served => {
+                        // `creak clear` dropped our entry instead of signalling the daemon.
+                        close_requested = true;
+                    }
+                    _ => {}
+                }
+            }
+            command_paused = load_state(&state_paths.state_path)
+                .map(|state| state.paused_since.is_some())
+                .unwrap_or(false);
+            last_check = Instant::now();
+        }
+        // Idle for input but not idle overall means something (a video
+        // player, say) holds an idle inhibitor.
+        let inhibited = state.input_idle && !state.idle;
+        let hold = command_paused || inhibited;
+        if hold != paused {
+            log_info!(
+                "countdown {}{}",
+                if hold { "paused" } else { "resumed" },
+                if inhibited { " (idle inhibited)" } else { "" }
+            );
+            paused = hold;
+            if !paused {
+                deadline = paused_left.take().map(|left| Instant::now() + left);
+            }
+            // Keep other processes from pruning our entry while held.
+            if let Some(guard) = stack_guard.as_ref() {
+                let expires_at = match deadline {
+                    Some(d) => {
+                        now_millis()
+                            + d.saturating_duration_since(Instant::now()).as_millis() as u64
                     }
+                    None => 0,
+                };
+                let _ = set_entry_expiry(guard, expires_at);
+            }
+        }
+        if paused {
+            if let Some(d) = deadline.take() {
+                paused_left = Some(d.saturating_duration_since(Instant::now()));
+            }
+        }
+        if let (Some(percent), Some(output_width)) = (cfg.width_percent, state.output_width()) {
+            let resolved = percent_of(percent, output_width);
+            if resolved != cfg.width {
+                log_debug!("width {}% of {} -> {}", percent, output_width, resolved);
+                cfg.width = resolved;
+                relayout = true;
+            }
+        }
+        if relayout {
+            relayout = false;
+            let size = measure_notification(cfg, &args)?;
+            if size != (width, height) {
+                (width, height) = size;
+                (state.width, state.height) = size;
+                shell_surface.set_size(width, height);
+                if cfg.exclusive_zone.is_none() {
+                    shell_surface.set_exclusive_zone(height);
                 }
-                last_check = Instant::now();
+                set_input_region(&globals.compositor, &qh, &surface, cfg, width, height);
+                buffers.resize(&qh, width * scale, height * scale, transform)?;
+                resize_mirrors(&mut state, &globals.compositor, &qh, cfg, fixed_scale)?;
+                paint(
+                    &surface,
+                    &mut buffers,
+                    &mut event_queue,
+                    &mut state,
+                    cfg,
+                    &args,
+                    &frame,
+                )?;
+                if let Some(guard) = stack_guard.as_ref() {
+                    let _ = set_entry_height(guard, height);
+                }
+            } else {
+                paint(
+                    &surface,
+                    &mut buffers,
+                    &mut event_queue,
+                    &mut state,
+                    cfg,
+                    &args,
+                    &frame,
+                )?;
             }
+            conn.flush()?;
         }
     }
 
     drop(stack_guard);
-    Ok(())
-}
-
-unsafe extern "C" fn handle_signal(_: i32) {
-    SHOULD_CLOSE.store(true, Ordering::Relaxed);
+    if let Some(action) = state.action.as_ref() {
+        writeln!(out, "{}", action)?;
+    }
+    Ok(state.dismissed.unwrap_or(DismissReason::Timeout))
 }
 
-fn install_signal_handlers() {
-    unsafe {
-        libc::signal(libc::SIGTERM, handle_signal as libc::sighandler_t);
-        libc::signal(libc::SIGINT, handle_signal as libc::sighandler_t);
+fn record_history(args: &AlertArgs, cfg: &Config, paths: &StatePaths, suppressed: bool) {
+    if !cfg.history {
+        return;
+    }
+    let entry = HistoryEntry {
+        created_at: now_millis(),
+        summary: message_summary(&args.message, cfg.summary_length, &cfg.ellipsis),
+        name: args.name.clone(),
+        class: args.class.clone(),
+        position: position_key(args.position).to_string(),
+        pid: std::process::id(),
+        suppressed,
+    };
+    if let Err(err) = append_history(paths, &entry, cfg.history_max_bytes) {
+        log_warn!("history append failed: {}", err);
     }
 }
 
-fn parse_args() -> Result<(Args, Config)> {
-    let cfg = default_config();
-    let cli_tokens: Vec<String> = env::args().skip(1).collect();
-    let (style, mut cli_tokens) = extract_style_arg(cli_tokens)?;
-    let mut tokens = load_config_args(style.as_deref())?;
-    tokens.append(&mut cli_tokens);
-    if env::var("CREAK_DEBUG").is_ok() {
-        eprintln!("creak tokens: {:?}", tokens);
+/// Runs `command` with `sh -c` without waiting for it; a thread reaps it so
+/// the daemon doesn't collect zombies.
+fn spawn_shell_command(command: &str) {
+    let child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::null())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(err) => log_warn!("failed to run {:?}: {}", command, err),
     }
-    parse_tokens(tokens, cfg)
 }
 
-fn extract_style_arg(tokens: Vec<String>) -> Result<(Option<String>, Vec<String>)> {
-    let mut out = Vec::with_capacity(tokens.len());
-    let mut style: Option<String> = None;
-    let mut i = 0usize;
-    while i < tokens.len() {
-        let arg = &tokens[i];
-        if arg == "--style" {
-            if i + 1 >= tokens.len() {
-                return Err(anyhow!("--style requires a value"));
+/// `--bell` and `--sound`. Neither waits: the player runs on its own and a
+/// reaper thread collects it.
+fn announce(cfg: &Config) {
+    if cfg.bell {
+        let mut stderr = std::io::stderr();
+        let _ = stderr.write_all(b"\x07").and_then(|_| stderr.flush());
+    }
+    let Some(path) = cfg.sound.as_deref() else {
+        return;
+    };
+    for player in ["paplay", "pw-play"] {
+        let child = std::process::Command::new(player)
+            .arg(path)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn();
+        match child {
+            Ok(mut child) => {
+                std::thread::spawn(move || child.wait());
+                return;
             }
-            style = Some(tokens[i + 1].clone());
-            i += 2;
-            continue;
+            Err(err) => log_debug!("{} unavailable: {}", player, err),
         }
-        if let Some(value) = arg.strip_prefix("--style=") {
-            style = Some(value.to_string());
-            i += 1;
-            continue;
-        }
-        out.push(arg.clone());
-        i += 1;
     }
-    Ok((style, out))
+    log_warn!("no audio player found for --sound {:?}", path);
 }
 
-fn parse_tokens(tokens: Vec<String>, mut cfg: Config) -> Result<(Args, Config)> {
-    let mut position = Position::Default;
-    let mut alert_name: Option<String> = None;
-    let mut alert_class: Option<String> = None;
-    let mut state_dir: Option<String> = None;
-    let mut command: Option<Command> = None;
-    let mut rest: Vec<String> = Vec::new();
-    let mut iter = tokens.into_iter().peekable();
-    while let Some(arg) = iter.next() {
-        if arg == "--top-left" {
-            position = Position::TopLeft;
-        } else if arg == "--top" || arg == "--top-center" {
-            position = Position::Top;
-        } else if arg == "--top-right" {
-            position = Position::TopRight;
-        } else if arg == "--left" {
-            position = Position::Left;
-        } else if arg == "--center" {
-            position = Position::Center;
-        } else if arg == "--right" {
-            position = Position::Right;
-        } else if arg == "--bottom-left" {
-            position = Position::BottomLeft;
-        } else if arg == "--bottom" || arg == "--bottom-center" {
-            position = Position::Bottom;
-        } else if arg == "--bottom-right" {
-            position = Position::BottomRight;
-        } else if arg == "--timeout" {
-            let val = next_value("--timeout", &mut iter)?;
-            cfg.timeout_ms = val.parse()?;
-        } else if arg.starts_with("--timeout=") {
-            let val = arg.trim_start_matches("--timeout=");
-            cfg.timeout_ms = val.parse()?;
-        } else if arg == "--width" {
-            let val = next_value("--width", &mut iter)?;
-            cfg.width = val.parse()?;
-        } else if arg.starts_with("--width=") {
-            let val = arg.trim_start_matches("--width=");
-            cfg.width = val.parse()?;
-        } else if arg == "--font" {
-            cfg.font = next_value("--font", &mut iter)?;
-        } else if arg.starts_with("--font=") {
-            cfg.font = arg.trim_start_matches("--font=").to_string();
-        } else if arg == "--padding" {
-            let val = next_value("--padding", &mut iter)?;
-            cfg.padding = val.parse()?;
-        } else if arg.starts_with("--padding=") {
-            cfg.padding = arg.trim_start_matches("--padding=").parse()?;
-        } else if arg == "--border-size" {
-            let val = next_value("--border-size", &mut iter)?;
-            cfg.border_size = val.parse()?;
-        } else if arg.starts_with("--border-size=") {
-            cfg.border_size = arg.trim_start_matches("--border-size=").parse()?;
-        } else if arg == "--border-radius" {
-            let val = next_value("--border-radius", &mut iter)?;
-            cfg.border_radius = val.parse()?;
-        } else if arg.starts_with("--border-radius=") {
-            cfg.border_radius = arg.trim_start_matches("--border-radius=").parse()?;
-        } else if arg == "--background" {
-            let val = next_value("--background", &mut iter)?;
-            cfg.background =
-                parse_hex_color(&val).ok_or_else(|| anyhow!("invalid color for --background"))?;
-        } else if arg.starts_with("--background=") {
-            let val = arg.trim_start_matches("--background=");
-            cfg.background =
-                parse_hex_color(val).ok_or_else(|| anyhow!("invalid color for --background"))?;
-        } else if arg == "--text" {
-            let val = next_value("--text", &mut iter)?;
-            cfg.text = parse_hex_color(&val).ok_or_else(|| anyhow!("invalid color for --text"))?;
-        } else if arg.starts_with("--text=") {
-            let val = arg.trim_start_matches("--text=");
-            cfg.text = parse_hex_color(val).ok_or_else(|| anyhow!("invalid color for --text"))?;
-        } else if arg == "--border" {
-            let val = next_value("--border", &mut iter)?;
-            cfg.border =
-                parse_hex_color(&val).ok_or_else(|| anyhow!("invalid color for --border"))?;
-        } else if arg.starts_with("--border=") {
-            let val = arg.trim_start_matches("--border=");
-            cfg.border =
-                parse_hex_color(val).ok_or_else(|| anyhow!("invalid color for --border"))?;
-        } else if arg == "--edge" {
-            let val = next_value("--edge", &mut iter)?;
-            cfg.edge = val.parse()?;
-        } else if arg.starts_with("--edge=") {
-            cfg.edge = arg.trim_start_matches("--edge=").parse()?;
-        } else if arg == "--scale" {
-            let val = next_value("--scale", &mut iter)?;
-            cfg.output_scale = val.parse()?;
-        } else if arg.starts_with("--scale=") {
-            cfg.output_scale = arg.trim_start_matches("--scale=").parse()?;
-        } else if arg == "--text-antialias" {
-            let val = next_value("--text-antialias", &mut iter)?;
-            cfg.text_antialias = parse_antialias(&val)?;
-        } else if arg.starts_with("--text-antialias=") {
-            let val = arg.trim_start_matches("--text-antialias=");
-            cfg.text_antialias = parse_antialias(val)?;
-        } else if arg == "--text-hint" {
-            let val = next_value("--text-hint", &mut iter)?;
-            cfg.text_hint = parse_hint_style(&val)?;
-        } else if arg.starts_with("--text-hint=") {
-            let val = arg.trim_start_matches("--text-hint=");
-            cfg.text_hint = parse_hint_style(val)?;
-        } else if arg == "--text-hint-metrics" {
-            let val = next_value("--text-hint-metrics", &mut iter)?;
-            cfg.text_hint_metrics = parse_hint_metrics(&val)?;
-        } else if arg.starts_with("--text-hint-metrics=") {
-            let val = arg.trim_start_matches("--text-hint-metrics=");
-            cfg.text_hint_metrics = parse_hint_metrics(val)?;
-        } else if arg == "--default-offset" {
-            let val = next_value("--default-offset", &mut iter)?;
-            cfg.default_offset = val.parse()?;
-        } else if arg.starts_with("--default-offset=") {
-            cfg.default_offset = arg.trim_start_matches("--default-offset=").parse()?;
-        } else if arg == "--stack-gap" {
-            let val = next_value("--stack-gap", &mut iter)?;
-            cfg.stack_gap = val.parse()?;
-        } else if arg.starts_with("--stack-gap=") {
-            cfg.stack_gap = arg.trim_start_matches("--stack-gap=").parse()?;
-        } else if arg == "--stack" {
-            cfg.stack = true;
-        } else if arg == "--no-stack" {
-            cfg.stack = false;
-        } else if arg == "--name" {
-            alert_name = Some(next_value("--name", &mut iter)?);
-        } else if arg.starts_with("--name=") {
-            alert_name = Some(arg.trim_start_matches("--name=").to_string());
-        } else if arg == "--class" {
-            alert_class = Some(next_value("--class", &mut iter)?);
-        } else if arg.starts_with("--class=") {
-            alert_class = Some(arg.trim_start_matches("--class=").to_string());
-        } else if arg == "--state-dir" {
-            state_dir = Some(next_value("--state-dir", &mut iter)?);
-        } else if arg.starts_with("--state-dir=") {
-            state_dir = Some(arg.trim_start_matches("--state-dir=").to_string());
-        } else if arg == "--list-active" {
-            command = Some(Command::ListActive);
-        } else if arg == "--clear-by-name" {
-            let name = next_value("--clear-by-name", &mut iter)?;
-            command = Some(Command::ClearByName(name));
-        } else if arg.starts_with("--clear-by-name=") {
-            command = Some(Command::ClearByName(
-                arg.trim_start_matches("--clear-by-name=").to_string(),
-            ));
-        } else if arg == "--clear-by-class" {
-            let class = next_value("--clear-by-class", &mut iter)?;
-            command = Some(Command::ClearByClass(class));
-        } else if arg.starts_with("--clear-by-class=") {
-            command = Some(Command::ClearByClass(
-                arg.trim_start_matches("--clear-by-class=").to_string(),
-            ));
-        } else if arg == "--clear-by-id" {
-            let id = next_value("--clear-by-id", &mut iter)?;
-            command = Some(Command::ClearById(id.parse()?));
-        } else if arg.starts_with("--clear-by-id=") {
-            let id = arg.trim_start_matches("--clear-by-id=");
-            command = Some(Command::ClearById(id.parse()?));
-        } else if arg == "list" {
-            let sub = next_value("list", &mut iter)?;
-            if sub != "active" {
-                return Err(anyhow!("usage: creak list active"));
+/// Connects again after the compositor went away, retrying with backoff
+/// while it restarts.
+fn reconnect() -> Result<Connection> {
+    let mut delay = Duration::from_millis(RECONNECT_FIRST_DELAY_MS);
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        std::thread::sleep(delay);
+        log_debug!("reconnect attempt {}/{}", attempt, RECONNECT_ATTEMPTS);
+        match Connection::connect_to_env() {
+            Ok(conn) => {
+                log_info!("reconnected to wayland");
+                return Ok(conn);
             }
-            command = Some(Command::ListActive);
-        } else if arg == "clear" {
-            command = Some(parse_clear_command(&mut iter)?);
-        } else if arg == "--help" || arg == "-h" {
-            command = Some(Command::Help);
-        } else if arg.starts_with('-') {
-            return Err(anyhow!("unknown option: {}", arg));
-        } else {
-            rest.push(arg);
+            Err(err) => log_debug!("reconnect attempt {} failed: {}", attempt, err),
         }
+        delay *= 2;
     }
+    Err(anyhow!(
+        "wayland connection lost; gave up after {} reconnect attempts",
+        RECONNECT_ATTEMPTS
+    ))
+}
 
-    let command = if let Some(command) = command {
-        if !rest.is_empty() {
-            return Err(anyhow!(
-                "unexpected positional arguments for control command"
-            ));
+/// Whether `err` means the compositor hung up, rather than a protocol error.
+fn is_disconnect(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(cause.downcast_ref(), Some(WaylandError::Io(_)))
+            || matches!(
+                cause.downcast_ref(),
+                Some(DispatchError::Backend(WaylandError::Io(_)))
+            )
+    })
+}
+
+/// What `run_alert` binds from the registry; rebuilt after a reconnect.
+struct Globals {
+    compositor: WlCompositor,
+    shm: WlShm,
+    shell: Shell,
+    /// Only held so the notifications stay alive.
+    _idle_notifications: Option<(ExtIdleNotificationV1, ExtIdleNotificationV1)>,
+}
+
+fn bind_globals(
+    conn: &Connection,
+    cfg: &Config,
+    state: &mut State,
+) -> Result<(wayland_client::EventQueue<State>, Globals)> {
+    let (globals, event_queue) = registry_queue_init(conn).context("init registry")?;
+    let qh = event_queue.handle();
+
+    let compositor: WlCompositor = globals.bind(&qh, 4..=5, ()).context("bind wl_compositor")?;
+    let shm: WlShm = globals.bind(&qh, 1..=1, ()).context("bind wl_shm")?;
+    let shell = match globals.bind(&qh, 1..=4, ()) {
+        Ok(layer_shell) => Shell::Layer(layer_shell),
+        Err(BindError::NotPresent) if cfg.fallback_window => {
+            Shell::Window(globals.bind(&qh, 1..=5, ()).context("bind xdg_wm_base")?)
         }
-        command
-    } else {
-        if rest.is_empty() {
-            return Err(anyhow!("missing message"));
+        Err(BindError::NotPresent) => {
+            return Err(anyhow!(
+                "compositor does not support wlr-layer-shell (try --fallback-window)"
+            ))
         }
-        let message = if rest.len() == 1 {
-            rest[0].clone()
-        } else {
-            let title = &rest[0];
-            let body = rest[1..].join(" ");
-            format!("{}\n{}", title, body)
-        };
-        Command::Show(AlertArgs {
-            position,
-            message,
-            name: alert_name,
-            class: alert_class,
-        })
+        Err(err) => return Err(err).context("bind zwlr_layer_shell_v1"),
     };
-
-    if env::var("CREAK_DEBUG").is_ok() {
-        eprintln!("creak config: {:?}", cfg);
+    state.cursor_shape_manager = globals.bind(&qh, 1..=1, ()).ok();
+    state.seat = globals.bind(&qh, 1..=7, ()).ok();
+    // Two idle notifications with the same timeout: only the input one
+    // ignores inhibitors, so they disagree exactly while one is active.
+    let idle_notifications = match (cfg.defer_while_inhibited, state.seat.as_ref()) {
+        (true, Some(seat)) => match globals.bind::<ExtIdleNotifierV1, _, _>(&qh, 2..=2, ()) {
+            Ok(notifier) => Some((
+                notifier.get_idle_notification(INHIBIT_PROBE_MS, seat, &qh, false),
+                notifier.get_input_idle_notification(INHIBIT_PROBE_MS, seat, &qh, true),
+            )),
+            Err(err) => {
+                log_info!(
+                    "ext_idle_notifier_v1 v2 unavailable, not deferring: {}",
+                    err
+                );
+                None
+            }
+        },
+        _ => None,
+    };
+    for global in globals.contents().clone_list() {
+        if global.interface == WlOutput::interface().name {
+            let output = globals
+                .registry()
+                .bind(global.name, global.version.min(4), &qh, ());
+            state.output_globals.insert(global.name, output);
+        }
     }
-    Ok((Args { command, state_dir }, cfg))
+    Ok((
+        event_queue,
+        Globals {
+            compositor,
+            shm,
+            shell,
+            _idle_notifications: idle_notifications,
+        },
+    ))
 }
 
-fn parse_clear_command(
-    iter: &mut std::iter::Peekable<std::vec::IntoIter<String>>,
-) -> Result<Command> {
-    let by = next_value("clear", iter)?;
-    if by != "by" {
-        return Err(anyhow!("usage: creak clear by <name|class|id> <value>"));
-    }
-    let key = next_value("clear by", iter)?;
-    let value = next_value("clear by <key>", iter)?;
-    match key.as_str() {
-        "name" => Ok(Command::ClearByName(value)),
-        "class" => Ok(Command::ClearByClass(value)),
-        "id" => Ok(Command::ClearById(value.parse()?)),
-        _ => Err(anyhow!("usage: creak clear by <name|class|id> <value>")),
+/// Blocks until the shell surface's first configure, which is all the first
+/// paint needs, rather than a full roundtrip.
+fn wait_for_configure(
+    event_queue: &mut wayland_client::EventQueue<State>,
+    state: &mut State,
+) -> Result<()> {
+    while !state.configured && !state.surface_closed && state.dismissed.is_none() {
+        event_queue.blocking_dispatch(state)?;
     }
+    Ok(())
 }
 
-fn dispatch_with_timeout(
+/// Wayland never reveals the global pointer position, so briefly map an
+/// invisible full-output surface and read where the pointer enters it.
+/// It needs input to see the pointer, so for up to 100ms it takes pointer
+/// focus from the window below and a click then lands on the probe; that
+/// click still dismisses the alert rather than vanishing. The transparent
+/// buffer costs 4 bytes per output pixel (about 33MB at 4K) until it is freed.
+fn probe_pointer(
     event_queue: &mut wayland_client::EventQueue<State>,
     state: &mut State,
-    timeout_ms: i32,
-) -> Result<()> {
-    if let Some(guard) = event_queue.prepare_read() {
-        let fd = guard.connection_fd().as_raw_fd();
-        let mut pollfd = libc::pollfd {
-            fd,
-            events: libc::POLLIN,
-            revents: 0,
-        };
-        let rc = unsafe { libc::poll(&mut pollfd as *mut libc::pollfd, 1, timeout_ms) };
-        if rc > 0 && (pollfd.revents & libc::POLLIN) != 0 {
-            if let Err(err) = guard.read() {
-                match err {
-                    WaylandError::Io(io_err) if io_err.kind() == ErrorKind::WouldBlock => {}
-                    other => return Err(anyhow!("wayland read error: {:?}", other)),
-                }
+    compositor: &WlCompositor,
+    layer_shell: &ZwlrLayerShellV1,
+    shm: &WlShm,
+    qh: &QueueHandle<State>,
+) -> Result<Option<PointerProbe>> {
+    if state.pointer.is_none() {
+        return Ok(None);
+    }
+    let saved = (state.width, state.height, state.entered.clone());
+    state.width = 0;
+    state.height = 0;
+
+    let surface = compositor.create_surface(qh, ());
+    let layer_surface = layer_shell.get_layer_surface(
+        &surface,
+        None,
+        zwlr_layer_shell_v1::Layer::Overlay,
+        "creak-probe".to_string(),
+        qh,
+        (),
+    );
+    layer_surface.set_anchor(
+        zwlr_layer_surface_v1::Anchor::Top
+            | zwlr_layer_surface_v1::Anchor::Bottom
+            | zwlr_layer_surface_v1::Anchor::Left
+            | zwlr_layer_surface_v1::Anchor::Right,
+    );
+    layer_surface.set_size(0, 0);
+    layer_surface.set_exclusive_zone(-1);
+    surface.commit();
+    event_queue.roundtrip(state)?;
+
+    let (output_width, output_height) = (state.width, state.height);
+    let mut probe = None;
+    if output_width > 0 && output_height > 0 {
+        let buffer = create_buffer(shm, qh, output_width, output_height, Transform::Normal)?;
+        surface.attach(Some(&buffer.wl_buffer), 0, 0);
+        surface.commit();
+        state.pointer_seen = false;
+        let started = Instant::now();
+        while !state.pointer_seen && started.elapsed() < Duration::from_millis(100) {
+            event_queue.flush()?;
+            dispatch_with_timeout(event_queue, state, 10, None)?;
+        }
+        if state.pointer_seen {
+            probe = Some(PointerProbe {
+                x: state.pointer_pos.0,
+                y: state.pointer_pos.1,
+                output_width,
+                output_height,
+            });
+        }
+        buffer.destroy();
+    }
+
+    layer_surface.destroy();
+    surface.destroy();
+    event_queue.roundtrip(state)?;
+    (state.width, state.height, state.entered) = saved;
+    state.pointer_seen = false;
+    state.surface_closed = false;
+    state.configured = false;
+    // Closing the probe surface is not a dismissal, but a click during the
+    // probe was meant for something and is honored.
+    state.dismissed = state
+        .dismissed
+        .filter(|reason| *reason == DismissReason::PointerClick);
+    Ok(probe)
+}
+
+fn blink_phase(elapsed: Duration) -> bool {
+    (elapsed.as_millis() / BLINK_PERIOD_MS) % 2 == 1
+}
+
+/// Bar width in buffer pixels; redraws are skipped until this changes.
+fn timeout_bar_pixels(frame: &Frame, logical_width: i32, scale: i32) -> i32 {
+    frame
+        .remaining
+        .map(|remaining| (remaining * (logical_width * scale) as f64).round() as i32)
+        .unwrap_or(0)
+}
+
+/// Where the alert's surface gets its role: wlr-layer-shell, or a plain
+/// xdg-shell window when `--fallback-window` kicks in.
+enum Shell {
+    Layer(ZwlrLayerShellV1),
+    Window(XdgWmBase),
+}
+
+enum ShellSurface {
+    Layer(ZwlrLayerSurfaceV1),
+    Window(XdgSurface, XdgToplevel),
+}
+
+impl ShellSurface {
+    fn set_margin(&self, margins: Margins) {
+        // xdg-shell windows are placed by the compositor.
+        if let ShellSurface::Layer(layer_surface) = self {
+            layer_surface.set_margin(margins.top, margins.right, margins.bottom, margins.left);
+        }
+    }
+
+    fn set_size(&self, width: i32, height: i32) {
+        match self {
+            ShellSurface::Layer(layer_surface) => {
+                layer_surface.set_size(width as u32, height as u32)
+            }
+            ShellSurface::Window(_, toplevel) => {
+                toplevel.set_min_size(width, height);
+                toplevel.set_max_size(width, height);
+            }
+        }
+    }
+
+    fn set_exclusive_zone(&self, zone: i32) {
+        if let ShellSurface::Layer(layer_surface) = self {
+            layer_surface.set_exclusive_zone(zone);
+        }
+    }
+
+    fn destroy(&self) {
+        match self {
+            ShellSurface::Layer(layer_surface) => layer_surface.destroy(),
+            ShellSurface::Window(xdg_surface, toplevel) => {
+                toplevel.destroy();
+                xdg_surface.destroy();
             }
         }
     }
-    event_queue.dispatch_pending(state)?;
-    Ok(())
 }
 
-fn load_config_args(style: Option<&str>) -> Result<Vec<String>> {
-    let xdg_config = env::var("XDG_CONFIG_HOME")
-        .unwrap_or_else(|_| format!("{}/.config", env::var("HOME").unwrap_or_default()));
-    let path = config_path_for_style(&xdg_config, style);
-    if env::var("CREAK_DEBUG").is_ok() {
-        eprintln!("creak config path: {}", path);
+/// Rectangles covering the bubble, with the rounded corners approximated
+/// row by row, so clicks on the transparent corners and beside a tail pass
+/// through.
+fn input_rects(cfg: &Config, width: i32, height: i32) -> Vec<(i32, i32, i32, i32)> {
+    let (mut x, mut y, mut w, mut h) = (0, 0, width, height);
+    match cfg.tail.map(|tail| tail.edge) {
+        Some(TailEdge::Top) => (y, h) = (TAIL_SIZE, h - TAIL_SIZE),
+        Some(TailEdge::Left) => (x, w) = (TAIL_SIZE, w - TAIL_SIZE),
+        Some(TailEdge::Bottom) => h -= TAIL_SIZE,
+        Some(TailEdge::Right) => w -= TAIL_SIZE,
+        None => {}
     }
-    let contents = match fs::read_to_string(&path) {
-        Ok(v) => v,
-        Err(_) => return Ok(Vec::new()),
+    let r = cfg.border_radius.min(w / 2).min(h / 2).max(0);
+    // How far the arc sits from the side at the middle of corner row `row`.
+    let inset = |row: i32| {
+        let d = (r - row) as f64 - 0.5;
+        (r as f64 - (r as f64 * r as f64 - d * d).max(0.0).sqrt()).round() as i32
     };
+    let mut rects = Vec::new();
+    let mut start = 0;
+    while start < r {
+        let i = inset(start);
+        let mut end = start + 1;
+        while end < r && inset(end) == i {
+            end += 1;
+        }
+        rects.push((x + i, y + start, w - 2 * i, end - start));
+        rects.push((x + i, y + h - end, w - 2 * i, end - start));
+        start = end;
+    }
+    rects.push((x, y + r, w, h - 2 * r));
+    rects
+}
 
-    let mut args = Vec::new();
-    for line in contents.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
+/// With `--all-outputs` the main surface sits on the lowest-numbered output
+/// and `sync_mirrors` covers the rest. Otherwise `--output`/`--output-index`
+/// pick one, or the compositor does.
+fn main_output(state: &State, cfg: &Config) -> Option<WlOutput> {
+    let mut names: Vec<u32> = state.output_globals.keys().copied().collect();
+    names.sort_unstable();
+    if cfg.all_outputs {
+        return state.output_globals.get(names.first()?).cloned();
+    }
+    if cfg.output.is_none() && cfg.output_index.is_none() {
+        return None;
+    }
+    let infos: Vec<OutputInfo> = names
+        .iter()
+        .map(|name| state.output_globals[name].id().protocol_id())
+        .map(|id| state.outputs.get(&id).cloned().unwrap_or_default())
+        .collect();
+    match select_output(&infos, cfg) {
+        Some(index) => state.output_globals.get(&names[index]).cloned(),
+        None => {
+            log_warn!(
+                "no output matches {}, letting the compositor choose",
+                cfg.output.clone().unwrap_or_else(|| format!(
+                    "--output-index {}",
+                    cfg.output_index.unwrap_or_default()
+                ))
+            );
+            None
         }
-        let parts = shell_words::split(line).context("parse config line")?;
-        args.extend(parts);
     }
-    Ok(args)
 }
 
-fn config_path_for_style(xdg_config_home: &str, style: Option<&str>) -> String {
-    let default_dir = format!("{}/creak", xdg_config_home);
-    match style {
-        Some(value) if value.contains('/') => value.to_string(),
-        Some(value) => format!("{}/{}", default_dir, value),
-        None => format!("{}/config", default_dir),
+/// Index into `infos` (outputs in registry order) for `--output-index`, or
+/// for `--output`: an exact connector name first, then a case-insensitive
+/// match inside "make model".
+fn select_output(infos: &[OutputInfo], cfg: &Config) -> Option<usize> {
+    if let Some(index) = cfg.output_index {
+        return (index < infos.len()).then_some(index);
     }
+    let selector = cfg.output.as_deref()?;
+    infos
+        .iter()
+        .position(|info| info.name.as_deref() == Some(selector))
+        .or_else(|| {
+            let needle = selector.to_lowercase();
+            infos.iter().position(|info| {
+                format!("{} {}", info.make, info.model)
+                    .to_lowercase()
+                    .contains(&needle)
+            })
+        })
 }
 
-fn next_value(
-    name: &str,
-    iter: &mut std::iter::Peekable<std::vec::IntoIter<String>>,
-) -> Result<String> {
-    iter.next()
-        .ok_or_else(|| anyhow!("{} requires a value", name))
+/// Drops copies on outputs that are gone and adds one for each new output.
+/// Returns whether any were added; they need a roundtrip and a paint.
+#[allow(clippy::too_many_arguments)]
+fn sync_mirrors(
+    state: &mut State,
+    globals: &Globals,
+    qh: &QueueHandle<State>,
+    cfg: &Config,
+    anchor: zwlr_layer_surface_v1::Anchor,
+    margins: Margins,
+    fixed_scale: Option<i32>,
+) -> Result<bool> {
+    let Shell::Layer(layer_shell) = &globals.shell else {
+        return Ok(false);
+    };
+    let main = state.output_globals.keys().min().copied();
+    state.mirrors.retain(|mirror| {
+        let keep = !mirror.closed
+            && Some(mirror.output) != main
+            && state.output_globals.contains_key(&mirror.output);
+        if !keep {
+            mirror.destroy();
+        }
+        keep
+    });
+    let (width, height) = (state.width, state.height);
+    let mut changed = false;
+    // An output's scale or rotation can change under an existing copy.
+    for i in 0..state.mirrors.len() {
+        let (scale, transform) = mirror_look(state, state.mirrors[i].output, fixed_scale);
+        let mirror = &mut state.mirrors[i];
+        if (scale, transform) != (mirror.scale, mirror.transform) {
+            (mirror.scale, mirror.transform) = (scale, transform);
+            mirror.surface.set_buffer_scale(scale);
+            mirror.surface.set_buffer_transform(transform);
+            mirror
+                .buffers
+                .resize(qh, width * scale, height * scale, transform)?;
+            changed = true;
+        }
+    }
+    let mut names: Vec<u32> = state.output_globals.keys().copied().collect();
+    names.sort_unstable();
+    for name in names {
+        if Some(name) == main || state.mirrors.iter().any(|mirror| mirror.output == name) {
+            continue;
+        }
+        let (surface, layer_surface) = create_layer_surface(
+            &globals.compositor,
+            layer_shell,
+            qh,
+            cfg,
+            state.output_globals.get(&name),
+            anchor,
+            margins,
+            (width, height),
+            name,
+        );
+        let (scale, transform) = mirror_look(state, name, fixed_scale);
+        surface.set_buffer_scale(scale);
+        surface.set_buffer_transform(transform);
+        set_input_region(&globals.compositor, qh, &surface, cfg, width, height);
+        let buffers = Buffers::new(
+            &globals.shm,
+            qh,
+            width * scale,
+            height * scale,
+            transform,
+            if cfg.double_buffer { 2 } else { 1 },
+        )?;
+        log_debug!("mirroring onto output {}", name);
+        state.mirrors.push(Mirror {
+            output: name,
+            surface,
+            layer_surface,
+            buffers,
+            scale,
+            transform,
+            configured: false,
+            closed: false,
+        });
+        changed = true;
+    }
+    Ok(changed)
 }
 
-fn default_config() -> Config {
-    Config {
-        font: "SimSun 25".to_string(),
-        width: 350,
-        padding: 10,
-        border_size: 5,
-        border_radius: 10,
-        timeout_ms: 5000,
-        background: [0.1, 0.1, 0.1, 1.0],
-        text: [1.0, 1.0, 1.0, 1.0],
-        border: [1.0, 1.0, 1.0, 1.0],
-        edge: 20,
-        default_offset: 250,
-        stack_gap: 10,
-        stack: true,
-        output_scale: 0,
-        text_antialias: None,
-        text_hint: None,
-        text_hint_metrics: None,
+/// Buffer scale and transform for a copy on `output`.
+fn mirror_look(state: &State, output: u32, fixed_scale: Option<i32>) -> (i32, Transform) {
+    let info = state.outputs.get(&output);
+    let scale = fixed_scale.unwrap_or_else(|| info.map_or(state.scale, |info| info.scale));
+    let transform = info.map_or(Transform::Normal, |info| info.transform);
+    (scale.max(1), transform)
+}
+
+/// Keeps the `--all-outputs` copies at the main surface's size.
+fn resize_mirrors(
+    state: &mut State,
+    compositor: &WlCompositor,
+    qh: &QueueHandle<State>,
+    cfg: &Config,
+    fixed_scale: Option<i32>,
+) -> Result<()> {
+    let (width, height) = (state.width, state.height);
+    for i in 0..state.mirrors.len() {
+        let (scale, transform) = mirror_look(state, state.mirrors[i].output, fixed_scale);
+        let mirror = &mut state.mirrors[i];
+        (mirror.scale, mirror.transform) = (scale, transform);
+        mirror.layer_surface.set_size(width as u32, height as u32);
+        if cfg.exclusive_zone.is_none() {
+            mirror.layer_surface.set_exclusive_zone(height);
+        }
+        mirror.surface.set_buffer_scale(scale);
+        mirror.surface.set_buffer_transform(transform);
+        set_input_region(compositor, qh, &mirror.surface, cfg, width, height);
+        mirror
+            .buffers
+            .resize(qh, width * scale, height * scale, transform)?;
     }
+    Ok(())
 }
 
-fn parse_hex_color(value: &str) -> Option<[f64; 4]> {
-    let hex = value.trim_start_matches('#');
-    let (r, g, b, a) = match hex.len() {
-        6 => {
-            let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
-            let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
-            let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
-            (r, g, b, 255)
+/// `output` pins the surface to one output (`--all-outputs`); otherwise the
+/// compositor picks.
+#[allow(clippy::too_many_arguments)]
+fn create_shell_surface(
+    compositor: &WlCompositor,
+    shell: &Shell,
+    qh: &QueueHandle<State>,
+    cfg: &Config,
+    output: Option<&WlOutput>,
+    anchor: zwlr_layer_surface_v1::Anchor,
+    margins: Margins,
+    (width, height): (i32, i32),
+) -> (WlSurface, ShellSurface) {
+    match shell {
+        Shell::Layer(layer_shell) => {
+            let (surface, layer_surface) = create_layer_surface(
+                compositor,
+                layer_shell,
+                qh,
+                cfg,
+                output,
+                anchor,
+                margins,
+                (width, height),
+                (),
+            );
+            (surface, ShellSurface::Layer(layer_surface))
         }
-        8 => {
-            let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
-            let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
-            let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
-            let a = u8::from_str_radix(&hex[6..8], 16).ok()?;
-            (r, g, b, a)
+        Shell::Window(wm_base) => {
+            let surface = compositor.create_surface(qh, ());
+            let xdg_surface = wm_base.get_xdg_surface(&surface, qh, ());
+            let toplevel = xdg_surface.get_toplevel(qh, ());
+            toplevel.set_title("creak".to_string());
+            toplevel.set_app_id(cfg.namespace.clone());
+            toplevel.set_min_size(width, height);
+            toplevel.set_max_size(width, height);
+            surface.commit();
+            (surface, ShellSurface::Window(xdg_surface, toplevel))
         }
-        _ => return None,
-    };
+    }
+}
 
-    Some([
-        r as f64 / 255.0,
-        g as f64 / 255.0,
-        b as f64 / 255.0,
-        a as f64 / 255.0,
-    ])
+/// `data` tells the main surface (`()`) from `--all-outputs` copies.
+#[allow(clippy::too_many_arguments)]
+fn create_layer_surface<U: Clone + Send + Sync + 'static>(
+    compositor: &WlCompositor,
+    layer_shell: &ZwlrLayerShellV1,
+    qh: &QueueHandle<State>,
+    cfg: &Config,
+    output: Option<&WlOutput>,
+    anchor: zwlr_layer_surface_v1::Anchor,
+    margins: Margins,
+    (width, height): (i32, i32),
+    data: U,
+) -> (WlSurface, ZwlrLayerSurfaceV1)
+where
+    State: Dispatch<WlSurface, U> + Dispatch<ZwlrLayerSurfaceV1, U>,
+{
+    let surface = compositor.create_surface(qh, data.clone());
+    let layer_surface =
+        layer_shell.get_layer_surface(&surface, output, cfg.layer, cfg.namespace.clone(), qh, data);
+    layer_surface.set_anchor(anchor);
+    layer_surface.set_margin(margins.top, margins.right, margins.bottom, margins.left);
+    layer_surface.set_size(width as u32, height as u32);
+    let mut keyboard = cfg.keyboard;
+    if keyboard == zwlr_layer_surface_v1::KeyboardInteractivity::OnDemand
+        && layer_shell.version() < 4
+    {
+        // on-demand needs layer-shell v4. Exclusive would take focus from
+        // every other window until the alert closes, so go without instead.
+        log_warn!(
+            "--keyboard on-demand needs layer-shell v4 (compositor has v{}); using none",
+            layer_shell.version()
+        );
+        keyboard = zwlr_layer_surface_v1::KeyboardInteractivity::None;
+    }
+    layer_surface.set_keyboard_interactivity(keyboard);
+    layer_surface.set_exclusive_zone(cfg.exclusive_zone.unwrap_or(height));
+    surface.commit();
+    (surface, layer_surface)
 }
 
-fn parse_antialias(value: &str) -> Result<Option<Antialias>> {
-    match value {
-        "default" => Ok(None),
-        "none" => Ok(Some(Antialias::None)),
-        "gray" => Ok(Some(Antialias::Gray)),
-        "subpixel" => Ok(Some(Antialias::Subpixel)),
-        _ => Err(anyhow!("invalid --text-antialias: {}", value)),
+/// The bubble takes input, or nothing does with `--click-through`.
+fn set_input_region(
+    compositor: &WlCompositor,
+    qh: &QueueHandle<State>,
+    surface: &WlSurface,
+    cfg: &Config,
+    width: i32,
+    height: i32,
+) {
+    let region = compositor.create_region(qh, ());
+    if !cfg.click_through {
+        for (x, y, w, h) in input_rects(cfg, width, height) {
+            region.add(x, y, w, h);
+        }
     }
+    surface.set_input_region(Some(&region));
+    region.destroy();
 }
 
-fn parse_hint_style(value: &str) -> Result<Option<HintStyle>> {
-    match value {
-        "default" => Ok(None),
-        "none" => Ok(Some(HintStyle::None)),
-        "slight" => Ok(Some(HintStyle::Slight)),
-        "medium" => Ok(Some(HintStyle::Medium)),
-        "full" => Ok(Some(HintStyle::Full)),
-        _ => Err(anyhow!("invalid --text-hint: {}", value)),
+/// Writes the alert as a PNG to `path`, or to `out` when `path` is `-`.
+fn render_png(args: &AlertArgs, cfg: &mut Config, path: &str, out: &mut dyn Write) -> Result<()> {
+    if cfg.output_scale <= 0 {
+        cfg.output_scale = 1;
     }
+    let surface = render_offscreen(args, cfg)?;
+    if path == "-" {
+        surface
+            .write_to_png(&mut &mut *out)
+            .context("write png to stdout")?;
+        out.flush()?;
+        return Ok(());
+    }
+    let mut file = fs::File::create(path).with_context(|| format!("create {}", path))?;
+    surface
+        .write_to_png(&mut file)
+        .with_context(|| format!("write {}", path))?;
+    Ok(())
 }
 
-fn parse_hint_metrics(value: &str) -> Result<Option<HintMetrics>> {
-    match value {
-        "default" => Ok(None),
-        "on" => Ok(Some(HintMetrics::On)),
-        "off" => Ok(Some(HintMetrics::Off)),
-        _ => Err(anyhow!("invalid --text-hint-metrics: {}", value)),
+/// Measures and draws the alert into a new image surface, as `render` does.
+fn render_offscreen(args: &AlertArgs, cfg: &Config) -> Result<ImageSurface> {
+    let scale = cfg.output_scale;
+    let (width, height) = measure_notification(cfg, args)?;
+    let surface = ImageSurface::create(Format::ARgb32, width * scale, height * scale)?;
+    let frame = Frame {
+        remaining: (cfg.timeout_bar_height() > 0).then_some(1.0),
+        ..Frame::default()
+    };
+    let text = display_message(cfg, args);
+    let content = Content {
+        text: &text,
+        app_name: args.app_name.as_deref(),
+        actions: &args.actions,
+    };
+    render_notification(&surface, width, height, cfg, &content, &frame)?;
+    Ok(surface)
+}
+
+/// `creak bench`: times `iterations` runs of `render_offscreen` after one
+/// untimed warm-up run, which loads the fonts.
+fn run_bench(args: &AlertArgs, cfg: &mut Config, iterations: u32) -> Result<serde_json::Value> {
+    if cfg.output_scale <= 0 {
+        cfg.output_scale = 1;
+    }
+    let (width, height) = measure_notification(cfg, args)?;
+    render_offscreen(args, cfg)?;
+    let mut times = Vec::with_capacity(iterations as usize);
+    let allocs_before = allocation_count();
+    for _ in 0..iterations {
+        let started = Instant::now();
+        render_offscreen(args, cfg)?;
+        times.push(started.elapsed());
+    }
+    // `times` has its capacity already, so every counted allocation came
+    // from drawing.
+    let allocs = allocation_count()
+        .zip(allocs_before)
+        .map(|(after, before)| after - before);
+    times.sort();
+    let total: Duration = times.iter().sum();
+    let micros = |d: Duration| (d.as_nanos() as f64 / 100.0).round() / 10.0;
+    let mut report = serde_json::json!({
+        "iterations": iterations,
+        "width": width,
+        "height": height,
+        "mean_us": micros(total / iterations),
+        "median_us": micros(percentile(&times, 50.0)),
+        "p99_us": micros(percentile(&times, 99.0)),
+    });
+    if let Some(allocs) = allocs {
+        report["allocs_per_iter"] = (allocs as f64 / f64::from(iterations)).into();
     }
+    Ok(report)
 }
 
-fn position_to_anchor(
+/// Nearest-rank percentile of an ascending, non-empty slice.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn paint(
+    surface: &WlSurface,
+    buffers: &mut Buffers,
+    event_queue: &mut wayland_client::EventQueue<State>,
+    state: &mut State,
     cfg: &Config,
-    position: Position,
-) -> (zwlr_layer_surface_v1::Anchor, Margins) {
-    let edge = cfg.edge;
-    let default_offset = cfg.default_offset;
+    args: &AlertArgs,
+    frame: &Frame,
+) -> Result<()> {
+    if buffers.all_busy() {
+        log_debug!("redraw waiting for wl_buffer release");
+        let started = Instant::now();
+        while buffers.all_busy() && started.elapsed() < RELEASE_WAIT {
+            dispatch_with_timeout(event_queue, state, 5, None)?;
+        }
+        if buffers.all_busy() {
+            log_debug!(
+                "no wl_buffer released after {:?}, drawing anyway",
+                RELEASE_WAIT
+            );
+        }
+    }
+    let text = display_message(cfg, args);
+    let content = Content {
+        text: &text,
+        app_name: args.app_name.as_deref(),
+        actions: &args.actions,
+    };
+    let buffer = buffers.back();
+    state.buttons = draw_notification(buffer, state.width, state.height, cfg, &content, frame)?;
+    surface.attach(Some(&buffer.wl_buffer), 0, 0);
+    surface.damage_buffer(0, 0, buffer.width, buffer.height);
+    surface.commit();
+    buffer.busy.store(true, Ordering::Relaxed);
+    // Copies share the layout, so the main surface's buttons hit-test for them too.
+    for mirror in state.mirrors.iter_mut().filter(|mirror| mirror.configured) {
+        let buffer = mirror.buffers.back();
+        draw_notification(buffer, state.width, state.height, cfg, &content, frame)?;
+        mirror.surface.attach(Some(&buffer.wl_buffer), 0, 0);
+        mirror
+            .surface
+            .damage_buffer(0, 0, buffer.width, buffer.height);
+        mirror.surface.commit();
+        buffer.busy.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
 
-    match position {
-        Position::TopLeft => (
-            zwlr_layer_surface_v1::Anchor::Top | zwlr_layer_surface_v1::Anchor::Left,
-            Margins {
-                top: edge,
-                left: edge,
-                ..Margins::default()
-            },
-        ),
-        Position::Top => (
-            zwlr_layer_surface_v1::Anchor::Top,
-            Margins {
-                top: edge,
-                ..Margins::default()
-            },
-        ),
-        Position::TopRight => (
-            zwlr_layer_surface_v1::Anchor::Top | zwlr_layer_surface_v1::Anchor::Right,
-            Margins {
-                top: edge,
-                right: edge,
-                ..Margins::default()
-            },
-        ),
-        Position::Left => (
-            zwlr_layer_surface_v1::Anchor::Left,
-            Margins {
-                left: edge,
-                ..Margins::default()
-            },
-        ),
-        Position::Center => (zwlr_layer_surface_v1::Anchor::empty(), Margins::default()),
-        Position::Right => (
-            zwlr_layer_surface_v1::Anchor::Right,
-            Margins {
-                right: edge,
-                ..Margins::default()
-            },
-        ),
-        Position::BottomLeft => (
-            zwlr_layer_surface_v1::Anchor::Bottom | zwlr_layer_surface_v1::Anchor::Left,
-            Margins {
-                bottom: edge,
-                left: edge,
-                ..Margins::default()
-            },
-        ),
-        Position::Bottom => (
-            zwlr_layer_surface_v1::Anchor::Bottom,
-            Margins {
-                bottom: edge,
-                ..Margins::default()
-            },
-        ),
-        Position::BottomRight => (
-            zwlr_layer_surface_v1::Anchor::Bottom | zwlr_layer_surface_v1::Anchor::Right,
-            Margins {
-                bottom: edge,
-                right: edge,
-                ..Margins::default()
-            },
-        ),
-        Position::Default => (
-            zwlr_layer_surface_v1::Anchor::Top,
-            Margins {
-                top: default_offset,
-                ..Margins::default()
-            },
-        ),
+fn init_logging_from_env() {
+    let level = if env::var("CREAK_DEBUG").is_ok() {
+        Some(LogLevel::Debug)
+    } else {
+        env::var("CREAK_LOG")
+            .ok()
+            .and_then(|value| parse_log_level(&value).ok())
+    };
+    if let Some(level) = level {
+        LOG_LEVEL.store(level as u8, Ordering::Relaxed);
     }
 }
 
-fn position_key(position: Position) -> &'static str {
-    match position {
-        Position::TopLeft => "top-left",
-        Position::Top => "top",
-        Position::TopRight => "top-right",
-        Position::Left => "left",
-        Position::Center => "center",
-        Position::Right => "right",
-        Position::BottomLeft => "bottom-left",
-        Position::Bottom => "bottom",
-        Position::BottomRight => "bottom-right",
-        Position::Default => "default",
+/// Applies `--log-level`/`--log-file` on top of the environment. Alerts are
+/// often spawned with stderr discarded, so a file is the way to see them.
+fn configure_logging(cfg: &Config) -> Result<()> {
+    if let Some(level) = cfg.log_level {
+        LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+    }
+    if let Some(path) = cfg.log_file.as_deref() {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open log file {}", path))?;
+        *LOG_FILE.lock().unwrap_or_else(|err| err.into_inner()) = Some(file);
     }
+    Ok(())
 }
 
-fn apply_stack_offset(mut margins: Margins, position: Position, offset: i32) -> Margins {
-    match position {
-        Position::Bottom | Position::BottomLeft | Position::BottomRight => {
-            margins.bottom += offset;
-        }
-        _ => {
-            margins.top += offset;
+fn log_enabled(level: LogLevel) -> bool {
+    level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+fn log_write(level: LogLevel, args: std::fmt::Arguments) {
+    if !log_enabled(level) {
+        return;
+    }
+    let now = now_millis();
+    let line = format!(
+        "{}.{:03} creak[{}] {:?}: {}\n",
+        now / 1000,
+        now % 1000,
+        std::process::id(),
+        level,
+        args
+    );
+    match LOG_FILE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_mut()
+    {
+        Some(file) => {
+            let _ = file.write_all(line.as_bytes());
         }
+        None => eprint!("{}", line),
     }
-    margins
 }
 
-fn now_millis() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_else(|_| Duration::from_secs(0))
-        .as_millis() as u64
+unsafe extern "C" fn handle_signal(_: i32) {
+    SHOULD_CLOSE.store(true, Ordering::Relaxed);
 }
 
-fn state_paths(state_dir: Option<&str>) -> Result<StatePaths> {
-    let dir = match state_dir {
-        Some(dir) => dir.to_string(),
-        None => {
-            let xdg_state = env::var("XDG_STATE_HOME").unwrap_or_else(|_| {
-                format!("{}/.local/state", env::var("HOME").unwrap_or_default())
-            });
-            format!("{}/creak", xdg_state)
-        }
-    };
-    fs::create_dir_all(&dir)?;
-    Ok(StatePaths {
-        state_path: format!("{}/stack.json", dir),
-        lock_path: format!("{}/stack.lock", dir),
-    })
+fn install_signal_handlers() {
+    unsafe {
+        libc::signal(
+            libc::SIGTERM,
+            handle_signal as *const () as libc::sighandler_t,
+        );
+        libc::signal(
+            libc::SIGINT,
+            handle_signal as *const () as libc::sighandler_t,
+        );
+    }
 }
 
-fn lock_state(lock_path: &str) -> Result<fs::File> {
-    let file = OpenOptions::new()
-        .create(true)
-        .read(true)
-        .write(true)
-        .open(lock_path)?;
-    let rc = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) };
-    if rc != 0 {
-        return Err(anyhow!("failed to lock stack state"));
+/// Packagers can embed a commit with `CREAK_GIT_HASH=<hash> cargo build`.
+fn version_string() -> String {
+    match option_env!("CREAK_GIT_HASH") {
+        Some(hash) => format!("creak {} ({})", env!("CARGO_PKG_VERSION"), hash),
+        None => format!("creak {}", env!("CARGO_PKG_VERSION")),
     }
-    Ok(file)
 }
 
-fn load_state(path: &str) -> Result<StackState> {
-    match fs::read_to_string(path) {
-        Ok(data) => {
-            if data.trim().is_empty() {
-                return Ok(StackState::default());
-            }
-            match serde_json::from_str(&data) {
-                Ok(state) => Ok(state),
-                Err(err) => {
-                    if env::var("CREAK_DEBUG").is_ok() {
-                        eprintln!("creak stack state parse failed: {}", err);
-                    }
-                    Ok(StackState::default())
-                }
-            }
+fn parse_args() -> Result<(Args, Config)> {
+    parse_cli(env::args().skip(1).collect())
+}
+
+/// Config-file tokens for the requested style, followed by `cli_tokens`.
+fn parse_cli(cli_tokens: Vec<String>) -> Result<(Args, Config)> {
+    let cfg = default_config();
+    let argv = cli_tokens.clone();
+    let (style, cli_tokens) = extract_style_arg(cli_tokens)?;
+    let (dir_flag, cli_tokens) = extract_arg(cli_tokens, "--config-dir")?;
+    let (profile, mut cli_tokens) = extract_arg(cli_tokens, "--profile")?;
+    let dir = config_dir(dir_flag.as_deref(), |key| env::var(key).ok());
+    // A broken config must not stop `config check` from reporting on it.
+    if is_config_check(&cli_tokens) {
+        let (mut args, cfg) = parse_tokens(cli_tokens, cfg)?;
+        if let Command::ConfigCheck(check) = &mut args.command {
+            *check = config_path_for_style(&dir, style.as_deref());
         }
-        Err(_) => Ok(StackState::default()),
+        return Ok((args, cfg));
     }
+    let mut tokens = load_config_args(&dir, style.as_deref())?;
+    let mut profile_tokens = match profile.as_deref() {
+        Some(name) => load_profile(&dir, name)?,
+        None => Vec::new(),
+    };
+    // Precedence: defaults, base config, urgency defaults, urgency config,
+    // class config, profile, CLI.
+    let later = || {
+        tokens
+            .iter()
+            .chain(profile_tokens.iter())
+            .chain(cli_tokens.iter())
+    };
+    let urgency = match last_arg(later(), "--urgency") {
+        Some(value) => parse_urgency(&value)?,
+        None => Urgency::Normal,
+    };
+    let class = last_arg(later(), "--class");
+    tokens.extend(urgency_defaults(urgency).iter().map(|t| t.to_string()));
+    let path = format!("{}/urgency.{}", dir, urgency_key(urgency));
+    log_debug!("urgency config path: {}", path);
+    tokens.append(&mut load_config_file(&path)?);
+    if let Some(path) = class.and_then(|class| config_path_for_class(&dir, &class)) {
+        log_debug!("class config path: {}", path);
+        tokens.append(&mut load_config_file(&path)?);
+    }
+    tokens.append(&mut profile_tokens);
+    tokens.append(&mut cli_tokens);
+    log_debug!("tokens: {:?}", tokens);
+    let (mut args, cfg) = parse_tokens(tokens, cfg)?;
+    if let Command::Show(alert) = &mut args.command {
+        alert.argv = argv;
+    }
+    Ok((args, cfg))
 }
 
-fn save_state(path: &str, state: &StackState) -> Result<()> {
-    let tmp = format!("{}.tmp", path);
-    let data = serde_json::to_vec(state)?;
-    fs::write(&tmp, data)?;
-    fs::rename(tmp, path)?;
-    Ok(())
+/// Whether the command is `config check`, looking past the global options
+/// that may come before it.
+fn is_config_check(tokens: &[String]) -> bool {
+    let mut iter = tokens.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--log-level" | "--log-file" | "--state-dir" => {
+                iter.next();
+            }
+            "--json-errors" | "--no-json-errors" => {}
+            _ if ["--log-level=", "--log-file=", "--state-dir="]
+                .iter()
+                .any(|prefix| arg.starts_with(prefix)) => {}
+            _ => return arg == "config",
+        }
+    }
+    false
 }
 
-fn message_summary(message: &str) -> String {
-    let mut summary = message
-        .lines()
-        .next()
-        .unwrap_or_default()
-        .trim()
-        .to_string();
-    if summary.len() > 120 {
-        summary.truncate(120);
-    }
-    summary
+fn extract_style_arg(tokens: Vec<String>) -> Result<(Option<String>, Vec<String>)> {
+    extract_arg(tokens, "--style")
 }
 
-fn process_alive(pid: u32) -> bool {
-    if pid == 0 {
-        return true;
-    }
-    let rc = unsafe { libc::kill(pid as i32, 0) };
-    if rc == 0 {
-        return true;
+/// Pulls `name <value>`/`name=<value>` out of `tokens` (the last one wins),
+/// for options needed before the config is read.
+fn extract_arg(tokens: Vec<String>, name: &str) -> Result<(Option<String>, Vec<String>)> {
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut value: Option<String> = None;
+    let mut i = 0usize;
+    while i < tokens.len() {
+        let arg = &tokens[i];
+        if arg == name {
+            if i + 1 >= tokens.len() {
+                return Err(anyhow!("{} requires a value", name));
+            }
+            value = Some(tokens[i + 1].clone());
+            i += 2;
+            continue;
+        }
+        if let Some(v) = arg
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            value = Some(v.to_string());
+            i += 1;
+            continue;
+        }
+        out.push(arg.clone());
+        i += 1;
     }
-    let code = std::io::Error::last_os_error().raw_os_error();
-    code == Some(libc::EPERM)
+    Ok((value, out))
 }
 
-fn prune_entries(state: &mut StackState, now: u64) {
-    state.entries.retain(|entry| {
-        let not_expired = entry.expires_at == 0 || entry.expires_at > now;
-        not_expired && process_alive(entry.pid)
-    });
-}
+fn parse_tokens(tokens: Vec<String>, mut cfg: Config) -> Result<(Args, Config)> {
+    let mut position = Position::Default;
+    let mut alert_name: Option<String> = None;
+    let mut alert_class: Option<String> = None;
+    let mut app_name: Option<String> = None;
+    let mut print_id = false;
+    let mut created_at = None;
+    let mut replace_id = None;
+    let mut actions: Vec<AlertAction> = Vec::new();
+    let mut state_dir: Option<String> = None;
+    let mut format = OutputFormat::Json;
+    let mut limit = 20usize;
+    let mut concurrent = false;
+    let mut render = false;
+    let mut measure = false;
+    let mut bench = false;
+    let mut iterations = 1000u32;
+    let mut out: Option<String> = None;
+    let mut socket: Option<String> = None;
+    let mut command: Option<Command> = None;
+    let mut rest: Vec<String> = Vec::new();
+    let mut iter = tokens.into_iter().peekable();
+    while let Some(arg) = iter.next() {
+        // Subcommand words only count in front of the message, so they can
+        // still appear in it.
+        let first_positional =
+            command.is_none() && rest.is_empty() && !(render || measure || bench);
+        if arg == "--top-left" {
+            position = Position::TopLeft;
+        } else if arg == "--top" || arg == "--top-center" {
+            position = Position::Top;
+        } else if arg == "--top-right" {
+            position = Position::TopRight;
+        } else if arg == "--left" {
+            position = Position::Left;
+        } else if arg == "--center" {
+            position = Position::Center;
+        } else if arg == "--right" {
+            position = Position::Right;
+        } else if arg == "--bottom-left" {
+            position = Position::BottomLeft;
+        } else if arg == "--bottom" || arg == "--bottom-center" {
+            position = Position::Bottom;
+        } else if arg == "--bottom-right" {
+            position = Position::BottomRight;
+        } else if arg == "--at-cursor" {
+            position = Position::Cursor;
+        } else if arg == "--timeout" {
+            let val = next_value("--timeout", &mut iter)?;
+            cfg.timeout_ms = val.parse()?;
+        } else if arg.starts_with("--timeout=") {
+            let val = arg.trim_start_matches("--timeout=");
+            cfg.timeout_ms = val.parse()?;
+        } else if arg == "--min-duration-ms" {
+            let val = next_value("--min-duration-ms", &mut iter)?;
+            cfg.min_duration_ms = val.parse()?;
+        } else if arg.starts_with("--min-duration-ms=") {
+            cfg.min_duration_ms = arg.trim_start_matches("--min-duration-ms=").parse()?;
+        } else if arg == "--min-timeout" {
+            let val = next_value("--min-timeout", &mut iter)?;
+            cfg.min_timeout_ms = val.parse()?;
+        } else if arg.starts_with("--min-timeout=") {
+            cfg.min_timeout_ms = arg.trim_start_matches("--min-timeout=").parse()?;
+        } else if arg == "--max-timeout" {
+            let val = next_value("--max-timeout", &mut iter)?;
+            cfg.max_timeout_ms = val.parse()?;
+        } else if arg.starts_with("--max-timeout=") {
+            cfg.max_timeout_ms = arg.trim_start_matches("--max-timeout=").parse()?;
+        } else if arg == "--allow-persistent" {
+            cfg.allow_persistent = true;
+        } else if arg == "--no-allow-persistent" {
+            cfg.allow_persistent = false;
+        } else if arg == "--timeout-bar" {
+            cfg.timeout_bar = true;
+        } else if arg == "--no-timeout-bar" {
+            cfg.timeout_bar = false;
+        } else if arg == "--timeout-bar-color" {
+            let val = next_value("--timeout-bar-color", &mut iter)?;
+            cfg.timeout_bar_color = Some(
+                parse_color(&val)
+                    .ok_or_else(|| anyhow!("invalid color for --timeout-bar-color"))?,
+            );
+        } else if arg.starts_with("--timeout-bar-color=") {
+            let val = arg.trim_start_matches("--timeout-bar-color=");
+            cfg.timeout_bar_color = Some(
+                parse_color(val).ok_or_else(|| anyhow!("invalid color for --timeout-bar-color"))?,
+            );
+        } else if arg == "--width" {
+            let val = next_value("--width", &mut iter)?;
+            (cfg.width, cfg.width_percent) = parse_width(&val)?;
+        } else if arg.starts_with("--width=") {
+            let val = arg.trim_start_matches("--width=");
+            (cfg.width, cfg.width_percent) = parse_width(val)?;
+        } else if arg == "--min-width" {
+            let val = next_value("--min-width", &mut iter)?;
+            cfg.min_width = val.parse()?;
+        } else if arg.starts_with("--min-width=") {
+            cfg.min_width = arg.trim_start_matches("--min-width=").parse()?;
+        } else if arg == "--fit-width" {
+            cfg.fit_width = true;
+        } else if arg == "--no-fit-width" {
+            cfg.fit_width = false;
+        } else if arg == "--font" {
+            cfg.font = next_value("--font", &mut iter)?;
+        } else if arg.starts_with("--font=") {
+            cfg.font = arg.trim_start_matches("--font=").to_string();
+        } else if arg == "--font-scale" {
+            let val = next_value("--font-scale", &mut iter)?;
+            cfg.font_scale = parse_font_scale(&val)?;
+        } else if arg.starts_with("--font-scale=") {
+            cfg.font_scale = parse_font_scale(arg.trim_start_matches("--font-scale="))?;
+        } else if arg == "--line-spacing" {
+            let val = next_value("--line-spacing", &mut iter)?;
+            cfg.line_spacing = val.parse()?;
+        } else if arg.starts_with("--line-spacing=") {
+            cfg.line_spacing = arg.trim_start_matches("--line-spacing=").parse()?;
+        } else if arg == "--letter-spacing" {
+            let val = next_value("--letter-spacing", &mut iter)?;
+            cfg.letter_spacing = val.parse()?;
+        } else if arg.starts_with("--letter-spacing=") {
+            cfg.letter_spacing = arg.trim_start_matches("--letter-spacing=").parse()?;
+        } else if arg == "--strip-markup" {
+            cfg.strip_markup = true;
+        } else if arg == "--no-strip-markup" {
+            cfg.strip_markup = false;
+        } else if arg == "--double-buffer" {
+            cfg.double_buffer = true;
+        } else if arg == "--no-double-buffer" {
+            cfg.double_buffer = false;
+        } else if arg == "--template" {
+            cfg.template = true;
+        } else if arg == "--no-template" {
+            cfg.template = false;
+        } else if arg == "--template-strict" {
+            cfg.template_strict = true;
+        } else if arg == "--no-template-strict" {
+            cfg.template_strict = false;
+        } else if arg == "--justify" {
+            cfg.justify = true;
+        } else if arg == "--no-justify" {
+            cfg.justify = false;
+        } else if arg == "--fallback-font" {
+            cfg.fallback_fonts
+                .push(next_value("--fallback-font", &mut iter)?);
+        } else if arg.starts_with("--fallback-font=") {
+            cfg.fallback_fonts
+                .push(arg.trim_start_matches("--fallback-font=").to_string());
+        } else if arg == "--padding" {
+            let val = next_value("--padding", &mut iter)?;
+            cfg.padding = parse_sides("--padding", &val)?;
+        } else if arg.starts_with("--padding=") {
+            cfg.padding = parse_sides("--padding", arg.trim_start_matches("--padding="))?;
+        } else if arg == "--padding-top" {
+            let val = next_value("--padding-top", &mut iter)?;
+            cfg.padding.top = val.parse()?;
+        } else if arg.starts_with("--padding-top=") {
+            cfg.padding.top = arg.trim_start_matches("--padding-top=").parse()?;
+        } else if arg == "--padding-right" {
+            let val = next_value("--padding-right", &mut iter)?;
+            cfg.padding.right = val.parse()?;
+        } else if arg.starts_with("--padding-right=") {
+            cfg.padding.right = arg.trim_start_matches("--padding-right=").parse()?;
+        } else if arg == "--padding-bottom" {
+            let val = next_value("--padding-bottom", &mut iter)?;
+            cfg.padding.bottom = val.parse()?;
+        } else if arg.starts_with("--padding-bottom=") {
+            cfg.padding.bottom = arg.trim_start_matches("--padding-bottom=").parse()?;
+        } else if arg == "--padding-left" {
+            let val = next_value("--padding-left", &mut iter)?;
+            cfg.padding.left = val.parse()?;
+        } else if arg.starts_with("--padding-left=") {
+            cfg.padding.left = arg.trim_start_matches("--padding-left=").parse()?;
+        } else if arg == "--border-size" {
+            let val = next_value("--border-size", &mut iter)?;
+            cfg.border_size = parse_sides("--border-size", &val)?;
+        } else if arg.starts_with("--border-size=") {
+            cfg.border_size =
+                parse_sides("--border-size", arg.trim_start_matches("--border-size="))?;
+        } else if arg == "--border-top" {
+            let val = next_value("--border-top", &mut iter)?;
+            cfg.border_size.top = val.parse()?;
+        } else if arg.starts_with("--border-top=") {
+            cfg.border_size.top = arg.trim_start_matches("--border-top=").parse()?;
+        } else if arg == "--border-right" {
+            let val = next_value("--border-right", &mut iter)?;
+            cfg.border_size.right = val.parse()?;
+        } else if arg.starts_with("--border-right=") {
+            cfg.border_size.right = arg.trim_start_matches("--border-right=").parse()?;
+        } else if arg == "--border-bottom" {
+            let val = next_value("--border-bottom", &mut iter)?;
+            cfg.border_size.bottom = val.parse()?;
+        } else if arg.starts_with("--border-bottom=") {
+            cfg.border_size.bottom = arg.trim_start_matches("--border-bottom=").parse()?;
+        } else if arg == "--border-left" {
+            let val = next_value("--border-left", &mut iter)?;
+            cfg.border_size.left = val.parse()?;
+        } else if arg.starts_with("--border-left=") {
+            cfg.border_size.left = arg.trim_start_matches("--border-left=").parse()?;
+        } else if arg == "--tail" {
+            let val = next_value("--tail", &mut iter)?;
+            cfg.tail = parse_tail(&val)?;
+        } else if arg.starts_with("--tail=") {
+            cfg.tail = parse_tail(arg.trim_start_matches("--tail="))?;
+        } else if arg == "--border-radius" {
+            let val = next_value("--border-radius", &mut iter)?;
+            cfg.border_radius = val.parse()?;
+        } else if arg.starts_with("--border-radius=") {
+            cfg.border_radius = arg.trim_start_matches("--border-radius=").parse()?;
+        } else if arg == "--corner-smoothing" {
+            let val = next_value("--corner-smoothing", &mut iter)?;
+            cfg.corner_smoothing = parse_corner_smoothing(&val)?;
+        } else if arg.starts_with("--corner-smoothing=") {
+            let val = arg.trim_start_matches("--corner-smoothing=");
+            cfg.corner_smoothing = parse_corner_smoothing(val)?;
+        } else if arg == "--background" {
+            let val = next_value("--background", &mut iter)?;
+            cfg.background =
+                parse_color(&val).ok_or_else(|| anyhow!("invalid color for --background"))?;
+        } else if arg.starts_with("--background=") {
+            let val = arg.trim_start_matches("--background=");
+            cfg.background =
+                parse_color(val).ok_or_else(|| anyhow!("invalid color for --background"))?;
+        } else if arg == "--background-gradient" {
+            let val = next_value("--background-gradient", &mut iter)?;
+            cfg.background_gradient = parse_gradient(&val)?;
+        } else if arg.starts_with("--background-gradient=") {
+            cfg.background_gradient =
+                parse_gradient(arg.trim_start_matches("--background-gradient="))?;
+        } else if arg == "--background-image" {
+            cfg.background_image = Some(next_value("--background-image", &mut iter)?);
+        } else if arg.starts_with("--background-image=") {
+            cfg.background_image = Some(arg.trim_start_matches("--background-image=").to_string());
+        } else if arg == "--no-background-image" {
+            cfg.background_image = None;
+        } else if arg == "--gradient-angle" {
+            let val = next_value("--gradient-angle", &mut iter)?;
+            cfg.gradient_angle = val.parse()?;
+        } else if arg.starts_with("--gradient-angle=") {
+            cfg.gradient_angle = arg.trim_start_matches("--gradient-angle=").parse()?;
+        } else if arg == "--opacity" {
+            let val = next_value("--opacity", &mut iter)?;
+            cfg.opacity = parse_opacity(&val)?;
+        } else if arg.starts_with("--opacity=") {
+            cfg.opacity = parse_opacity(arg.trim_start_matches("--opacity="))?;
+        } else if arg == "--text" {
+            let val = next_value("--text", &mut iter)?;
+            set_text_color(&mut cfg, &val)?;
+        } else if arg.starts_with("--text=") {
+            set_text_color(&mut cfg, arg.trim_start_matches("--text="))?;
+        } else if arg == "--text-outline" {
+            let val = next_value("--text-outline", &mut iter)?;
+            cfg.text_outline = val.parse()?;
+        } else if arg.starts_with("--text-outline=") {
+            cfg.text_outline = arg.trim_start_matches("--text-outline=").parse()?;
+        } else if arg == "--text-outline-color" {
+            let val = next_value("--text-outline-color", &mut iter)?;
+            cfg.text_outline_color = parse_color(&val)
+                .ok_or_else(|| anyhow!("invalid color for --text-outline-color"))?;
+        } else if arg.starts_with("--text-outline-color=") {
+            let val = arg.trim_start_matches("--text-outline-color=");
+            cfg.text_outline_color = parse_color(val)
+                .ok_or_else(|| anyhow!("invalid color for --text-outline-color"))?;
+        } else if arg == "--border" {
+            let val = next_value("--border", &mut iter)?;
+            cfg.border = parse_color(&val).ok_or_else(|| anyhow!("invalid color for --border"))?;
+        } else if arg.starts_with("--border=") {
+            let val = arg.trim_start_matches("--border=");
+            cfg.border = parse_color(val).ok_or_else(|| anyhow!("invalid color for --border"))?;
+        } else if arg == "--separator" {
+            cfg.separator = true;
+        } else if arg == "--no-separator" {
+            cfg.separator = false;
+        } else if arg == "--separator-color" {
+            let val = next_value("--separator-color", &mut iter)?;
+            cfg.separator_color = Some(
+                parse_color(&val).ok_or_else(|| anyhow!("invalid color for --separator-color"))?,
+            );
+        } else if arg.starts_with("--separator-color=") {
+            let val = arg.trim_start_matches("--separator-color=");
+            cfg.separator_color = Some(
+                parse_color(val).ok_or_else(|| anyhow!("invalid color for --separator-color"))?,
+            );
+        } else if arg == "--blink" {
+            cfg.blink = true;
+        } else if arg == "--no-blink" {
+            cfg.blink = false;
+        } else if arg == "--blink-color" {
+            let val = next_value("--blink-color", &mut iter)?;
+            cfg.blink_color =
+                parse_color(&val).ok_or_else(|| anyhow!("invalid color for --blink-color"))?;
+        } else if arg.starts_with("--blink-color=") {
+            let val = arg.trim_start_matches("--blink-color=");
+            cfg.blink_color =
+                parse_color(val).ok_or_else(|| anyhow!("invalid color for --blink-color"))?;
+        } else if arg == "--edge" {
+            let val = next_value("--edge", &mut iter)?;
+            cfg.edge = val.parse()?;
+        } else if arg.starts_with("--edge=") {
+            cfg.edge = arg.trim_start_matches("--edge=").parse()?;
+        } else if arg == "--scale" {
+            let val = next_value("--scale", &mut iter)?;
+            cfg.output_scale = val.parse()?;
+        } else if arg.starts_with("--scale=") {
+            cfg.output_scale = arg.trim_start_matches("--scale=").parse()?;
+        } else if arg == "--text-antialias" {
+            let val = next_value("--text-antialias", &mut iter)?;
+            cfg.text_antialias = parse_antialias(&val)?;
+        } else if arg.starts_with("--text-antialias=") {
+            let val = arg.trim_start_matches("--text-antialias=");
+            cfg.text_antialias = parse_antialias(val)?;
+        } else if arg == "--text-hint" {
+            let val = next_value("--text-hint", &mut iter)?;
+            cfg.text_hint = parse_hint_style(&val)?;
+        } else if arg.starts_with("--text-hint=") {
+            let val = arg.trim_start_matches("--text-hint=");
+            cfg.text_hint = parse_hint_style(val)?;
+        } else if arg == "--text-hint-metrics" {
+            let val = next_value("--text-hint-metrics", &mut iter)?;
+            cfg.text_hint_metrics = parse_hint_metrics(&val)?;
+        } else if arg.starts_with("--text-hint-metrics=") {
+            let val = arg.trim_start_matches("--text-hint-metrics=");
+            cfg.text_hint_metrics = parse_hint_metrics(val)?;
+        } else if arg == "--subpixel-order" {
+            let val = next_value("--subpixel-order", &mut iter)?;
+            cfg.subpixel_order = parse_subpixel_order(&val)?;
+        } else if arg.starts_with("--subpixel-order=") {
+            let val = arg.trim_start_matches("--subpixel-order=");
+            cfg.subpixel_order = parse_subpixel_order(val)?;
+        } else if arg == "--ellipsis" {
+            cfg.ellipsis = next_value("--ellipsis", &mut iter)?;
+        } else if arg.starts_with("--ellipsis=") {
+            cfg.ellipsis = arg.trim_start_matches("--ellipsis=").to_string();
+        } else if arg == "--ellipsize" {
+            let val = next_value("--ellipsize", &mut iter)?;
+            cfg.ellipsize = parse_ellipsize(&val)?;
+        } else if arg.starts_with("--ellipsize=") {
+            cfg.ellipsize = parse_ellipsize(arg.trim_start_matches("--ellipsize="))?;
+        } else if arg == "--wrap" {
+            let val = next_value("--wrap", &mut iter)?;
+            cfg.wrap = parse_wrap(&val)?;
+        } else if arg.starts_with("--wrap=") {
+            cfg.wrap = parse_wrap(arg.trim_start_matches("--wrap="))?;
+        } else if arg == "--max-lines" {
+            let val = next_value("--max-lines", &mut iter)?;
+            cfg.max_lines = val.parse()?;
+        } else if arg.starts_with("--max-lines=") {
+            cfg.max_lines = arg.trim_start_matches("--max-lines=").parse()?;
+        } else if arg == "--default-offset" {
+            let val = next_value("--default-offset", &mut iter)?;
+            cfg.default_offset = val.parse()?;
+        } else if arg.starts_with("--default-offset=") {
+            cfg.default_offset = arg.trim_start_matches("--default-offset=").parse()?;
+        } else if arg == "--offset" {
+            let val = next_value("--offset", &mut iter)?;
+            cfg.offset = val.parse()?;
+        } else if arg.starts_with("--offset=") {
+            cfg.offset = arg.trim_start_matches("--offset=").parse()?;
+        } else if arg == "--stack-gap" {
+            let val = next_value("--stack-gap", &mut iter)?;
+            cfg.stack_gap = val.parse()?;
+        } else if arg.starts_with("--stack-gap=") {
+            cfg.stack_gap = arg.trim_start_matches("--stack-gap=").parse()?;
+        } else if arg == "--slot-height" {
+            let val = next_value("--slot-height", &mut iter)?;
+            cfg.slot_height = val.parse()?;
+        } else if arg.starts_with("--slot-height=") {
+            cfg.slot_height = arg.trim_start_matches("--slot-height=").parse()?;
+        } else if arg == "--priority" {
+            let val = next_value("--priority", &mut iter)?;
+            cfg.priority = val.parse()?;
+        } else if arg.starts_with("--priority=") {
+            cfg.priority = arg.trim_start_matches("--priority=").parse()?;
+        } else if arg == "--max-stack" {
+            let val = next_value("--max-stack", &mut iter)?;
+            cfg.max_stack = val.parse()?;
+        } else if arg.starts_with("--max-stack=") {
+            cfg.max_stack = arg.trim_start_matches("--max-stack=").parse()?;
+        } else if arg == "--on-full" {
+            let val = next_value("--on-full", &mut iter)?;
+            cfg.on_full = parse_on_full(&val)?;
+        } else if arg.starts_with("--on-full=") {
+            cfg.on_full = parse_on_full(arg.trim_start_matches("--on-full="))?;
+        } else if arg == "--stack-order" {
+            let val = next_value("--stack-order", &mut iter)?;
+            cfg.stack_order = parse_stack_order(&val)?;
+        } else if arg.starts_with("--stack-order=") {
+            cfg.stack_order = parse_stack_order(arg.trim_start_matches("--stack-order="))?;
+        } else if arg == "--rate-limit" {
+            let val = next_value("--rate-limit", &mut iter)?;
+            cfg.rate_limit = val.parse()?;
+        } else if arg.starts_with("--rate-limit=") {
+            cfg.rate_limit = arg.trim_start_matches("--rate-limit=").parse()?;
+        } else if arg == "--group-by" {
+            let val = next_value("--group-by", &mut iter)?;
+            cfg.group_by = parse_group_by(&val)?;
+        } else if arg.starts_with("--group-by=") {
+            cfg.group_by = parse_group_by(arg.trim_start_matches("--group-by="))?;
+        } else if arg == "--coalesce" {
+            cfg.coalesce = true;
+        } else if arg == "--no-coalesce" {
+            cfg.coalesce = false;
+        } else if arg == "--stack" {
+            cfg.stack = true;
+        } else if arg == "--no-stack" {
+            cfg.stack = false;
+        } else if arg == "--layer" {
+            let val = next_value("--layer", &mut iter)?;
+            cfg.layer = parse_layer(&val)?;
+        } else if arg.starts_with("--layer=") {
+            cfg.layer = parse_layer(arg.trim_start_matches("--layer="))?;
+        } else if arg == "--namespace" {
+            cfg.namespace = next_value("--namespace", &mut iter)?;
+        } else if arg.starts_with("--namespace=") {
+            cfg.namespace = arg.trim_start_matches("--namespace=").to_string();
+        } else if arg == "--fallback-window" {
+            cfg.fallback_window = true;
+        } else if arg == "--no-fallback-window" {
+            cfg.fallback_window = false;
+        } else if arg == "--all-outputs" {
+            cfg.all_outputs = true;
+        } else if arg == "--no-all-outputs" {
+            cfg.all_outputs = false;
+        } else if arg == "--output" {
+            cfg.output = Some(next_value("--output", &mut iter)?);
+            cfg.output_index = None;
+        } else if arg.starts_with("--output=") {
+            cfg.output = Some(arg.trim_start_matches("--output=").to_string());
+            cfg.output_index = None;
+        } else if arg == "--output-index" {
+            let val = next_value("--output-index", &mut iter)?;
+            cfg.output_index = Some(val.parse()?);
+            cfg.output = None;
+        } else if arg.starts_with("--output-index=") {
+            cfg.output_index = Some(arg.trim_start_matches("--output-index=").parse()?);
+            cfg.output = None;
+        } else if arg == "--keyboard" {
+            let val = next_value("--keyboard", &mut iter)?;
+            cfg.keyboard = parse_keyboard(&val)?;
+        } else if arg.starts_with("--keyboard=") {
+            cfg.keyboard = parse_keyboard(arg.trim_start_matches("--keyboard="))?;
+        } else if arg == "--exclusive-zone" {
+            let val = next_value("--exclusive-zone", &mut iter)?;
+            cfg.exclusive_zone = parse_exclusive_zone(&val)?;
+        } else if arg.starts_with("--exclusive-zone=") {
+            cfg.exclusive_zone = parse_exclusive_zone(arg.trim_start_matches("--exclusive-zone="))?;
+        } else if arg == "--name" {
+            alert_name = Some(next_value("--name", &mut iter)?);
+        } else if arg.starts_with("--name=") {
+            alert_name = Some(arg.trim_start_matches("--name=").to_string());
+        } else if arg == "--urgency" {
+            let val = next_value("--urgency", &mut iter)?;
+            cfg.urgency = parse_urgency(&val)?;
+        } else if arg.starts_with("--urgency=") {
+            cfg.urgency = parse_urgency(arg.trim_start_matches("--urgency="))?;
+        } else if arg == "--class" {
+            alert_class = Some(next_value("--class", &mut iter)?);
+        } else if arg.starts_with("--class=") {
+            alert_class = Some(arg.trim_start_matches("--class=").to_string());
+        } else if arg == "--app-name" {
+            app_name = Some(next_value("--app-name", &mut iter)?);
+        } else if arg.starts_with("--app-name=") {
+            app_name = Some(arg.trim_start_matches("--app-name=").to_string());
+        } else if arg == "--action" {
+            let val = next_value("--action", &mut iter)?;
+            actions.push(parse_action(&val)?);
+        } else if arg.starts_with("--action=") {
+            actions.push(parse_action(arg.trim_start_matches("--action="))?);
+        } else if arg == "--on-right-click" {
+            cfg.on_right_click = Some(next_value("--on-right-click", &mut iter)?);
+        } else if arg.starts_with("--on-right-click=") {
+            cfg.on_right_click = Some(arg.trim_start_matches("--on-right-click=").to_string());
+        } else if arg == "--on-middle-click" {
+            cfg.on_middle_click = Some(next_value("--on-middle-click", &mut iter)?);
+        } else if arg.starts_with("--on-middle-click=") {
+            cfg.on_middle_click = Some(arg.trim_start_matches("--on-middle-click=").to_string());
+        } else if arg == "--keep-open-on-right-click" {
+            cfg.keep_open_on_right_click = true;
+        } else if arg == "--no-keep-open-on-right-click" {
+            cfg.keep_open_on_right_click = false;
+        } else if arg == "--keep-open-on-middle-click" {
+            cfg.keep_open_on_middle_click = true;
+        } else if arg == "--no-keep-open-on-middle-click" {
+            cfg.keep_open_on_middle_click = false;
+        } else if arg == "--sound" {
+            cfg.sound = Some(next_value("--sound", &mut iter)?);
+        } else if arg.starts_with("--sound=") {
+            cfg.sound = Some(arg.trim_start_matches("--sound=").to_string());
+        } else if arg == "--no-sound" {
+            cfg.sound = None;
+        } else if arg == "--bell" {
+            cfg.bell = true;
+        } else if arg == "--no-bell" {
+            cfg.bell = false;
+        } else if arg == "--defer-while-inhibited" {
+            cfg.defer_while_inhibited = true;
+        } else if arg == "--no-defer-while-inhibited" {
+            cfg.defer_while_inhibited = false;
+        } else if arg == "--click-through" {
+            cfg.click_through = true;
+        } else if arg == "--no-click-through" {
+            cfg.click_through = false;
+        } else if arg == "--dismiss-button" {
+            let val = next_value("--dismiss-button", &mut iter)?;
+            cfg.dismiss_button = parse_dismiss_button(&val)?;
+        } else if arg.starts_with("--dismiss-button=") {
+            cfg.dismiss_button = parse_dismiss_button(arg.trim_start_matches("--dismiss-button="))?;
+        } else if arg == "--scroll-dismiss" {
+            cfg.scroll_dismiss = true;
+        } else if arg == "--no-scroll-dismiss" {
+            cfg.scroll_dismiss = false;
+        } else if arg == "--dry-run" {
+            cfg.dry_run = true;
+        } else if arg == "--json-errors" {
+            cfg.json_errors = true;
+        } else if arg == "--no-json-errors" {
+            cfg.json_errors = false;
+        } else if arg == "--print-id" {
+            print_id = true;
+        } else if arg == "--replace-id" {
+            let val = next_value("--replace-id", &mut iter)?;
+            replace_id = Some(val.parse()?);
+        } else if arg.starts_with("--replace-id=") {
+            replace_id = Some(arg.trim_start_matches("--replace-id=").parse()?);
+        } else if arg == "--created-at" {
+            let val = next_value("--created-at", &mut iter)?;
+            created_at = Some(val.parse()?);
+        } else if arg.starts_with("--created-at=") {
+            created_at = Some(arg.trim_start_matches("--created-at=").parse()?);
+        } else if arg == "--show-time" {
+            let val = next_value("--show-time", &mut iter)?;
+            cfg.show_time = parse_show_time(&val)?;
+        } else if arg.starts_with("--show-time=") {
+            cfg.show_time = parse_show_time(arg.trim_start_matches("--show-time="))?;
+        } else if arg == "--state-dir" {
+            state_dir = Some(next_value("--state-dir", &mut iter)?);
+        } else if arg.starts_with("--state-dir=") {
+            state_dir = Some(arg.trim_start_matches("--state-dir=").to_string());
+        } else if arg == "--format" {
+            let val = next_value("--format", &mut iter)?;
+            format = parse_output_format(&val)?;
+        } else if arg.starts_with("--format=") {
+            format = parse_output_format(arg.trim_start_matches("--format="))?;
+        } else if arg == "--socket" {
+            socket = Some(next_value("--socket", &mut iter)?);
+        } else if arg.starts_with("--socket=") {
+            socket = Some(arg.trim_start_matches("--socket=").to_string());
+        } else if arg == "--out" {
+            out = Some(next_value("--out", &mut iter)?);
+        } else if arg.starts_with("--out=") {
+            out = Some(arg.trim_start_matches("--out=").to_string());
+        } else if arg == "--iterations" {
+            let val = next_value("--iterations", &mut iter)?;
+            iterations = val.parse()?;
+        } else if arg.starts_with("--iterations=") {
+            iterations = arg.trim_start_matches("--iterations=").parse()?;
+        } else if arg == "--limit" {
+            let val = next_value("--limit", &mut iter)?;
+            limit = val.parse()?;
+        } else if arg.starts_with("--limit=") {
+            limit = arg.trim_start_matches("--limit=").parse()?;
+        } else if arg == "--history" {
+            cfg.history = true;
+        } else if arg == "--no-history" {
+            cfg.history = false;
+        } else if arg == "--summary-length" {
+            let val = next_value("--summary-length", &mut iter)?;
+            cfg.summary_length = val.parse()?;
+        } else if arg.starts_with("--summary-length=") {
+            cfg.summary_length = arg.trim_start_matches("--summary-length=").parse()?;
+        } else if arg == "--history-max-bytes" {
+            let val = next_value("--history-max-bytes", &mut iter)?;
+            cfg.history_max_bytes = val.parse()?;
+        } else if arg.starts_with("--history-max-bytes=") {
+            cfg.history_max_bytes = arg.trim_start_matches("--history-max-bytes=").parse()?;
+        } else if arg == "--log-level" {
+            let val = next_value("--log-level", &mut iter)?;
+            cfg.log_level = Some(parse_log_level(&val)?);
+        } else if arg.starts_with("--log-level=") {
+            cfg.log_level = Some(parse_log_level(arg.trim_start_matches("--log-level="))?);
+        } else if arg == "--log-file" {
+            cfg.log_file = Some(next_value("--log-file", &mut iter)?);
+        } else if arg.starts_with("--log-file=") {
+            cfg.log_file = Some(arg.trim_start_matches("--log-file=").to_string());
+        } else if arg == "--idle-exit-ms" {
+            let val = next_value("--idle-exit-ms", &mut iter)?;
+            cfg.idle_exit_ms = val.parse()?;
+        } else if arg.starts_with("--idle-exit-ms=") {
+            cfg.idle_exit_ms = arg.trim_start_matches("--idle-exit-ms=").parse()?;
+        } else if arg == "--reposition-interval-ms" {
+            let val = next_value("--reposition-interval-ms", &mut iter)?;
+            cfg.reposition_interval_ms = parse_interval("--reposition-interval-ms", &val)?;
+        } else if arg.starts_with("--reposition-interval-ms=") {
+            let val = arg.trim_start_matches("--reposition-interval-ms=");
+            cfg.reposition_interval_ms = parse_interval("--reposition-interval-ms", val)?;
+        } else if arg == "--poll-interval-ms" {
+            let val = next_value("--poll-interval-ms", &mut iter)?;
+            cfg.poll_interval_ms = parse_interval("--poll-interval-ms", &val)?;
+        } else if arg.starts_with("--poll-interval-ms=") {
+            let val = arg.trim_start_matches("--poll-interval-ms=");
+            cfg.poll_interval_ms = parse_interval("--poll-interval-ms", val)?;
+        } else if arg == "--lock-timeout-ms" {
+            let val = next_value("--lock-timeout-ms", &mut iter)?;
+            cfg.lock_timeout_ms = val.parse()?;
+        } else if arg.starts_with("--lock-timeout-ms=") {
+            cfg.lock_timeout_ms = arg.trim_start_matches("--lock-timeout-ms=").parse()?;
+        } else if arg == "--list-active" {
+            command = Some(Command::ListActive);
+        } else if arg == "--clear-by-name" {
+            let name = next_value("--clear-by-name", &mut iter)?;
+            command = Some(Command::ClearByName(name));
+        } else if arg.starts_with("--clear-by-name=") {
+            command = Some(Command::ClearByName(
+                arg.trim_start_matches("--clear-by-name=").to_string(),
+            ));
+        } else if arg == "--clear-by-class" {
+            let class = next_value("--clear-by-class", &mut iter)?;
+            command = Some(Command::ClearByClass(class));
+        } else if arg.starts_with("--clear-by-class=") {
+            command = Some(Command::ClearByClass(
+                arg.trim_start_matches("--clear-by-class=").to_string(),
+            ));
+        } else if arg == "--clear-by-id" {
+            let id = next_value("--clear-by-id", &mut iter)?;
+            command = Some(Command::ClearById(id.parse()?));
+        } else if arg.starts_with("--clear-by-id=") {
+            let id = arg.trim_start_matches("--clear-by-id=");
+            command = Some(Command::ClearById(id.parse()?));
+        } else if arg == "list" && first_positional {
+            let sub = next_value("list", &mut iter)?;
+            if sub != "active" {
+                return Err(anyhow!("usage: creak list active"));
+            }
+            command = Some(Command::ListActive);
+        } else if arg == "clear" && first_positional {
+            command = Some(parse_clear_command(&mut iter)?);
+        } else if arg == "update" && first_positional {
+            command = Some(parse_update_command(&mut iter)?);
+        } else if arg == "snooze" && first_positional {
+            command = Some(parse_snooze_command(&mut iter)?);
+        } else if arg == "tick" && first_positional {
+            command = Some(Command::Tick);
+        } else if arg == "history" && first_positional {
+            command = Some(Command::History);
+        } else if arg == "dnd" && first_positional {
+            let action = match next_value("dnd", &mut iter)?.as_str() {
+                "on" => DndAction::On,
+                "off" => DndAction::Off,
+                "toggle" => DndAction::Toggle,
+                "status" => DndAction::Status,
+                _ => return Err(anyhow!("usage: creak dnd on|off|toggle|status")),
+            };
+            command = Some(Command::Dnd(action));
+        } else if arg == "--bypass-dnd" {
+            cfg.bypass_dnd = true;
+        } else if arg == "--no-bypass-dnd" {
+            cfg.bypass_dnd = false;
+        } else if arg == "pause" && first_positional {
+            command = Some(Command::Pause);
+        } else if arg == "resume" && first_positional {
+            command = Some(Command::Resume);
+        } else if arg == "batch" && first_positional {
+            command = Some(Command::Batch(next_value("batch", &mut iter)?));
+        } else if arg == "config" && first_positional {
+            if next_value("config", &mut iter)? != "check" {
+                return Err(anyhow!("usage: creak config check [--style <name|path>]"));
+            }
+            command = Some(Command::ConfigCheck(String::new()));
+        } else if arg == "--concurrent" {
+            concurrent = true;
+        } else if arg == "serve" && first_positional {
+            command = Some(Command::Serve);
+        } else if arg == "render"
+            && !(render || measure || bench)
+            && command.is_none()
+            && rest.is_empty()
+        {
+            render = true;
+        } else if arg == "measure"
+            && !(render || measure || bench)
+            && command.is_none()
+            && rest.is_empty()
+        {
+            measure = true;
+        } else if arg == "bench"
+            && !(render || measure || bench)
+            && command.is_none()
+            && rest.is_empty()
+        {
+            bench = true;
+        } else if arg == "--help" || arg == "-h" {
+            command = Some(Command::Help);
+        } else if (arg == "version" && first_positional) || arg == "--version" || arg == "-V" {
+            command = Some(Command::Version);
+        } else if arg.starts_with('-') {
+            return Err(match suggest_option(&arg) {
+                Some(option) => anyhow!("unknown option: {}; did you mean {}?", arg, option),
+                None => anyhow!("unknown option: {}", arg),
+            });
+        } else {
+            rest.push(arg);
+        }
+    }
+
+    let command = if let Some(command) = command {
+        if !rest.is_empty() {
+            return Err(anyhow!(
+                "unexpected positional arguments for control command"
+            ));
+        }
+        command
+    } else {
+        if rest.is_empty() && !bench {
+            return Err(anyhow!("missing message"));
+        }
+        let message = if rest.is_empty() {
+            BENCH_MESSAGE.to_string()
+        } else if rest.len() == 1 {
+            rest[0].clone()
+        } else {
+            let title = &rest[0];
+            let body = rest[1..].join(" ");
+            format!("{}\n{}", title, body)
+        };
+        let alert = AlertArgs {
+            position,
+            message,
+            name: alert_name,
+            class: alert_class,
+            print_id,
+            actions,
+            argv: Vec::new(),
+            created_at,
+            app_name,
+            replace_id,
+        };
+        if render {
+            let out = out.ok_or_else(|| anyhow!("render requires --out <file.png>"))?;
+            Command::Render { alert, out }
+        } else if measure {
+            Command::Measure(alert)
+        } else if bench {
+            if iterations == 0 {
+                return Err(anyhow!("bench needs --iterations of at least 1"));
+            }
+            Command::Bench { alert, iterations }
+        } else {
+            Command::Show(alert)
+        }
+    };
+
+    log_debug!("config: {:?}", cfg);
+    Ok((
+        Args {
+            command,
+            state_dir,
+            format,
+            limit,
+            concurrent,
+            socket,
+        },
+        cfg,
+    ))
+}
+
+fn parse_update_command(
+    iter: &mut std::iter::Peekable<std::vec::IntoIter<String>>,
+) -> Result<Command> {
+    const USAGE: &str = "usage: creak update by <id|name> <value> <message>";
+    if next_value("update", iter)? != "by" {
+        return Err(anyhow!(USAGE));
+    }
+    let key = next_value("update by", iter)?;
+    let value = next_value("update by <key>", iter)?;
+    let message = next_value("update by <key> <value>", iter)?;
+    match key.as_str() {
+        "id" => Ok(Command::UpdateById(value.parse()?, message)),
+        "name" => Ok(Command::UpdateByName(value, message)),
+        _ => Err(anyhow!(USAGE)),
+    }
+}
+
+fn parse_snooze_command(
+    iter: &mut std::iter::Peekable<std::vec::IntoIter<String>>,
+) -> Result<Command> {
+    const USAGE: &str = "usage: creak snooze by <id|name> <value> <ms>";
+    if next_value("snooze", iter)? != "by" {
+        return Err(anyhow!(USAGE));
+    }
+    let key = next_value("snooze by", iter)?;
+    let value = next_value("snooze by <key>", iter)?;
+    let delay_ms = next_value("snooze by <key> <value>", iter)?.parse()?;
+    match key.as_str() {
+        "id" => Ok(Command::SnoozeById(value.parse()?, delay_ms)),
+        "name" => Ok(Command::SnoozeByName(value, delay_ms)),
+        _ => Err(anyhow!(USAGE)),
+    }
+}
+
+fn parse_clear_command(
+    iter: &mut std::iter::Peekable<std::vec::IntoIter<String>>,
+) -> Result<Command> {
+    let by = next_value("clear", iter)?;
+    if by != "by" {
+        return Err(anyhow!("usage: creak clear by <name|class|id> <value>"));
+    }
+    let key = next_value("clear by", iter)?;
+    let value = next_value("clear by <key>", iter)?;
+    match key.as_str() {
+        "name" => Ok(Command::ClearByName(value)),
+        "class" => Ok(Command::ClearByClass(value)),
+        "id" => Ok(Command::ClearById(value.parse()?)),
+        _ => Err(anyhow!("usage: creak clear by <name|class|id> <value>")),
+    }
+}
+
+fn dispatch_with_timeout(
+    event_queue: &mut wayland_client::EventQueue<State>,
+    state: &mut State,
+    timeout_ms: i32,
+    watch: Option<&StateWatch>,
+) -> Result<bool> {
+    if let Some(guard) = event_queue.prepare_read() {
+        let fd = guard.connection_fd().as_raw_fd();
+        // poll skips negative fds, so the watch slot is inert without one.
+        let mut pollfds = [
+            libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: watch.map(StateWatch::raw_fd).unwrap_or(-1),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        let rc = unsafe { libc::poll(pollfds.as_mut_ptr(), 2, timeout_ms) };
+        // A hangup still goes through read() so it surfaces as an error.
+        if rc > 0 && (pollfds[0].revents & (libc::POLLIN | libc::POLLHUP)) != 0 {
+            if let Err(err) = guard.read() {
+                match err {
+                    WaylandError::Io(io_err) if io_err.kind() == ErrorKind::WouldBlock => {}
+                    other => return Err(other).context("wayland read error"),
+                }
+            }
+        }
+    }
+    event_queue.dispatch_pending(state)?;
+    Ok(watch.map(StateWatch::changed).unwrap_or(false))
+}
+
+/// `--config-dir`, else `$CREAK_CONFIG_DIR`, else `$XDG_CONFIG_HOME/creak`.
+/// `var` reads the environment.
+fn config_dir(flag: Option<&str>, var: impl Fn(&str) -> Option<String>) -> String {
+    if let Some(dir) = flag {
+        return dir.to_string();
+    }
+    if let Some(dir) = var("CREAK_CONFIG_DIR").filter(|dir| !dir.is_empty()) {
+        return dir;
+    }
+    let xdg_config = var("XDG_CONFIG_HOME")
+        .unwrap_or_else(|| format!("{}/.config", var("HOME").unwrap_or_default()));
+    format!("{}/creak", xdg_config)
+}
+
+fn load_config_args(dir: &str, style: Option<&str>) -> Result<Vec<String>> {
+    let path = config_path_for_style(dir, style);
+    log_debug!("config path: {}", path);
+    load_config_file(&path)
+}
+
+fn load_config_file(path: &str) -> Result<Vec<String>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut lines = Vec::new();
+    config_lines(path, &contents, &mut lines);
+    let mut args = Vec::new();
+    for line in lines {
+        args.extend(line?.tokens);
+    }
+    Ok(args)
+}
+
+/// One config line's tokens; `at` is `file:line` for error messages.
+struct ConfigLine {
+    at: String,
+    tokens: Vec<String>,
+}
+
+/// Splits config lines into tokens, splicing in `include <path>` files.
+/// Errors are collected in place rather than returned, so `config check` can
+/// report all of them.
+fn config_lines(path: &str, contents: &str, out: &mut Vec<Result<ConfigLine>>) {
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    collect_config_lines(Path::new(path), contents, &mut vec![canonical], out);
+}
+
+/// `including` is the chain of files being read, for cycle detection.
+fn collect_config_lines(
+    path: &Path,
+    contents: &str,
+    including: &mut Vec<PathBuf>,
+    out: &mut Vec<Result<ConfigLine>>,
+) {
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let at = format!("{}:{}", path.display(), index + 1);
+        let parts = match shell_words::split(line) {
+            Ok(parts) => parts,
+            Err(err) => {
+                out.push(Err(anyhow!("{}: {}", at, err)));
+                continue;
+            }
+        };
+        match parts.as_slice() {
+            [directive, target] if directive == "include" => {
+                let target = path.parent().unwrap_or(Path::new(".")).join(target);
+                let read = fs::canonicalize(&target).and_then(|canonical| {
+                    fs::read_to_string(&canonical).map(|contents| (canonical, contents))
+                });
+                match read {
+                    Err(err) => {
+                        out.push(Err(anyhow!(
+                            "{}: include {}: {}",
+                            at,
+                            target.display(),
+                            err
+                        )));
+                    }
+                    Ok((canonical, _)) if including.contains(&canonical) => {
+                        out.push(Err(anyhow!(
+                            "{}: include cycle at {}",
+                            at,
+                            target.display()
+                        )));
+                    }
+                    Ok((canonical, contents)) => {
+                        including.push(canonical);
+                        collect_config_lines(&target, &contents, including, out);
+                        including.pop();
+                    }
+                }
+            }
+            [directive, ..] if directive == "include" => {
+                out.push(Err(anyhow!("{}: include takes one path", at)));
+            }
+            _ => out.push(Ok(ConfigLine { at, tokens: parts })),
+        }
+    }
+}
+
+/// Every problem in the config at `path` and its includes, one per line:
+/// unreadable files, bad quoting and options `parse_tokens` rejects.
+fn check_config(path: &str) -> Vec<String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => return vec![format!("{}: {}", path, err)],
+    };
+    let mut lines = Vec::new();
+    config_lines(path, &contents, &mut lines);
+    let mut problems = Vec::new();
+    for line in lines {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                problems.push(format!("{:#}", err));
+                continue;
+            }
+        };
+        // A placeholder title, since a config line alone is not an alert.
+        let mut tokens = line.tokens.clone();
+        tokens.push("check".to_string());
+        if let Err(err) = parse_tokens(tokens, default_config()) {
+            problems.push(format!("{}: {}: {:#}", line.at, line.tokens.join(" "), err));
+        }
+    }
+    problems
+}
+
+fn config_path_for_style(config_dir: &str, style: Option<&str>) -> String {
+    match style {
+        Some(value) if value.contains('/') => value.to_string(),
+        Some(value) => format!("{}/{}", config_dir, value),
+        None => format!("{}/config", config_dir),
+    }
+}
+
+/// `--profile <name>`: `profiles/<name>` in the config dir (or a path, like
+/// `--style`). Unlike a style, a missing profile is an error.
+fn load_profile(config_dir: &str, name: &str) -> Result<Vec<String>> {
+    let path = if name.contains('/') {
+        name.to_string()
+    } else {
+        format!("{}/profiles/{}", config_dir, name)
+    };
+    log_debug!("profile path: {}", path);
+    if !Path::new(&path).is_file() {
+        return Err(anyhow!("profile not found: {}", path));
+    }
+    load_config_file(&path)
+}
+
+/// `class.<class>` next to the base config; `None` for names that would
+/// leave the config dir.
+fn config_path_for_class(config_dir: &str, class: &str) -> Option<String> {
+    if class.is_empty() || class.contains('/') || class.starts_with('.') {
+        return None;
+    }
+    Some(format!("{}/class.{}", config_dir, class))
+}
+
+/// The value `parse_tokens` will end up with for option `name` (the last one).
+fn last_arg<'a>(tokens: impl Iterator<Item = &'a String>, name: &str) -> Option<String> {
+    let mut value = None;
+    let mut tokens = tokens.peekable();
+    while let Some(arg) = tokens.next() {
+        if arg == name {
+            value = tokens.next().cloned();
+        } else if let Some(v) = arg
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            value = Some(v.to_string());
+        }
+    }
+    value
+}
+
+/// Built-in options per urgency, applied before `urgency.<level>` in the
+/// config dir. Critical alerts stay until dismissed, like freedesktop servers.
+fn urgency_defaults(urgency: Urgency) -> &'static [&'static str] {
+    match urgency {
+        Urgency::Low | Urgency::Normal => &[],
+        Urgency::Critical => &[
+            "--timeout",
+            "0",
+            "--allow-persistent",
+            "--border",
+            "#ff4040",
+        ],
+    }
+}
+
+fn urgency_key(urgency: Urgency) -> &'static str {
+    match urgency {
+        Urgency::Low => "low",
+        Urgency::Normal => "normal",
+        Urgency::Critical => "critical",
+    }
+}
+
+/// The known option closest to a mistyped `arg`, if any is close enough.
+fn suggest_option(arg: &str) -> Option<&'static str> {
+    let name = arg.split_once('=').map_or(arg, |(name, _)| name);
+    let limit = (name.len() / 5).max(2);
+    OPTIONS
+        .iter()
+        .map(|option| (edit_distance(name, option), *option))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, option)| option)
+}
+
+/// Edit distance over chars where swapping two neighbours counts as one
+/// edit (optimal string alignment), so `--widht` finds `--width`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+fn next_value(
+    name: &str,
+    iter: &mut std::iter::Peekable<std::vec::IntoIter<String>>,
+) -> Result<String> {
+    iter.next()
+        .ok_or_else(|| anyhow!("{} requires a value", name))
+}
+
+fn default_config() -> Config {
+    Config {
+        font: "SimSun 25".to_string(),
+        font_scale: 1.0,
+        fallback_fonts: Vec::new(),
+        line_spacing: 1.0,
+        letter_spacing: 0,
+        justify: false,
+        strip_markup: false,
+        template: false,
+        template_strict: false,
+        width: 350,
+        width_percent: None,
+        min_width: 0,
+        fit_width: false,
+        padding: Margins {
+            top: 10,
+            right: 10,
+            bottom: 10,
+            left: 10,
+        },
+        border_size: Margins {
+            top: 5,
+            right: 5,
+            bottom: 5,
+            left: 5,
+        },
+        border_radius: 10,
+        corner_smoothing: 0.0,
+        tail: None,
+        show_time: None,
+        urgency: Urgency::Normal,
+        timeout_ms: 5000,
+        min_timeout_ms: 0,
+        max_timeout_ms: 0,
+        allow_persistent: false,
+        min_duration_ms: 0,
+        double_buffer: true,
+        bypass_dnd: false,
+        on_right_click: None,
+        on_middle_click: None,
+        keep_open_on_right_click: false,
+        keep_open_on_middle_click: false,
+        scroll_dismiss: false,
+        dismiss_button: Some(DismissButton::Any),
+        click_through: false,
+        sound: None,
+        bell: false,
+        idle_exit_ms: 0,
+        defer_while_inhibited: false,
+        timeout_bar: false,
+        timeout_bar_color: None,
+        background: [0.1, 0.1, 0.1, 1.0],
+        background_gradient: Vec::new(),
+        background_image: None,
+        gradient_angle: 90.0,
+        opacity: 1.0,
+        text: [1.0, 1.0, 1.0, 1.0],
+        text_auto: false,
+        text_outline: 0,
+        text_outline_color: [0.0, 0.0, 0.0, 1.0],
+        border: [1.0, 1.0, 1.0, 1.0],
+        separator: false,
+        separator_color: None,
+        blink: false,
+        blink_color: [1.0, 0.25, 0.25, 1.0],
+        edge: 20,
+        default_offset: 250,
+        offset: 0,
+        stack_gap: 10,
+        slot_height: 0,
+        stack_order: StackOrder::Fifo,
+        priority: 0,
+        max_stack: 0,
+        on_full: OnFull::Drop,
+        stack: true,
+        rate_limit: 0,
+        coalesce: false,
+        group_by: None,
+        layer: zwlr_layer_shell_v1::Layer::Overlay,
+        namespace: "creak".to_string(),
+        keyboard: zwlr_layer_surface_v1::KeyboardInteractivity::None,
+        exclusive_zone: Some(0),
+        fallback_window: false,
+        all_outputs: false,
+        output: None,
+        output_index: None,
+        output_scale: 0,
+        text_antialias: None,
+        text_hint: None,
+        text_hint_metrics: None,
+        subpixel_order: None,
+        ellipsize: pango::EllipsizeMode::None,
+        wrap: Some(pango::WrapMode::WordChar),
+        max_lines: 0,
+        history: false,
+        history_max_bytes: 1024 * 1024,
+        summary_length: 120,
+        ellipsis: "…".to_string(),
+        lock_timeout_ms: DEFAULT_LOCK_TIMEOUT_MS,
+        reposition_interval_ms: 100,
+        poll_interval_ms: 10,
+        log_level: None,
+        log_file: None,
+        json_errors: false,
+        dry_run: false,
+    }
+}
+
+impl Config {
+    /// Distance from each surface edge to the text area. A `--text-outline`
+    /// reaches past the glyphs, so it gets room too.
+    fn insets(&self) -> Margins {
+        let outline = self.text_outline.max(0);
+        Margins {
+            top: self.padding.top + self.border_size.top + outline,
+            right: self.padding.right + self.border_size.right + outline,
+            bottom: self.padding.bottom
+                + self.border_size.bottom
+                + self.timeout_bar_height()
+                + outline,
+            left: self.padding.left + self.border_size.left + outline,
+        }
+    }
+
+    /// `timeout_ms` after `--min-timeout`/`--max-timeout`. A persistent `0`
+    /// only survives a cap with `--allow-persistent`.
+    fn effective_timeout_ms(&self) -> u64 {
+        let max = self.max_timeout_ms;
+        if self.timeout_ms == 0 {
+            return if max == 0 || self.allow_persistent {
+                0
+            } else {
+                max
+            };
+        }
+        let timeout = self.timeout_ms.max(self.min_timeout_ms);
+        if max == 0 {
+            timeout
+        } else {
+            timeout.min(max)
+        }
+    }
+
+    fn timeout_bar_height(&self) -> i32 {
+        if self.timeout_bar && self.timeout_ms > 0 {
+            TIMEOUT_BAR_HEIGHT
+        } else {
+            0
+        }
+    }
+}
+
+/// `350` or `40%`; a percent also yields its number as the pixel fallback.
+fn parse_width(value: &str) -> Result<(i32, Option<f64>)> {
+    match value.strip_suffix('%') {
+        Some(percent) => {
+            let percent: f64 = percent.trim().parse()?;
+            if !(percent > 0.0 && percent <= 100.0) {
+                return Err(anyhow!("invalid --width: {}", value));
+            }
+            Ok((percent.round() as i32, Some(percent)))
+        }
+        None => Ok((value.parse()?, None)),
+    }
+}
+
+fn percent_of(percent: f64, output_width: i32) -> i32 {
+    ((output_width as f64 * percent / 100.0).round() as i32).max(1)
+}
+
+fn parse_sides(name: &str, value: &str) -> Result<Margins> {
+    let parts = value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<i32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| anyhow!("invalid {}: {}", name, value))?;
+    let (top, right, bottom, left) = match parts.as_slice() {
+        [all] => (*all, *all, *all, *all),
+        [v, h] => (*v, *h, *v, *h),
+        [t, h, b] => (*t, *h, *b, *h),
+        [t, r, b, l] => (*t, *r, *b, *l),
+        _ => return Err(anyhow!("invalid {}: {}", name, value)),
+    };
+    Ok(Margins {
+        top,
+        right,
+        bottom,
+        left,
+    })
+}
+
+fn set_text_color(cfg: &mut Config, value: &str) -> Result<()> {
+    cfg.text_auto = value == "auto";
+    if !cfg.text_auto {
+        cfg.text = parse_color(value).ok_or_else(|| anyhow!("invalid color for --text"))?;
+    }
+    Ok(())
+}
+
+/// With `--text auto` the color follows the background's relative luminance.
+/// A gradient is judged by its first stop; a background image is not sampled,
+/// so the background color behind it decides.
+fn text_color(cfg: &Config) -> [f64; 4] {
+    if !cfg.text_auto {
+        return cfg.text;
+    }
+    let bg = cfg
+        .background_gradient
+        .first()
+        .copied()
+        .unwrap_or(cfg.background);
+    let linear = |c: f64| {
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let luminance = 0.2126 * linear(bg[0]) + 0.7152 * linear(bg[1]) + 0.0722 * linear(bg[2]);
+    // Crossover where contrast against black equals contrast against white.
+    if luminance > 0.179 {
+        [0.0, 0.0, 0.0, 1.0]
+    } else {
+        [1.0, 1.0, 1.0, 1.0]
+    }
+}
+
+/// Hex, or CSS-style `rgb()`/`rgba()`/`hsl()`/`hsla()`: channels 0-255 or
+/// percentages, hue in degrees, saturation and lightness as percentages, and
+/// alpha 0-1 or a percentage. Hex takes an alpha suffix too: `#222222/50%`
+/// or `#222222@0.5`.
+fn parse_color(value: &str) -> Option<[f64; 4]> {
+    let value = value.trim();
+    let Some((function, rest)) = value.split_once('(') else {
+        return match value.split_once(['/', '@']) {
+            Some((hex, alpha)) => {
+                let [r, g, b, _] = parse_hex_color(hex.trim())?;
+                Some([r, g, b, parse_unit(alpha.trim(), 1.0)?])
+            }
+            None => parse_hex_color(value),
+        };
+    };
+    let parts: Vec<&str> = rest.strip_suffix(')')?.split(',').map(str::trim).collect();
+    let alpha = match parts.len() {
+        3 => 1.0,
+        4 => parse_unit(parts[3], 1.0)?,
+        _ => return None,
+    };
+    let [r, g, b] = match function.trim().to_ascii_lowercase().as_str() {
+        "rgb" | "rgba" => [
+            parse_unit(parts[0], 255.0)?,
+            parse_unit(parts[1], 255.0)?,
+            parse_unit(parts[2], 255.0)?,
+        ],
+        "hsl" | "hsla" => {
+            let hue = parts[0]
+                .trim_end_matches("deg")
+                .parse::<f64>()
+                .ok()
+                .filter(|hue| hue.is_finite())?;
+            let percent = |part: &str| part.ends_with('%').then(|| parse_unit(part, 1.0))?;
+            hsl_to_rgb(hue, percent(parts[1])?, percent(parts[2])?)
+        }
+        _ => return None,
+    };
+    Some([r, g, b, alpha])
+}
+
+/// `n%`, or a plain number out of `max`, as a fraction in 0..=1.
+fn parse_unit(value: &str, max: f64) -> Option<f64> {
+    let fraction = match value.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().ok()? / 100.0,
+        None => value.parse::<f64>().ok()? / max,
+    };
+    (0.0..=1.0).contains(&fraction).then_some(fraction)
+}
+
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> [f64; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    [r + m, g + m, b + m]
+}
+
+/// Gradient stops are separated by spaces or commas, except for the commas
+/// inside `rgb(...)` and friends.
+fn split_colors(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in value.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if depth == 0 && (c.is_whitespace() || c == ',') => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn parse_hex_color(value: &str) -> Option<[f64; 4]> {
+    let hex = value.trim_start_matches('#');
+    // Also keeps the byte slicing below on char boundaries.
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let (r, g, b, a) = match hex.len() {
+        3 | 4 => {
+            let nibble = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok().map(|v| v * 17);
+            let a = if hex.len() == 4 { nibble(3)? } else { 255 };
+            (nibble(0)?, nibble(1)?, nibble(2)?, a)
+        }
+        6 => {
+            let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
+            let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
+            let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
+            (r, g, b, 255)
+        }
+        8 => {
+            let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
+            let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
+            let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
+            let a = u8::from_str_radix(&hex[6..8], 16).ok()?;
+            (r, g, b, a)
+        }
+        _ => return None,
+    };
+
+    Some([
+        r as f64 / 255.0,
+        g as f64 / 255.0,
+        b as f64 / 255.0,
+        a as f64 / 255.0,
+    ])
+}
+
+fn parse_gradient(value: &str) -> Result<Vec<[f64; 4]>> {
+    let stops = split_colors(value)
+        .into_iter()
+        .map(|part| {
+            parse_color(part)
+                .ok_or_else(|| anyhow!("invalid color for --background-gradient: {}", part))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if stops.len() < 2 {
+        return Err(anyhow!("--background-gradient needs at least two colors"));
+    }
+    Ok(stops)
+}
+
+fn parse_corner_smoothing(value: &str) -> Result<f64> {
+    match value.parse::<f64>() {
+        Ok(smoothing) if (0.0..=1.0).contains(&smoothing) => Ok(smoothing),
+        _ => Err(anyhow!("invalid --corner-smoothing: {}", value)),
+    }
+}
+
+fn parse_font_scale(value: &str) -> Result<f64> {
+    match value.parse::<f64>() {
+        Ok(scale) if scale.is_finite() && scale > 0.0 => Ok(scale),
+        _ => Err(anyhow!("invalid --font-scale: {}", value)),
+    }
+}
+
+fn parse_interval(name: &str, value: &str) -> Result<u64> {
+    match value.parse::<u64>() {
+        Ok(ms) if ms > 0 => Ok(ms),
+        _ => Err(anyhow!(
+            "invalid {}: {} (expected milliseconds > 0)",
+            name,
+            value
+        )),
+    }
+}
+
+fn parse_opacity(value: &str) -> Result<f64> {
+    match value.parse::<f64>() {
+        Ok(opacity) if (0.0..=1.0).contains(&opacity) => Ok(opacity),
+        _ => Err(anyhow!("invalid --opacity: {}", value)),
+    }
+}
+
+fn parse_antialias(value: &str) -> Result<Option<Antialias>> {
+    match value {
+        "default" => Ok(None),
+        "none" => Ok(Some(Antialias::None)),
+        "gray" => Ok(Some(Antialias::Gray)),
+        "subpixel" => Ok(Some(Antialias::Subpixel)),
+        _ => Err(anyhow!("invalid --text-antialias: {}", value)),
+    }
+}
+
+fn parse_hint_style(value: &str) -> Result<Option<HintStyle>> {
+    match value {
+        "default" => Ok(None),
+        "none" => Ok(Some(HintStyle::None)),
+        "slight" => Ok(Some(HintStyle::Slight)),
+        "medium" => Ok(Some(HintStyle::Medium)),
+        "full" => Ok(Some(HintStyle::Full)),
+        _ => Err(anyhow!("invalid --text-hint: {}", value)),
+    }
+}
+
+fn parse_hint_metrics(value: &str) -> Result<Option<HintMetrics>> {
+    match value {
+        "default" => Ok(None),
+        "on" => Ok(Some(HintMetrics::On)),
+        "off" => Ok(Some(HintMetrics::Off)),
+        _ => Err(anyhow!("invalid --text-hint-metrics: {}", value)),
+    }
+}
+
+fn parse_subpixel_order(value: &str) -> Result<Option<SubpixelOrder>> {
+    match value {
+        "none" => Ok(None),
+        "rgb" => Ok(Some(SubpixelOrder::Rgb)),
+        "bgr" => Ok(Some(SubpixelOrder::Bgr)),
+        "vrgb" => Ok(Some(SubpixelOrder::Vrgb)),
+        "vbgr" => Ok(Some(SubpixelOrder::Vbgr)),
+        _ => Err(anyhow!("invalid --subpixel-order: {}", value)),
+    }
+}
+
+fn parse_ellipsize(value: &str) -> Result<pango::EllipsizeMode> {
+    match value {
+        "none" => Ok(pango::EllipsizeMode::None),
+        "start" => Ok(pango::EllipsizeMode::Start),
+        "middle" => Ok(pango::EllipsizeMode::Middle),
+        "end" => Ok(pango::EllipsizeMode::End),
+        _ => Err(anyhow!("invalid --ellipsize: {}", value)),
+    }
+}
+
+fn parse_tail(value: &str) -> Result<Option<Tail>> {
+    if value == "none" {
+        return Ok(None);
+    }
+    let invalid = || {
+        anyhow!(
+            "invalid --tail: {} (expected <edge>:<offset> or none)",
+            value
+        )
+    };
+    let (edge, offset) = value.split_once(':').ok_or_else(invalid)?;
+    let edge = match edge {
+        "top" => TailEdge::Top,
+        "right" => TailEdge::Right,
+        "bottom" => TailEdge::Bottom,
+        "left" => TailEdge::Left,
+        _ => return Err(invalid()),
+    };
+    let offset = offset.parse().map_err(|_| invalid())?;
+    Ok(Some(Tail { edge, offset }))
+}
+
+fn parse_urgency(value: &str) -> Result<Urgency> {
+    match value {
+        "low" => Ok(Urgency::Low),
+        "normal" => Ok(Urgency::Normal),
+        "critical" => Ok(Urgency::Critical),
+        _ => Err(anyhow!("invalid --urgency: {}", value)),
+    }
+}
+
+fn parse_show_time(value: &str) -> Result<Option<ShowTime>> {
+    match value {
+        "absolute" => Ok(Some(ShowTime::Absolute)),
+        "relative" => Ok(Some(ShowTime::Relative)),
+        "none" => Ok(None),
+        _ => Err(anyhow!(
+            "invalid --show-time: {} (expected absolute, relative or none)",
+            value
+        )),
+    }
+}
+
+fn parse_dismiss_button(value: &str) -> Result<Option<DismissButton>> {
+    match value {
+        "any" => Ok(Some(DismissButton::Any)),
+        "left" => Ok(Some(DismissButton::Left)),
+        "right" => Ok(Some(DismissButton::Right)),
+        "middle" => Ok(Some(DismissButton::Middle)),
+        "none" => Ok(None),
+        _ => Err(anyhow!(
+            "invalid --dismiss-button: {} (expected left, right, middle, any or none)",
+            value
+        )),
+    }
+}
+
+fn parse_on_full(value: &str) -> Result<OnFull> {
+    match value {
+        "drop" => Ok(OnFull::Drop),
+        "expire-oldest" => Ok(OnFull::ExpireOldest),
+        _ => Err(anyhow!(
+            "invalid --on-full: {} (expected drop or expire-oldest)",
+            value
+        )),
+    }
+}
+
+fn parse_stack_order(value: &str) -> Result<StackOrder> {
+    match value {
+        "fifo" => Ok(StackOrder::Fifo),
+        "lifo" => Ok(StackOrder::Lifo),
+        _ => Err(anyhow!(
+            "invalid --stack-order: {} (expected fifo or lifo)",
+            value
+        )),
+    }
+}
+
+fn parse_group_by(value: &str) -> Result<Option<GroupBy>> {
+    match value {
+        "class" => Ok(Some(GroupBy::Class)),
+        "name" => Ok(Some(GroupBy::Name)),
+        "none" => Ok(None),
+        _ => Err(anyhow!(
+            "invalid --group-by: {} (expected class, name or none)",
+            value
+        )),
+    }
+}
+
+fn parse_wrap(value: &str) -> Result<Option<pango::WrapMode>> {
+    match value {
+        "word" => Ok(Some(pango::WrapMode::Word)),
+        "char" => Ok(Some(pango::WrapMode::Char)),
+        "word-char" => Ok(Some(pango::WrapMode::WordChar)),
+        "none" => Ok(None),
+        _ => Err(anyhow!(
+            "invalid --wrap: {} (expected word, char, word-char or none)",
+            value
+        )),
+    }
+}
+
+fn parse_action(value: &str) -> Result<AlertAction> {
+    let (id, label) = value.split_once(':').unwrap_or((value, value));
+    if id.is_empty() {
+        return Err(anyhow!("invalid --action: {}", value));
+    }
+    let label = if label.is_empty() { id } else { label };
+    Ok(AlertAction {
+        id: id.to_string(),
+        label: label.to_string(),
+    })
+}
+
+fn parse_layer(value: &str) -> Result<zwlr_layer_shell_v1::Layer> {
+    match value {
+        "overlay" => Ok(zwlr_layer_shell_v1::Layer::Overlay),
+        "top" => Ok(zwlr_layer_shell_v1::Layer::Top),
+        "bottom" => Ok(zwlr_layer_shell_v1::Layer::Bottom),
+        "background" => Ok(zwlr_layer_shell_v1::Layer::Background),
+        _ => Err(anyhow!("invalid --layer: {}", value)),
+    }
+}
+
+fn parse_exclusive_zone(value: &str) -> Result<Option<i32>> {
+    if value == "auto" {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| anyhow!("invalid --exclusive-zone: {}", value))
+}
+
+fn parse_keyboard(value: &str) -> Result<zwlr_layer_surface_v1::KeyboardInteractivity> {
+    match value {
+        "none" => Ok(zwlr_layer_surface_v1::KeyboardInteractivity::None),
+        "exclusive" => Ok(zwlr_layer_surface_v1::KeyboardInteractivity::Exclusive),
+        "on-demand" => Ok(zwlr_layer_surface_v1::KeyboardInteractivity::OnDemand),
+        _ => Err(anyhow!("invalid --keyboard: {}", value)),
+    }
+}
+
+fn parse_log_level(value: &str) -> Result<LogLevel> {
+    match value {
+        "off" => Ok(LogLevel::Off),
+        "error" => Ok(LogLevel::Error),
+        "warn" => Ok(LogLevel::Warn),
+        "info" => Ok(LogLevel::Info),
+        "debug" => Ok(LogLevel::Debug),
+        _ => Err(anyhow!("invalid --log-level: {}", value)),
+    }
+}
+
+fn parse_output_format(value: &str) -> Result<OutputFormat> {
+    match value {
+        "json" => Ok(OutputFormat::Json),
+        "ndjson" => Ok(OutputFormat::Ndjson),
+        _ => Err(anyhow!("invalid --format: {}", value)),
+    }
+}
+
+fn position_to_anchor(
+    cfg: &Config,
+    position: Position,
+) -> (zwlr_layer_surface_v1::Anchor, Margins) {
+    let edge = cfg.edge;
+    let default_offset = cfg.default_offset;
+
+    let (anchor, mut margins) = match position {
+        Position::TopLeft => (
+            zwlr_layer_surface_v1::Anchor::Top | zwlr_layer_surface_v1::Anchor::Left,
+            Margins {
+                top: edge,
+                left: edge,
+                ..Margins::default()
+            },
+        ),
+        Position::Top => (
+            zwlr_layer_surface_v1::Anchor::Top,
+            Margins {
+                top: edge,
+                ..Margins::default()
+            },
+        ),
+        Position::TopRight => (
+            zwlr_layer_surface_v1::Anchor::Top | zwlr_layer_surface_v1::Anchor::Right,
+            Margins {
+                top: edge,
+                right: edge,
+                ..Margins::default()
+            },
+        ),
+        Position::Left => (
+            zwlr_layer_surface_v1::Anchor::Left,
+            Margins {
+                left: edge,
+                ..Margins::default()
+            },
+        ),
+        Position::Center => (zwlr_layer_surface_v1::Anchor::empty(), Margins::default()),
+        Position::Right => (
+            zwlr_layer_surface_v1::Anchor::Right,
+            Margins {
+                right: edge,
+                ..Margins::default()
+            },
+        ),
+        Position::BottomLeft => (
+            zwlr_layer_surface_v1::Anchor::Bottom | zwlr_layer_surface_v1::Anchor::Left,
+            Margins {
+                bottom: edge,
+                left: edge,
+                ..Margins::default()
+            },
+        ),
+        Position::Bottom => (
+            zwlr_layer_surface_v1::Anchor::Bottom,
+            Margins {
+                bottom: edge,
+                ..Margins::default()
+            },
+        ),
+        Position::BottomRight => (
+            zwlr_layer_surface_v1::Anchor::Bottom | zwlr_layer_surface_v1::Anchor::Right,
+            Margins {
+                bottom: edge,
+                right: edge,
+                ..Margins::default()
+            },
+        ),
+        Position::Default | Position::Cursor => (
+            zwlr_layer_surface_v1::Anchor::Top,
+            Margins {
+                top: default_offset,
+                ..Margins::default()
+            },
+        ),
+    };
+    // `--offset` moves away from the edge the stack grows from; alerts only
+    // anchored left or right move sideways, and centered ones stay put.
+    if anchor.contains(zwlr_layer_surface_v1::Anchor::Top) {
+        margins.top += cfg.offset;
+    } else if anchor.contains(zwlr_layer_surface_v1::Anchor::Bottom) {
+        margins.bottom += cfg.offset;
+    } else if anchor.contains(zwlr_layer_surface_v1::Anchor::Left) {
+        margins.left += cfg.offset;
+    } else if anchor.contains(zwlr_layer_surface_v1::Anchor::Right) {
+        margins.right += cfg.offset;
+    }
+    (anchor, margins)
+}
+
+/// Anchor to the output corner nearest the pointer so the alert opens toward
+/// the middle of the screen, `edge` pixels away from the pointer.
+fn cursor_anchor(
+    cfg: &Config,
+    probe: PointerProbe,
+    width: i32,
+    height: i32,
+) -> (zwlr_layer_surface_v1::Anchor, Margins) {
+    let x = probe.x.round() as i32;
+    let y = probe.y.round() as i32;
+    let max_x = (probe.output_width - width).max(0);
+    let max_y = (probe.output_height - height).max(0);
+    let mut margins = Margins::default();
+    let horizontal = if x < probe.output_width / 2 {
+        margins.left = (x + cfg.edge).clamp(0, max_x);
+        zwlr_layer_surface_v1::Anchor::Left
+    } else {
+        margins.right = (probe.output_width - x + cfg.edge).clamp(0, max_x);
+        zwlr_layer_surface_v1::Anchor::Right
+    };
+    let vertical = if y < probe.output_height / 2 {
+        margins.top = (y + cfg.edge).clamp(0, max_y);
+        zwlr_layer_surface_v1::Anchor::Top
+    } else {
+        margins.bottom = (probe.output_height - y + cfg.edge).clamp(0, max_y);
+        zwlr_layer_surface_v1::Anchor::Bottom
+    };
+    (horizontal | vertical, margins)
+}
+
+fn parse_position(value: &str) -> Result<Position> {
+    match value {
+        "top-left" => Ok(Position::TopLeft),
+        "top" => Ok(Position::Top),
+        "top-right" => Ok(Position::TopRight),
+        "left" => Ok(Position::Left),
+        "center" => Ok(Position::Center),
+        "right" => Ok(Position::Right),
+        "bottom-left" => Ok(Position::BottomLeft),
+        "bottom" => Ok(Position::Bottom),
+        "bottom-right" => Ok(Position::BottomRight),
+        "default" => Ok(Position::Default),
+        "cursor" => Ok(Position::Cursor),
+        _ => Err(anyhow!("invalid position: {}", value)),
+    }
+}
+
+fn position_key(position: Position) -> &'static str {
+    match position {
+        Position::TopLeft => "top-left",
+        Position::Top => "top",
+        Position::TopRight => "top-right",
+        Position::Left => "left",
+        Position::Center => "center",
+        Position::Right => "right",
+        Position::BottomLeft => "bottom-left",
+        Position::Bottom => "bottom",
+        Position::BottomRight => "bottom-right",
+        Position::Default => "default",
+        Position::Cursor => "cursor",
+    }
+}
+
+/// Space an entry takes in its stack. A negative `--stack-gap` overlaps the
+/// cards, but never by more than the entry's height, so the stack can't run
+/// back past the first alert and off the screen edge. With `--slot-height`
+/// every alert takes the same step, so offsets land on a fixed grid.
+fn stack_step(entry: &StackEntry) -> i32 {
+    if entry.slot_height > 0 {
+        return entry.slot_height;
+    }
+    (entry.height + entry.gap).max(0)
+}
+
+fn apply_stack_offset(mut margins: Margins, position: Position, offset: i32) -> Margins {
+    let offset = offset.max(0);
+    match position {
+        Position::Bottom | Position::BottomLeft | Position::BottomRight => {
+            margins.bottom += offset;
+        }
+        _ => {
+            margins.top += offset;
+        }
+    }
+    margins
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0))
+        .as_millis() as u64
+}
+
+fn state_paths(state_dir: Option<&str>) -> Result<StatePaths> {
+    let dir = match state_dir {
+        Some(dir) => dir.to_string(),
+        None => {
+            let xdg_state = env::var("XDG_STATE_HOME").unwrap_or_else(|_| {
+                format!("{}/.local/state", env::var("HOME").unwrap_or_default())
+            });
+            format!("{}/creak", xdg_state)
+        }
+    };
+    fs::create_dir_all(&dir)?;
+    // Locks and sockets only matter while the session is up, so they go on
+    // the per-session tmpfs when there is one. `--state-dir` keeps everything
+    // together.
+    let runtime_dir = match (state_dir, env::var("XDG_RUNTIME_DIR")) {
+        (None, Ok(runtime)) if !runtime.is_empty() => {
+            let runtime_dir = format!("{}/creak", runtime);
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&runtime_dir)?;
+            runtime_dir
+        }
+        _ => dir.clone(),
+    };
+    Ok(StatePaths {
+        state_path: format!("{}/stack.json", dir),
+        lock_path: format!("{}/stack.lock", runtime_dir),
+        history_path: format!("{}/history.jsonl", dir),
+        pending_path: format!("{}/pending.json", dir),
+        socket_path: format!("{}/creak.sock", runtime_dir),
+        lock_timeout: Duration::from_millis(DEFAULT_LOCK_TIMEOUT_MS),
+    })
+}
+
+/// Polls a non-blocking flock so a wedged holder can't hang every creak.
+fn lock_state(lock_path: &str, timeout: Duration) -> Result<fs::File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(lock_path)?;
+    let started = Instant::now();
+    loop {
+        let rc = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if rc == 0 {
+            return Ok(file);
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != ErrorKind::WouldBlock {
+            return Err(anyhow!("failed to lock stack state: {}", err));
+        }
+        if started.elapsed() >= timeout {
+            return Err(anyhow!(
+                "timed out after {}ms waiting for {} (raise --lock-timeout-ms?)",
+                timeout.as_millis(),
+                lock_path
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn load_state(path: &str) -> Result<StackState> {
+    match fs::read_to_string(path) {
+        Ok(data) => {
+            if data.trim().is_empty() {
+                return Ok(StackState::default());
+            }
+            match serde_json::from_str(&data) {
+                Ok(mut state) => {
+                    migrate_state(&mut state);
+                    Ok(state)
+                }
+                Err(err) => {
+                    // Keep the bad file around for inspection; live alerts
+                    // lose their slots either way.
+                    let backup = format!("{}.corrupt-{}", path, now_millis());
+                    let renamed = fs::rename(path, &backup);
+                    log_warn!("stack state parse failed: {}", err);
+                    match renamed {
+                        Ok(()) => log_warn!("moved corrupt state to {}", backup),
+                        Err(err) => log_warn!("corrupt state backup failed: {}", err),
+                    }
+                    Ok(StackState::default())
+                }
+            }
+        }
+        Err(_) => Ok(StackState::default()),
+    }
+}
+
+/// Upgrades state written by older builds; `save_state` then writes it back
+/// at `STATE_VERSION`.
+fn migrate_state(state: &mut StackState) {
+    if state.version < 2 {
+        // Version 1 predates --coalesce, so every entry is a single alert.
+        for entry in state.entries.iter_mut() {
+            entry.count = entry.count.max(1);
+        }
+    }
+    state.version = state.version.max(STATE_VERSION);
+}
+
+fn save_state(path: &str, state: &StackState) -> Result<()> {
+    write_json_atomic(path, state)
+}
+
+fn write_json_atomic<T: Serialize + ?Sized>(path: &str, value: &T) -> Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let data = serde_json::to_vec(value)?;
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// inotify watch for `save_state` renaming a new stack file into place. The
+/// directory is watched because each save replaces the file's inode.
+struct StateWatch {
+    fd: OwnedFd,
+    file_name: Vec<u8>,
+}
+
+impl StateWatch {
+    fn new(state_path: &str) -> Option<Self> {
+        let path = std::path::Path::new(state_path);
+        let dir = path.parent()?;
+        let file_name = path.file_name()?.as_bytes().to_vec();
+        let dir = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+        let raw = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if raw < 0 {
+            log_debug!("inotify unavailable: {}", std::io::Error::last_os_error());
+            return None;
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+        let wd = unsafe { libc::inotify_add_watch(raw, dir.as_ptr(), libc::IN_MOVED_TO) };
+        if wd < 0 {
+            log_debug!("inotify watch failed: {}", std::io::Error::last_os_error());
+            return None;
+        }
+        Some(StateWatch { fd, file_name })
+    }
+
+    fn raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+
+    /// Drains queued events; true if any of them touched the state file.
+    fn changed(&self) -> bool {
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut buf = [0u8; 4096];
+        let mut changed = false;
+        loop {
+            let n = unsafe { libc::read(self.raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if n <= 0 {
+                return changed;
+            }
+            let mut at = 0;
+            while at + header <= n as usize {
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf[at..].as_ptr().cast()) };
+                let name = &buf[at + header..at + header + event.len as usize];
+                let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                changed |= name == self.file_name.as_slice();
+                at += header + event.len as usize;
+            }
+        }
+    }
+}
+
+fn append_history(paths: &StatePaths, entry: &HistoryEntry, max_bytes: u64) -> Result<()> {
+    let _lock = lock_state(&paths.lock_path, paths.lock_timeout)?;
+    if max_bytes > 0 {
+        if let Ok(meta) = fs::metadata(&paths.history_path) {
+            if meta.len() >= max_bytes {
+                fs::rename(&paths.history_path, format!("{}.1", paths.history_path))?;
+            }
+        }
+    }
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&paths.history_path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn read_history(paths: &StatePaths, limit: usize) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for path in [
+        format!("{}.1", paths.history_path),
+        paths.history_path.clone(),
+    ] {
+        match fs::read_to_string(&path) {
+            Ok(data) => lines.extend(
+                data.lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| line.to_string()),
+            ),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let skip = lines.len().saturating_sub(limit);
+    Ok(lines.split_off(skip))
+}
+
+/// First line of `message`, cut to `max_chars` characters (the `--ellipsis`
+/// marker included) so a multi-byte script never gets split mid-codepoint.
+fn message_summary(message: &str, max_chars: usize, ellipsis: &str) -> String {
+    let line = message.lines().next().unwrap_or_default().trim();
+    if line.chars().count() <= max_chars {
+        return line.to_string();
+    }
+    let keep = max_chars.saturating_sub(ellipsis.chars().count());
+    let end = line.char_indices().nth(keep).map_or(line.len(), |(i, _)| i);
+    format!("{}{}", &line[..end], ellipsis)
+}
+
+fn process_alive(pid: u32) -> bool {
+    if pid == 0 {
+        return true;
+    }
+    let rc = unsafe { libc::kill(pid as i32, 0) };
+    if rc == 0 {
+        return true;
+    }
+    let code = std::io::Error::last_os_error().raw_os_error();
+    code == Some(libc::EPERM)
+}
+
+/// Field 22 of `/proc/<pid>/stat`, in clock ticks since boot.
+fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // comm (field 2) may contain spaces and parens; count from its closing paren.
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+fn same_process(entry: &StackEntry) -> bool {
+    if entry.start_time == 0 {
+        return true;
+    }
+    match process_start_time(entry.pid) {
+        Some(start_time) => start_time == entry.start_time,
+        None => true,
+    }
+}
+
+fn prune_entries(state: &mut StackState, now: u64) {
+    // Nothing expires while paused.
+    let now = state.paused_since.map_or(now, |since| since.min(now));
+    state.entries.retain(|entry| {
+        let not_expired = entry.expires_at == 0 || entry.expires_at > now;
+        not_expired && process_alive(entry.pid) && same_process(entry)
+    });
+}
+
+/// `creak pause`/`resume`. Running alerts notice the change and freeze their
+/// countdown; resuming pushes every expiry back by the paused interval.
+fn set_paused(paths: &StatePaths, paused: bool) -> Result<()> {
+    let _lock = lock_state(&paths.lock_path, paths.lock_timeout)?;
+    let mut state = load_state(&paths.state_path)?;
+    let now = now_millis();
+    match (state.paused_since, paused) {
+        (None, true) => state.paused_since = Some(now),
+        (Some(since), false) => {
+            let paused_for = now.saturating_sub(since);
+            for entry in state.entries.iter_mut() {
+                if entry.expires_at > 0 {
+                    entry.expires_at += paused_for;
+                }
+            }
+            state.paused_since = None;
+        }
+        _ => return Ok(()),
+    }
+    save_state(&paths.state_path, &state)
+}
+
+fn dnd_enabled(paths: &StatePaths) -> bool {
+    load_state(&paths.state_path)
+        .map(|state| state.dnd)
+        .unwrap_or(false)
+}
+
+/// Applies `creak dnd <action>` and returns whether do-not-disturb is now on.
+fn set_dnd(paths: &StatePaths, action: DndAction) -> Result<bool> {
+    let _lock = lock_state(&paths.lock_path, paths.lock_timeout)?;
+    let mut state = load_state(&paths.state_path)?;
+    let on = match action {
+        DndAction::On => true,
+        DndAction::Off => false,
+        DndAction::Toggle => !state.dnd,
+        DndAction::Status => return Ok(state.dnd),
+    };
+    if on != state.dnd {
+        state.dnd = on;
+        save_state(&paths.state_path, &state)?;
+    }
+    Ok(on)
+}
+
+fn list_active_entries(paths: &StatePaths) -> Result<Vec<StackEntry>> {
+    let _lock = lock_state(&paths.lock_path, paths.lock_timeout)?;
+    let mut state = load_state(&paths.state_path)?;
+    let now = now_millis();
+    let before = state.entries.len();
+    prune_entries(&mut state, now);
+    if state.entries.len() != before {
+        save_state(&paths.state_path, &state)?;
+    }
+    Ok(state.entries)
+}
+
+enum ClearSelector {
+    Id(u64),
+    Name(String),
+    Class(String),
+}
+
+fn clear_matches(entry: &StackEntry, selector: &ClearSelector) -> bool {
+    match selector {
+        ClearSelector::Id(id) => entry.id == *id,
+        ClearSelector::Name(name) => entry.name.as_deref() == Some(name.as_str()),
+        ClearSelector::Class(class) => entry.class.as_deref() == Some(class.as_str()),
+    }
+}
+
+fn send_sigterm(pid: u32) -> Result<()> {
+    if pid == 0 {
+        return Ok(());
+    }
+    let rc = unsafe { libc::kill(pid as i32, libc::SIGTERM) };
+    if rc == 0 {
+        return Ok(());
+    }
+    let code = std::io::Error::last_os_error().raw_os_error();
+    if code == Some(libc::ESRCH) {
+        return Ok(());
+    }
+    Err(anyhow!("failed to SIGTERM pid {}: {:?}", pid, code))
+}
+
+fn clear_active_entries(paths: &StatePaths, selector: ClearSelector) -> Result<usize> {
+    let _lock = lock_state(&paths.lock_path, paths.lock_timeout)?;
+    Ok(take_entries(paths, &selector)?.len())
+}
+
+/// Removes matching entries and closes their alerts. Callers hold the lock.
+fn take_entries(paths: &StatePaths, selector: &ClearSelector) -> Result<Vec<StackEntry>> {
+    let mut state = load_state(&paths.state_path)?;
+    let now = now_millis();
+    prune_entries(&mut state, now);
+
+    let mut removed = Vec::new();
+    let mut keep = Vec::with_capacity(state.entries.len());
+    for entry in state.entries.into_iter() {
+        if clear_matches(&entry, selector) {
+            if !entry.served {
+                send_sigterm(entry.pid)?;
+            }
+            removed.push(entry);
+            continue;
+        }
+        keep.push(entry);
+    }
+    state.entries = keep;
+    save_state(&paths.state_path, &state)?;
+    Ok(removed)
+}
+
+/// `creak update`: swaps the text of live alerts. Their loops see the bumped
+/// `revision`, re-measure and redraw in place; the timeout keeps running.
+fn update_entries(
+    paths: &StatePaths,
+    selector: ClearSelector,
+    message: &str,
+    summary_length: usize,
+    ellipsis: &str,
+) -> Result<usize> {
+    let _lock = lock_state(&paths.lock_path, paths.lock_timeout)?;
+    let mut state = load_state(&paths.state_path)?;
+    prune_entries(&mut state, now_millis());
+    let mut updated = 0;
+    for entry in state.entries.iter_mut() {
+        if clear_matches(entry, &selector) {
+            entry.summary = message_summary(message, summary_length, ellipsis);
+            entry.message = Some(message.to_string());
+            entry.count = 1;
+            entry.revision += 1;
+            updated += 1;
+        }
+    }
+    if updated > 0 {
+        save_state(&paths.state_path, &state)?;
+    }
+    Ok(updated)
+}
+
+/// A snoozed alert in `pending.json`, shown again by `creak tick` or the
+/// daemon once `show_at` passes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct PendingAlert {
+    show_at: u64,
+    argv: Vec<String>,
+    #[serde(default)]
+    created_at: u64,
+}
+
+impl PendingAlert {
+    fn reshow_argv(&self) -> Vec<String> {
+        let mut argv = self.argv.clone();
+        if self.created_at > 0 {
+            argv.insert(0, format!("--created-at={}", self.created_at));
+        }
+        argv
+    }
+}
+
+/// `creak snooze`: closes matching alerts and queues them to come back after
+/// `delay_ms`.
+fn snooze_entries(paths: &StatePaths, selector: ClearSelector, delay_ms: u64) -> Result<usize> {
+    let _lock = lock_state(&paths.lock_path, paths.lock_timeout)?;
+    let entries = take_entries(paths, &selector)?;
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    let mut pending = load_pending(&paths.pending_path)?;
+    let show_at = now_millis().saturating_add(delay_ms);
+    for entry in entries.iter() {
+        pending.push(PendingAlert {
+            show_at,
+            argv: snooze_argv(entry),
+            created_at: entry.created_at,
+        });
+    }
+    write_json_atomic(&paths.pending_path, &pending)?;
+    Ok(entries.len())
+}
+
+/// The entry's original CLI tokens, or a plain rebuild from what the state
+/// file knows for alerts that have none (batch items, older builds).
+fn snooze_argv(entry: &StackEntry) -> Vec<String> {
+    if !entry.argv.is_empty() {
+        return entry.argv.clone();
+    }
+    let mut argv = Vec::new();
+    match entry.position.as_str() {
+        "default" => {}
+        "cursor" => argv.push("--at-cursor".to_string()),
+        key => argv.push(format!("--{}", key)),
+    }
+    if let Some(name) = entry.name.as_ref() {
+        argv.extend(["--name".to_string(), name.clone()]);
+    }
+    if let Some(class) = entry.class.as_ref() {
+        argv.extend(["--class".to_string(), class.clone()]);
+    }
+    if let Some(app_name) = entry.app_name.as_ref() {
+        argv.extend(["--app-name".to_string(), app_name.clone()]);
+    }
+    argv.push(
+        entry
+            .message
+            .clone()
+            .unwrap_or_else(|| entry.summary.clone()),
+    );
+    argv
+}
+
+fn load_pending(path: &str) -> Result<Vec<PendingAlert>> {
+    match fs::read_to_string(path) {
+        Ok(data) if !data.trim().is_empty() => {
+            serde_json::from_str(&data).with_context(|| format!("parse {}", path))
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Removes and returns the snoozed alerts that are due.
+fn take_due_snoozes(paths: &StatePaths) -> Result<Vec<PendingAlert>> {
+    let _lock = lock_state(&paths.lock_path, paths.lock_timeout)?;
+    let mut pending = load_pending(&paths.pending_path)?;
+    let now = now_millis();
+    let (due, waiting): (Vec<_>, Vec<_>) = pending.drain(..).partition(|p| p.show_at <= now);
+    if !due.is_empty() {
+        write_json_atomic(&paths.pending_path, &waiting)?;
+    }
+    Ok(due)
+}
+
+/// Shows a snoozed alert again: on the daemon's connection when there is
+/// one, otherwise as a detached `creak` process.
+fn reshow(argv: Vec<String>, conn: Option<&Connection>) -> Result<()> {
+    log_info!("re-showing snoozed alert: {:?}", argv);
+    match conn {
+        Some(conn) => {
+            let conn = conn.clone();
+            let busy = BusyGuard::new();
+            std::thread::spawn(move || {
+                let _busy = busy;
+                let result = parse_cli(argv).and_then(|(args, cfg)| {
+                    run_command(args, cfg, &mut std::io::sink(), Some(&conn))
+                });
+                if let Err(err) = result {
+                    log_warn!("snoozed alert failed: {:#}", err);
+                }
+            });
+        }
+        None => {
+            std::process::Command::new(env::current_exe()?)
+                .args(argv)
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .spawn()
+                .context("spawn snoozed alert")?;
+        }
+    }
+    Ok(())
+}
+
+/// Everything up to showing: template, measurement and the stack slot, as
+/// one JSON line. No Wayland connection and no state changes.
+fn dry_run(
+    mut args: AlertArgs,
+    cfg: &mut Config,
+    paths: &StatePaths,
+    out: &mut dyn Write,
+) -> Result<()> {
+    if cfg.template {
+        args.message = expand_template(&args.message, cfg.template_strict)?;
+    }
+    cfg.timeout_ms = cfg.effective_timeout_ms();
+    let (width, height) = measure_notification(cfg, &args)?;
+    let plan = if cfg.stack {
+        preview_stack_slot(paths, slot_request(&args, cfg, height, false))?
+    } else if !cfg.bypass_dnd && dnd_enabled(paths) {
+        SlotPlan::DoNotDisturb
+    } else {
+        SlotPlan::Slot(0, 0)
+    };
+    let (outcome, id, offset) = match plan {
+        SlotPlan::DoNotDisturb => ("do-not-disturb", 0, 0),
+        SlotPlan::Limited => ("rate-limited", 0, 0),
+        SlotPlan::Full => ("stack-full", 0, 0),
+        SlotPlan::Coalesced(id) => ("coalesced", id, 0),
+        SlotPlan::Slot(offset, id) => ("show", id, offset),
+    };
+    let preview = serde_json::json!({
+        "outcome": outcome,
+        "urgency": urgency_key(cfg.urgency),
+        "position": position_key(args.position),
+        "width": width,
+        "height": height,
+        "id": id,
+        "offset": offset,
+        "message": display_message(cfg, &args),
+    });
+    writeln!(out, "{}", preview)?;
+    Ok(())
+}
+
+fn slot_request(args: &AlertArgs, cfg: &Config, height: i32, served: bool) -> SlotRequest {
+    SlotRequest {
+        position: args.position,
+        height,
+        gap: cfg.stack_gap,
+        slot_height: cfg.slot_height,
+        order: cfg.stack_order,
+        priority: cfg.priority,
+        max_stack: cfg.max_stack,
+        on_full: cfg.on_full,
+        timeout_ms: cfg.timeout_ms,
+        name: args.name.clone(),
+        class: args.class.clone(),
+        app_name: args.app_name.clone(),
+        message: args.message.clone(),
+        served,
+        rate_limit: cfg.rate_limit,
+        coalesce: cfg.coalesce,
+        group_by: cfg.group_by,
+        bypass_dnd: cfg.bypass_dnd,
+        argv: args.argv.clone(),
+        replace_id: args.replace_id,
+        summary_length: cfg.summary_length,
+        ellipsis: cfg.ellipsis.clone(),
+    }
+}
+
+/// What `reserve_stack_slot` would do with a request.
+#[derive(Debug, PartialEq)]
+enum SlotPlan {
+    DoNotDisturb,
+    Limited,
+    /// `--max-stack` reached with `--on-full drop`.
+    Full,
+    Coalesced(u64),
+    /// Stack offset and the new entry's id.
+    Slot(i32, u64),
+}
+
+fn reserve_stack_slot(paths: &StatePaths, request: SlotRequest) -> Result<Reservation> {
+    let _lock = lock_state(&paths.lock_path, paths.lock_timeout)?;
+    let mut state = load_state(&paths.state_path)?;
+    let served = request.served;
+    let key = position_key(request.position);
+    let replaced = request
+        .replace_id
+        .and_then(|id| state.entries.iter().find(|entry| entry.id == id).cloned());
+    let mut evicted = Vec::new();
+    match plan_stack_slot(&mut state, request, now_millis(), &mut evicted) {
+        SlotPlan::DoNotDisturb => Ok(Reservation::DoNotDisturb),
+        SlotPlan::Limited | SlotPlan::Full => Ok(Reservation::Limited),
+        SlotPlan::Coalesced(id) => {
+            save_state(&paths.state_path, &state)?;
+            Ok(Reservation::Coalesced(id))
+        }
+        SlotPlan::Slot(offset, id) => {
+            save_state(&paths.state_path, &state)?;
+            let created_at = state
+                .entries
+                .iter()
+                .find(|entry| entry.id == id)
+                .map_or(0, |entry| entry.created_at);
+            // Daemon alerts notice their entry is gone or changed hands instead.
+            let replaced = replaced.filter(|old| old.id == id);
+            for old in replaced.iter().chain(evicted.iter()) {
+                if !old.served && old.pid != std::process::id() {
+                    send_sigterm(old.pid)?;
+                }
+            }
+            Ok(Reservation::Slot(
+                offset,
+                StackGuard {
+                    id,
+                    created_at,
+                    served,
+                    position: key.to_string(),
+                    state_path: paths.state_path.clone(),
+                    lock_path: paths.lock_path.clone(),
+                    lock_timeout: paths.lock_timeout,
+                },
+            ))
+        }
+    }
+}
+
+/// `--dry-run`: the plan for `request` against the current state, which is
+/// left untouched.
+fn preview_stack_slot(paths: &StatePaths, request: SlotRequest) -> Result<SlotPlan> {
+    let _lock = lock_state(&paths.lock_path, paths.lock_timeout)?;
+    let mut state = load_state(&paths.state_path)?;
+    Ok(plan_stack_slot(
+        &mut state,
+        request,
+        now_millis(),
+        &mut Vec::new(),
+    ))
+}
+
+/// Applies `request` to `state` in memory; saving is up to the caller, and
+/// so is closing the alerts moved to `evicted` by `--on-full expire-oldest`.
+fn plan_stack_slot(
+    state: &mut StackState,
+    request: SlotRequest,
+    now: u64,
+    evicted: &mut Vec<StackEntry>,
+) -> SlotPlan {
+    prune_entries(state, now);
+    if state.dnd && !request.bypass_dnd {
+        return SlotPlan::DoNotDisturb;
+    }
+
+    let key = position_key(request.position);
+    let expires_at = if request.timeout_ms == 0 {
+        0
+    } else {
+        now.saturating_add(request.timeout_ms)
+    };
+    let summary = message_summary(&request.message, request.summary_length, &request.ellipsis);
+    let replaced = request
+        .replace_id
+        .and_then(|id| state.entries.iter().position(|entry| entry.id == id));
+    // --group-by merges into any live alert of the group; --coalesce only into
+    // the newest one at the same position.
+    let merge_into = match request.group_by {
+        _ if replaced.is_some() => None,
+        Some(GroupBy::Name) if request.name.is_some() => state
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|e| e.name == request.name),
+        Some(GroupBy::Class) if request.class.is_some() => state
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|e| e.class == request.class),
+        _ if request.coalesce && request.name.is_some() => {
+            // Newest by age, since --stack-order lifo puts it first.
+            let newest = state
+                .entries
+                .iter_mut()
+                .filter(|e| e.position == key)
+                .max_by_key(|e| e.created_at);
+            newest.filter(|entry| entry.name == request.name)
+        }
+        _ => None,
+    };
+    if let Some(entry) = merge_into {
+        entry.count = entry.count.max(1) + 1;
+        entry.revision += 1;
+        entry.summary = summary;
+        entry.message = Some(request.message);
+        entry.expires_at = expires_at;
+        return SlotPlan::Coalesced(entry.id);
+    }
+
+    state.recent.retain(|at| now.saturating_sub(*at) < 1000);
+    if request.rate_limit > 0 && state.recent.len() >= request.rate_limit as usize {
+        return SlotPlan::Limited;
+    }
+
+    if request.max_stack > 0 && replaced.is_none() {
+        let cap = request.max_stack as usize;
+        loop {
+            let here = || state.entries.iter().filter(|entry| entry.position == key);
+            if here().count() < cap {
+                break;
+            }
+            if request.on_full == OnFull::Drop {
+                return SlotPlan::Full;
+            }
+            let oldest = here()
+                .min_by_key(|entry| entry.created_at)
+                .map(|entry| entry.id);
+            let i = state
+                .entries
+                .iter()
+                .position(|entry| Some(entry.id) == oldest);
+            evicted.extend(i.map(|i| state.entries.remove(i)));
+        }
+    }
+    state.recent.push(now);
+
+    // A replacement keeps the old entry's place in the stack, and its id.
+    // Otherwise entries at a position stay sorted by priority, with the order
+    // breaking ties; the others' loops see the saved state and move out of
+    // the way.
+    let priority = request.priority;
+    let end = match replaced {
+        Some(i) => i,
+        None => state
+            .entries
+            .iter()
+            .position(|entry| {
+                entry.position == key
+                    && match request.order {
+                        StackOrder::Fifo => entry.priority < priority,
+                        StackOrder::Lifo => entry.priority <= priority,
+                    }
+            })
+            .unwrap_or(state.entries.len()),
+    };
+    let mut offset = 0;
+    for entry in state.entries[..end]
+        .iter()
+        .filter(|entry| entry.position == key)
+    {
+        offset += stack_step(entry);
+    }
+
+    let (id, created_at) = match replaced {
+        Some(i) => {
+            let old = &state.entries[i];
+            (old.id, now.max(old.created_at + 1))
+        }
+        None => {
+            state.next_id += 1;
+            (state.next_id - 1, now)
+        }
+    };
+    let entry = StackEntry {
+        id,
+        position: key.to_string(),
+        height: request.height,
+        gap: request.gap,
+        slot_height: request.slot_height,
+        expires_at,
+        created_at,
+        pid: std::process::id(),
+        start_time: process_start_time(std::process::id()).unwrap_or(0),
+        name: request.name,
+        class: request.class,
+        app_name: request.app_name,
+        priority,
+        summary,
+        served: request.served,
+        count: 1,
+        argv: request.argv,
+        ..StackEntry::default()
+    };
+    match replaced {
+        Some(i) => state.entries[i] = entry,
+        None => state.entries.insert(end, entry),
+    }
+    SlotPlan::Slot(offset, id)
+}
+
+/// Our offset and entry; `None` once the entry is gone from the state file.
+fn stack_offset_for_id(guard: &StackGuard) -> Result<Option<(i32, StackEntry)>> {
+    let _lock = lock_state(&guard.lock_path, guard.lock_timeout)?;
+    let state = load_state(&guard.state_path)?;
+    let mut offset = 0;
+    for entry in state.entries.iter() {
+        if entry.position != guard.position {
+            continue;
+        }
+        if guard.owns(entry) {
+            return Ok(Some((offset, entry.clone())));
+        }
+        offset += stack_step(entry);
+    }
+    Ok(None)
+}
+
+fn set_entry_expiry(guard: &StackGuard, expires_at: u64) -> Result<()> {
+    let _lock = lock_state(&guard.lock_path, guard.lock_timeout)?;
+    let mut state = load_state(&guard.state_path)?;
+    if let Some(entry) = state.entries.iter_mut().find(|entry| guard.owns(entry)) {
+        entry.expires_at = expires_at;
+        save_state(&guard.state_path, &state)?;
+    }
+    Ok(())
+}
+
+fn set_entry_height(guard: &StackGuard, height: i32) -> Result<()> {
+    let _lock = lock_state(&guard.lock_path, guard.lock_timeout)?;
+    let mut state = load_state(&guard.state_path)?;
+    if let Some(entry) = state.entries.iter_mut().find(|entry| guard.owns(entry)) {
+        entry.height = height;
+        save_state(&guard.state_path, &state)?;
+    }
+    Ok(())
+}
+
+/// Tags the title line with "(xN)" once `--coalesce` has merged alerts.
+fn coalesced_message(message: &str, count: u32) -> String {
+    if count <= 1 {
+        return message.to_string();
+    }
+    match message.split_once('\n') {
+        Some((title, body)) => format!("{} (x{})\n{}", title, count, body),
+        None => format!("{} (x{})", message, count),
+    }
+}
+
+/// Escapes text so Pango markup shows it verbatim.
+fn escape_markup(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// `--template`: replaces `{time}`, `{date}`, `{host}` and `{env:VAR}`.
+/// Unknown placeholders stay literal unless `strict`.
+fn expand_template(text: &str, strict: bool) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['{', '}']) {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        let end = match (tail.starts_with('{'), tail.find('}')) {
+            (true, Some(end)) if !tail[1..end].contains('{') => end,
+            _ => {
+                out.push_str(&tail[..1]);
+                rest = &tail[1..];
+                continue;
+            }
+        };
+        let key = &tail[1..end];
+        match template_value(key) {
+            Some(value) => out.push_str(&value),
+            None if strict => return Err(anyhow!("unknown template placeholder: {{{}}}", key)),
+            None => out.push_str(&tail[..=end]),
+        }
+        rest = &tail[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn template_value(key: &str) -> Option<String> {
+    match key {
+        "time" => Some(local_time("%H:%M")),
+        "date" => Some(local_time("%Y-%m-%d")),
+        "host" => host_name(),
+        _ => env::var(key.strip_prefix("env:")?).ok(),
+    }
+}
+
+fn local_time(format: &str) -> String {
+    format_time(format, unsafe { libc::time(std::ptr::null_mut()) })
+}
+
+fn format_time(format: &str, secs: libc::time_t) -> String {
+    let format = std::ffi::CString::new(format).unwrap_or_default();
+    let mut buf = [0u8; 64];
+    let len = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&secs, &mut tm);
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+fn host_name() -> Option<String> {
+    let mut buf = [0u8; 256];
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if rc != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// `--strip-markup`: drops `<...>` tags and decodes entities, for relaying
+/// text that may carry Pango or HTML markup.
+fn strip_markup(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        plain.push_str(&rest[..start]);
+        match rest[start..].find('>') {
+            Some(end) => rest = &rest[start + end + 1..],
+            None => {
+                // A lone '<' is text, not a tag.
+                plain.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    plain.push_str(rest);
+
+    let mut out = String::with_capacity(plain.len());
+    let mut rest = plain.as_str();
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let entity = &rest[start + 1..];
+        let decoded = entity.find(';').and_then(|end| {
+            let name = &entity[..end];
+            let ch = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => name
+                    .strip_prefix("#x")
+                    .or_else(|| name.strip_prefix("#X"))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| name.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            ch.map(|ch| (ch, end))
+        });
+        match decoded {
+            Some((ch, end)) => {
+                out.push(ch);
+                rest = &entity[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = entity;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// `--font` may already list families ("Noto Sans, Noto Color Emoji 14");
+/// `--fallback-font` families are tried after those.
+fn font_description(cfg: &Config) -> pango::FontDescription {
+    let mut desc = pango::FontDescription::from_string(&cfg.font);
+    if !cfg.fallback_fonts.is_empty() {
+        let mut families: Vec<String> = desc
+            .family()
+            .map(|family| vec![family.to_string()])
+            .unwrap_or_default();
+        families.extend(cfg.fallback_fonts.iter().cloned());
+        desc.set_family(&families.join(","));
+    }
+    if cfg.font_scale != 1.0 {
+        let size = (f64::from(desc.size()) * cfg.font_scale).round() as i32;
+        if desc.is_size_absolute() {
+            desc.set_absolute_size(f64::from(size));
+        } else {
+            desc.set_size(size);
+        }
+    }
+    desc
+}
+
+/// Shared by `measure_text` and `draw_notification` so measured and drawn
+/// wrapping never diverge.
+fn configure_layout(layout: &pango::Layout, cfg: &Config, text: &str, text_width: i32) {
+    let text = if cfg.strip_markup {
+        strip_markup(text)
+    } else {
+        text.to_string()
+    };
+    // Text is always plain: escaped, it means the same thing as markup.
+    layout.set_markup(&escape_markup(&text));
+    layout.set_font_description(Some(&font_description(cfg)));
+    layout.set_width(text_width.max(1) * pango::SCALE);
+    layout.set_alignment(pango::Alignment::Center);
+    // Justified lines fill the width; the last line keeps the alignment.
+    layout.set_justify(cfg.justify);
+    match cfg.wrap {
+        Some(mode) => layout.set_wrap(mode),
+        // Ellipsizing already keeps each paragraph on one line; otherwise
+        // drop the width so nothing wraps (overlong lines are clipped).
+        None if cfg.ellipsize == pango::EllipsizeMode::None => layout.set_width(-1),
+        None => {}
+    }
+    layout.set_ellipsize(cfg.ellipsize);
+    // 1.0 is left unset: Pango's default spacing differs slightly from an
+    // explicit factor of 1.
+    if cfg.line_spacing != 1.0 {
+        layout.set_line_spacing(cfg.line_spacing);
+    }
+    if cfg.letter_spacing != 0 {
+        let attrs = pango::AttrList::new();
+        attrs.insert(pango::AttrInt::new_letter_spacing(
+            cfg.letter_spacing * pango::SCALE,
+        ));
+        layout.set_attributes(Some(&attrs));
+    }
+    if cfg.max_lines > 0 && layout.line_count() > cfg.max_lines as i32 {
+        // A negative height limits lines per paragraph, so clamp the whole
+        // layout to the bottom of line n instead. Pango only honors a height
+        // while ellipsizing.
+        let mut iter = layout.iter();
+        for _ in 1..cfg.max_lines {
+            iter.next_line();
+        }
+        let (_, bottom) = iter.line_yrange();
+        if cfg.ellipsize == pango::EllipsizeMode::None {
+            layout.set_ellipsize(pango::EllipsizeMode::End);
+        }
+        layout.set_height(bottom);
+    }
+}
+
+/// The text as drawn: `--show-time` adds a line that never reaches the
+/// state file or history.
+fn display_message<'a>(cfg: &Config, args: &'a AlertArgs) -> Cow<'a, str> {
+    match cfg.show_time {
+        Some(mode) => {
+            let created_at = args.created_at.unwrap_or_else(now_millis);
+            let label = time_label(mode, created_at, now_millis());
+            Cow::Owned(format!("{}\n{}", args.message, label))
+        }
+        None => Cow::Borrowed(&args.message),
+    }
+}
+
+fn time_label(mode: ShowTime, created_at: u64, now: u64) -> String {
+    let secs = (created_at / 1000) as libc::time_t;
+    match mode {
+        ShowTime::Absolute if format_time("%F", secs) == local_time("%F") => {
+            format_time("%H:%M", secs)
+        }
+        ShowTime::Absolute => format_time("%F %H:%M", secs),
+        ShowTime::Relative => {
+            let minutes = now.saturating_sub(created_at) / 60_000;
+            match minutes {
+                0 => "just now".to_string(),
+                1..=59 => format!("{}m ago", minutes),
+                60..=1439 => format!("{}h ago", minutes / 60),
+                _ => format!("{}d ago", minutes / 1440),
+            }
+        }
+    }
+}
+
+/// Logical size of the whole notification: text, action row and insets.
+fn measure_notification(cfg: &Config, args: &AlertArgs) -> Result<(i32, i32)> {
+    let (width, height) = measure_text(cfg, &display_message(cfg, args))?;
+    let height = height
+        + app_name_height(cfg, args.app_name.as_deref())?
+        + measure_actions(cfg, &args.actions)?;
+    let insets = cfg.insets();
+    let height = height.max(insets.top + insets.bottom + 1);
+    Ok(match cfg.tail.map(|tail| tail.edge) {
+        Some(TailEdge::Top | TailEdge::Bottom) => (width, height + TAIL_SIZE),
+        Some(TailEdge::Left | TailEdge::Right) => (width + TAIL_SIZE, height),
+        None => (width, height),
+    })
+}
+
+fn measure_text(cfg: &Config, text: &str) -> Result<(i32, i32)> {
+    let surface = ImageSurface::create(Format::ARgb32, cfg.width.max(1), 1)?;
+    let cr = CairoContext::new(&surface)?;
+    let insets = cfg.insets();
+    let horizontal = insets.left + insets.right;
+
+    let pieces = text_pieces(cfg, text);
+
+    let mut width = cfg.width;
+    if cfg.fit_width {
+        let mut natural = 0;
+        for piece in &pieces {
+            let layout = pangocairo::create_layout(&cr);
+            configure_layout(&layout, cfg, piece, cfg.width - horizontal);
+            layout.set_width(-1);
+            natural = natural.max(layout.pixel_size().0);
+        }
+        width = (natural + horizontal).clamp(cfg.min_width.min(cfg.width), cfg.width);
+    }
+
+    let mut text_height = (pieces.len() as i32 - 1) * (2 * SEPARATOR_GAP + 1);
+    for piece in &pieces {
+        let layout = pangocairo::create_layout(&cr);
+        configure_layout(&layout, cfg, piece, width - horizontal);
+        text_height += layout.pixel_size().1;
+    }
+    let height = text_height + insets.top + insets.bottom;
+    Ok((width, height))
+}
+
+/// `--separator` splits off the first line as a title, but only when there
+/// is a body left to separate it from.
+fn text_pieces<'a>(cfg: &Config, text: &'a str) -> Vec<&'a str> {
+    match text.split_once('\n') {
+        Some((title, body)) if cfg.separator && !body.trim().is_empty() => vec![title, body],
+        _ => vec![text],
+    }
+}
+
+/// An shm buffer plus the pool backing it. The pool outlives its wl_buffer
+/// so `resize` can reuse the mapping instead of allocating a new memfd.
+struct Buffer {
+    file: fs::File,
+    mmap: MmapMut,
+    pool: WlShmPool,
+    wl_buffer: wayland_client::protocol::wl_buffer::WlBuffer,
+    /// Set when attached, cleared by the compositor's `release`.
+    busy: Arc<AtomicBool>,
+    width: i32,
+    height: i32,
+    stride: i32,
+    transform: Transform,
+}
+
+/// `width`/`height` are the upright pixel size; the buffer itself is laid out
+/// for `transform`, so quarter turns swap its dimensions.
+fn create_buffer(
+    shm: &WlShm,
+    qh: &QueueHandle<State>,
+    width: i32,
+    height: i32,
+    transform: Transform,
+) -> Result<Buffer> {
+    let (width, height, stride) = buffer_layout(width, height, transform);
+    let size = stride * height;
+
+    let file = MemfdOptions::default().create("creak")?.into_file();
+    file.set_len(size as u64)?;
+
+    let mmap = unsafe { MmapMut::map_mut(&file)? };
+
+    let pool = shm.create_pool(file.as_fd(), size, qh, ());
+    let busy = Arc::new(AtomicBool::new(false));
+    let wl_buffer = pool.create_buffer(
+        0,
+        width,
+        height,
+        stride,
+        wayland_client::protocol::wl_shm::Format::Argb8888,
+        qh,
+        busy.clone(),
+    );
+
+    Ok(Buffer {
+        file,
+        mmap,
+        pool,
+        wl_buffer,
+        busy,
+        width,
+        height,
+        stride,
+        transform,
+    })
+}
+
+fn buffer_layout(width: i32, height: i32, transform: Transform) -> (i32, i32, i32) {
+    let (width, height) = if quarter_turn(transform) {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    (width, height, width * 4)
+}
+
+impl Buffer {
+    /// Re-creates the wl_buffer for a new size or transform, growing the pool
+    /// only when the pixels no longer fit. Returns false when nothing changed.
+    fn resize(
+        &mut self,
+        qh: &QueueHandle<State>,
+        width: i32,
+        height: i32,
+        transform: Transform,
+    ) -> Result<bool> {
+        let (width, height, stride) = buffer_layout(width, height, transform);
+        if (width, height, transform) == (self.width, self.height, self.transform) {
+            return Ok(false);
+        }
+        let size = stride * height;
+        if size as usize > self.mmap.len() {
+            log_debug!("growing shm pool {} -> {} bytes", self.mmap.len(), size);
+            self.file.set_len(size as u64)?;
+            self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+            self.pool.resize(size);
+        }
+        self.wl_buffer.destroy();
+        // A destroyed wl_buffer is never released, so start the new one idle.
+        self.busy.store(false, Ordering::Relaxed);
+        self.wl_buffer = self.pool.create_buffer(
+            0,
+            width,
+            height,
+            stride,
+            wayland_client::protocol::wl_shm::Format::Argb8888,
+            qh,
+            self.busy.clone(),
+        );
+        (self.width, self.height, self.stride) = (width, height, stride);
+        self.transform = transform;
+        Ok(true)
+    }
+
+    fn destroy(self) {
+        self.wl_buffer.destroy();
+        self.pool.destroy();
+    }
+}
+
+/// How long `paint` waits for the compositor to release a buffer. Some
+/// compositors hold the last buffer until the next attach, so a lone buffer
+/// might never come back.
+const RELEASE_WAIT: Duration = Duration::from_millis(50);
+
+/// The buffers `paint` alternates between. With `--double-buffer` it draws
+/// into whichever one the compositor isn't reading, so a redraw never shows
+/// up half-finished.
+struct Buffers {
+    slots: Vec<Buffer>,
+    front: usize,
+}
+
+impl Buffers {
+    fn new(
+        shm: &WlShm,
+        qh: &QueueHandle<State>,
+        width: i32,
+        height: i32,
+        transform: Transform,
+        count: usize,
+    ) -> Result<Self> {
+        let slots = (0..count.max(1))
+            .map(|_| create_buffer(shm, qh, width, height, transform))
+            .collect::<Result<_>>()?;
+        Ok(Buffers { slots, front: 0 })
+    }
+
+    fn resize(
+        &mut self,
+        qh: &QueueHandle<State>,
+        width: i32,
+        height: i32,
+        transform: Transform,
+    ) -> Result<()> {
+        for buffer in self.slots.iter_mut() {
+            buffer.resize(qh, width, height, transform)?;
+        }
+        Ok(())
+    }
+
+    fn all_busy(&self) -> bool {
+        self.slots
+            .iter()
+            .all(|buffer| buffer.busy.load(Ordering::Relaxed))
+    }
+
+    /// Picks the buffer to draw the next frame into, preferring one the
+    /// compositor has released over the one currently on screen.
+    fn back(&mut self) -> &mut Buffer {
+        let count = self.slots.len();
+        let next = (self.front + 1) % count;
+        self.front = (1..=count)
+            .map(|step| (self.front + step) % count)
+            .find(|&index| !self.slots[index].busy.load(Ordering::Relaxed))
+            .unwrap_or(next);
+        &mut self.slots[self.front]
+    }
+}
+
+fn quarter_turn(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+/// Maps upright surface pixels (`width` x `height`) into a buffer laid out for
+/// `transform`; the compositor applies the inverse when it shows the buffer.
+fn buffer_transform_matrix(transform: Transform, width: f64, height: f64) -> cairo::Matrix {
+    let (w, h) = (width, height);
+    // Arguments are xx, yx, xy, yy, x0, y0.
+    match transform {
+        Transform::_90 => cairo::Matrix::new(0.0, -1.0, 1.0, 0.0, 0.0, w),
+        Transform::_180 => cairo::Matrix::new(-1.0, 0.0, 0.0, -1.0, w, h),
+        Transform::_270 => cairo::Matrix::new(0.0, 1.0, -1.0, 0.0, h, 0.0),
+        Transform::Flipped => cairo::Matrix::new(-1.0, 0.0, 0.0, 1.0, w, 0.0),
+        Transform::Flipped90 => cairo::Matrix::new(0.0, 1.0, 1.0, 0.0, 0.0, 0.0),
+        Transform::Flipped180 => cairo::Matrix::new(1.0, 0.0, 0.0, -1.0, 0.0, h),
+        Transform::Flipped270 => cairo::Matrix::new(0.0, -1.0, -1.0, 0.0, h, w),
+        _ => cairo::Matrix::identity(),
+    }
+}
+
+fn draw_notification(
+    buffer: &mut Buffer,
+    logical_width: i32,
+    logical_height: i32,
+    cfg: &Config,
+    content: &Content,
+    frame: &Frame,
+) -> Result<Vec<ActionButton>> {
+    let size = (buffer.stride * buffer.height) as usize;
+    let data = &mut buffer.mmap[..size];
+    for b in data.iter_mut() {
+        *b = 0;
+    }
+
+    let surface = unsafe {
+        ImageSurface::create_for_data_unsafe(
+            data.as_mut_ptr(),
+            Format::ARgb32,
+            buffer.width,
+            buffer.height,
+            buffer.stride,
+        )?
+    };
+
+    let buttons = if buffer.transform == Transform::Normal {
+        render_notification(&surface, logical_width, logical_height, cfg, content, frame)?
+    } else {
+        // Buffers are sized at their own output's scale, which copies on
+        // other outputs don't share with `cfg`.
+        let upright_width = if quarter_turn(buffer.transform) {
+            buffer.height
+        } else {
+            buffer.width
+        };
+        let scale = (upright_width / logical_width.max(1)).max(1);
+        let upright = ImageSurface::create(
+            Format::ARgb32,
+            logical_width * scale,
+            logical_height * scale,
+        )?;
+        let buttons =
+            render_notification(&upright, logical_width, logical_height, cfg, content, frame)?;
+        let cr = CairoContext::new(&surface)?;
+        cr.set_matrix(buffer_transform_matrix(
+            buffer.transform,
+            upright.width() as f64,
+            upright.height() as f64,
+        ));
+        cr.set_source_surface(&upright, 0.0, 0.0)?;
+        cr.set_operator(cairo::Operator::Source);
+        cr.paint()?;
+        buttons
+    };
+    drop(surface);
+    if log_enabled(LogLevel::Debug) {
+        if data.len() >= 4 {
+            log_debug!(
+                "pixel0 argb bytes: {:02x} {:02x} {:02x} {:02x}",
+                data[0],
+                data[1],
+                data[2],
+                data[3]
+            );
+        }
+        let px = 10i32;
+        let py = 10i32;
+        let offset = (py * buffer.stride + px * 4) as usize;
+        if data.len() >= offset + 4 {
+            log_debug!(
+                "pixel10,10 argb bytes: {:02x} {:02x} {:02x} {:02x}",
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3]
+            );
+        }
+    }
+    Ok(buttons)
+}
+
+/// Draw into any ARGB32 surface a whole multiple of the logical size (the
+/// buffer scale); shared by the shm buffer path and offscreen rendering.
+fn render_notification(
+    surface: &ImageSurface,
+    logical_width: i32,
+    logical_height: i32,
+    cfg: &Config,
+    content: &Content,
+    frame: &Frame,
+) -> Result<Vec<ActionButton>> {
+    let (text, actions) = (content.text, content.actions);
+    let cr = CairoContext::new(surface)?;
+    let scale = (surface.width() / logical_width.max(1)).max(1) as f64;
+    cr.scale(scale, scale);
+
+    // The tail lives in a strip beside the bubble; everything else is drawn
+    // in the bubble's own coordinates.
+    let (mut logical_width, mut logical_height) = (logical_width, logical_height);
+    let (mut dx, mut dy) = (0, 0);
+    match cfg.tail.map(|tail| tail.edge) {
+        Some(TailEdge::Top) => {
+            dy = TAIL_SIZE;
+            logical_height -= TAIL_SIZE;
+        }
+        Some(TailEdge::Left) => {
+            dx = TAIL_SIZE;
+            logical_width -= TAIL_SIZE;
+        }
+        Some(TailEdge::Bottom) => logical_height -= TAIL_SIZE,
+        Some(TailEdge::Right) => logical_width -= TAIL_SIZE,
+        None => {}
+    }
+    cr.translate(dx as f64, dy as f64);
+
+    let radius = cfg.border_radius as f64;
+    let sides = cfg.border_size;
+    let border_color = if frame.highlight {
+        cfg.blink_color
+    } else {
+        cfg.border
+    };
+    let uniform = sides.top == sides.right && sides.top == sides.bottom && sides.top == sides.left;
+
+    if uniform {
+        let border = sides.top as f64;
+        let x = border / 2.0;
+        let y = border / 2.0;
+        let w = logical_width as f64 - border;
+        let h = logical_height as f64 - border;
+
+        bubble_path(&cr, x, y, w, h, [radius; 4], cfg.corner_smoothing, cfg.tail);
+        set_background_source(&cr, cfg, x, y, w, h)?;
+        cr.fill_preserve()?;
+        paint_background_image(&cr, cfg, x, y, w, h)?;
+
+        if border > 0.0 {
+            cr.set_line_width(border);
+            set_color(&cr, cfg, border_color);
+            cr.stroke()?;
+        } else {
+            cr.new_path();
+        }
+    } else {
+        // Cairo strokes are uniform, so uneven borders are drawn as the ring
+        // between the outer outline and an inset inner one.
+        let outer_w = logical_width as f64;
+        let outer_h = logical_height as f64;
+        let x = sides.left as f64;
+        let y = sides.top as f64;
+        let w = outer_w - (sides.left + sides.right) as f64;
+        let h = outer_h - (sides.top + sides.bottom) as f64;
+        let inner = |a: i32, b: i32| (radius - a.max(b) as f64).max(0.0);
+        let inner_radii = [
+            inner(sides.top, sides.left),
+            inner(sides.top, sides.right),
+            inner(sides.bottom, sides.right),
+            inner(sides.bottom, sides.left),
+        ];
+
+        bubble_path(&cr, x, y, w, h, inner_radii, cfg.corner_smoothing, cfg.tail);
+        set_background_source(&cr, cfg, x, y, w, h)?;
+        cr.fill_preserve()?;
+        paint_background_image(&cr, cfg, x, y, w, h)?;
+        cr.new_path();
+
+        cr.set_fill_rule(cairo::FillRule::EvenOdd);
+        bubble_path(
+            &cr,
+            0.0,
+            0.0,
+            outer_w,
+            outer_h,
+            [radius; 4],
+            cfg.corner_smoothing,
+            cfg.tail,
+        );
+        bubble_path(&cr, x, y, w, h, inner_radii, cfg.corner_smoothing, cfg.tail);
+        set_color(&cr, cfg, border_color);
+        cr.fill()?;
+        cr.set_fill_rule(cairo::FillRule::Winding);
+    }
+
+    let insets = cfg.insets();
+    let text_width = logical_width - insets.left - insets.right;
+    let mut text_top = insets.top as f64;
+    if let Some(name) = content.app_name {
+        let label = app_name_layout(&cr, cfg, name, text_width);
+        let text = text_color(cfg);
+        let dim = [text[0], text[1], text[2], text[3] * APP_NAME_ALPHA];
+        show_text(&cr, cfg, &label, insets.left as f64, text_top, dim)?;
+        text_top += label.pixel_size().1 as f64;
+    }
+
+    let mut font_options = None;
+    if cfg.text_antialias.is_some() || cfg.text_hint.is_some() || cfg.text_hint_metrics.is_some() {
+        if let Ok(mut opts) = FontOptions::new() {
+            if let Some(aa) = cfg.text_antialias {
+                opts.set_antialias(aa);
+            }
+            if let (Some(Antialias::Subpixel), Some(order)) =
+                (cfg.text_antialias, cfg.subpixel_order)
+            {
+                opts.set_subpixel_order(order);
+            }
+            if let Some(hint) = cfg.text_hint {
+                opts.set_hint_style(hint);
+            }
+            if let Some(metrics) = cfg.text_hint_metrics {
+                opts.set_hint_metrics(metrics);
+            }
+            cr.set_font_options(&opts);
+            font_options = Some(opts);
+        }
+    }
+
+    for (index, piece) in text_pieces(cfg, text).into_iter().enumerate() {
+        if index > 0 {
+            let text = text_color(cfg);
+            let rule = cfg.separator_color.unwrap_or([
+                text[0],
+                text[1],
+                text[2],
+                text[3] * APP_NAME_ALPHA,
+            ]);
+            set_color(&cr, cfg, rule);
+            cr.rectangle(
+                insets.left as f64,
+                text_top + SEPARATOR_GAP as f64,
+                text_width as f64,
+                1.0,
+            );
+            cr.fill()?;
+            text_top += (2 * SEPARATOR_GAP + 1) as f64;
+        }
+        let layout = pangocairo::create_layout(&cr);
+        configure_layout(&layout, cfg, piece, text_width);
+        if let Some(opts) = &font_options {
+            pangocairo::context_set_font_options(&layout.context(), Some(opts));
+        }
+        show_text(
+            &cr,
+            cfg,
+            &layout,
+            insets.left as f64,
+            text_top,
+            text_color(cfg),
+        )?;
+        text_top += layout.pixel_size().1 as f64;
+    }
+
+    let mut buttons = action_buttons(cfg, logical_width, logical_height, actions)?;
+    for (i, (button, action)) in buttons.iter().zip(actions.iter()).enumerate() {
+        draw_action_button(&cr, cfg, button, &action.label, frame.selected == Some(i))?;
+    }
+
+    if let Some(remaining) = frame.remaining {
+        draw_timeout_bar(&cr, cfg, logical_width, logical_height, remaining)?;
+    }
+
+    surface.flush();
+    // Hit testing works in surface coordinates.
+    for button in &mut buttons {
+        button.x += dx as f64;
+        button.y += dy as f64;
+    }
+    Ok(buttons)
+}
+
+fn draw_timeout_bar(
+    cr: &CairoContext,
+    cfg: &Config,
+    logical_width: i32,
+    logical_height: i32,
+    remaining: f64,
+) -> Result<()> {
+    let sides = cfg.border_size;
+    let x = sides.left as f64;
+    let y = sides.top as f64;
+    let w = (logical_width - sides.left - sides.right) as f64;
+    let h = (logical_height - sides.top - sides.bottom) as f64;
+    let radius = (cfg.border_radius - sides.top.max(sides.left)).max(0) as f64;
+    let color = cfg.timeout_bar_color.unwrap_or(cfg.border);
+
+    cr.save()?;
+    rounded_rect(cr, x, y, w, h, radius, cfg.corner_smoothing);
+    cr.clip();
+    cr.rectangle(
+        x,
+        y + h - TIMEOUT_BAR_HEIGHT as f64,
+        w * remaining.clamp(0.0, 1.0),
+        TIMEOUT_BAR_HEIGHT as f64,
+    );
+    set_color(cr, cfg, color);
+    cr.fill()?;
+    cr.restore()?;
+    Ok(())
+}
+
+/// Draws `layout` at (x, y), stroking `--text-outline` underneath first like
+/// a subtitle.
+fn show_text(
+    cr: &CairoContext,
+    cfg: &Config,
+    layout: &pango::Layout,
+    x: f64,
+    y: f64,
+    color: [f64; 4],
+) -> Result<()> {
+    if cfg.text_outline > 0 {
+        cr.move_to(x, y);
+        pangocairo::layout_path(cr, layout);
+        set_color(cr, cfg, cfg.text_outline_color);
+        // Half the stroke falls inside the glyphs and is painted over.
+        cr.set_line_width(2.0 * cfg.text_outline as f64);
+        cr.set_line_join(cairo::LineJoin::Round);
+        cr.stroke()?;
+    }
+    set_color(cr, cfg, color);
+    cr.move_to(x, y);
+    pangocairo::show_layout(cr, layout);
+    Ok(())
+}
+
+fn app_name_layout(cr: &CairoContext, cfg: &Config, name: &str, width: i32) -> pango::Layout {
+    let layout = pangocairo::create_layout(cr);
+    let mut font = font_description(cfg);
+    font.set_size((font.size() as f64 * APP_NAME_FONT_SCALE) as i32);
+    layout.set_text(name);
+    layout.set_font_description(Some(&font));
+    layout.set_width(width.max(1) * pango::SCALE);
+    layout.set_alignment(pango::Alignment::Center);
+    layout.set_ellipsize(pango::EllipsizeMode::End);
+    layout
+}
+
+fn app_name_height(cfg: &Config, app_name: Option<&str>) -> Result<i32> {
+    let Some(name) = app_name else {
+        return Ok(0);
+    };
+    let surface = ImageSurface::create(Format::ARgb32, 1, 1)?;
+    let cr = CairoContext::new(&surface)?;
+    Ok(app_name_layout(&cr, cfg, name, cfg.width).pixel_size().1)
+}
+
+fn action_label_layout(cr: &CairoContext, cfg: &Config, label: &str) -> pango::Layout {
+    let layout = pangocairo::create_layout(cr);
+    layout.set_text(label);
+    layout.set_font_description(Some(&font_description(cfg)));
+    layout.set_alignment(pango::Alignment::Center);
+    layout.set_ellipsize(pango::EllipsizeMode::End);
+    layout
+}
+
+fn action_row_height(cfg: &Config, actions: &[AlertAction]) -> Result<i32> {
+    if actions.is_empty() {
+        return Ok(0);
+    }
+    let surface = ImageSurface::create(Format::ARgb32, 1, 1)?;
+    let cr = CairoContext::new(&surface)?;
+    let mut label_height = 0;
+    for action in actions {
+        let (_, h) = action_label_layout(&cr, cfg, &action.label).pixel_size();
+        label_height = label_height.max(h);
+    }
+    Ok(label_height + cfg.padding.bottom)
+}
+
+fn measure_actions(cfg: &Config, actions: &[AlertAction]) -> Result<i32> {
+    let row = action_row_height(cfg, actions)?;
+    if row == 0 {
+        return Ok(0);
+    }
+    Ok(row + cfg.padding.bottom)
+}
+
+fn action_buttons(
+    cfg: &Config,
+    logical_width: i32,
+    logical_height: i32,
+    actions: &[AlertAction],
+) -> Result<Vec<ActionButton>> {
+    let row = action_row_height(cfg, actions)? as f64;
+    if row == 0.0 {
+        return Ok(Vec::new());
+    }
+    let insets = cfg.insets();
+    let gap = cfg.padding.left as f64;
+    let count = actions.len() as f64;
+    let inner = (logical_width - insets.left - insets.right) as f64;
+    let w = ((inner - gap * (count - 1.0)) / count).max(1.0);
+    let y = (logical_height - insets.bottom) as f64 - row;
+    Ok(actions
+        .iter()
+        .enumerate()
+        .map(|(i, action)| ActionButton {
+            id: action.id.clone(),
+            x: insets.left as f64 + i as f64 * (w + gap),
+            y,
+            w,
+            h: row,
+        })
+        .collect())
+}
+
+fn draw_action_button(
+    cr: &CairoContext,
+    cfg: &Config,
+    button: &ActionButton,
+    label: &str,
+    selected: bool,
+) -> Result<()> {
+    rounded_rect(
+        cr,
+        button.x,
+        button.y,
+        button.w,
+        button.h,
+        cfg.border_radius as f64 / 2.0,
+        cfg.corner_smoothing,
+    );
+    if selected {
+        let [r, g, b, a] = text_color(cfg);
+        set_color(cr, cfg, [r, g, b, a * 0.2]);
+        cr.fill_preserve()?;
+    }
+    cr.set_line_width(if selected { 2.0 } else { 1.0 });
+    set_color(cr, cfg, cfg.border);
+    cr.stroke()?;
+
+    let layout = action_label_layout(cr, cfg, label);
+    layout.set_width(button.w as i32 * pango::SCALE);
+    let (_, h) = layout.pixel_size();
+    set_color(cr, cfg, text_color(cfg));
+    cr.move_to(button.x, button.y + (button.h - h as f64) / 2.0);
+    pangocairo::show_layout(cr, &layout);
+    Ok(())
+}
+
+/// Tab and Right move to the next button, Left to the previous one, Enter or
+/// Space press the selected one and 1-9 press that button directly.
+fn action_key(key: u32, selected: Option<usize>, count: usize) -> Option<ActionKey> {
+    if count == 0 {
+        return None;
+    }
+    match key {
+        KEY_TAB | KEY_RIGHT => Some(ActionKey::Select(selected.map_or(0, |i| (i + 1) % count))),
+        KEY_LEFT => Some(ActionKey::Select(
+            selected.map_or(count - 1, |i| (i + count - 1) % count),
+        )),
+        KEY_ENTER | KEY_KPENTER | KEY_SPACE => selected.map(ActionKey::Activate),
+        KEY_1..=KEY_9 => {
+            let index = (key - KEY_1) as usize;
+            (index < count).then_some(ActionKey::Activate(index))
+        }
+        _ => None,
+    }
+}
+
+fn action_at(buttons: &[ActionButton], x: f64, y: f64) -> Option<&str> {
+    buttons
+        .iter()
+        .find(|b| x >= b.x && x < b.x + b.w && y >= b.y && y < b.y + b.h)
+        .map(|b| b.id.as_str())
+}
+
+fn set_background_source(
+    cr: &CairoContext,
+    cfg: &Config,
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+) -> Result<()> {
+    if cfg.background_gradient.len() >= 2 {
+        let stops: Vec<[f64; 4]> = cfg
+            .background_gradient
+            .iter()
+            .map(|c| [c[0], c[1], c[2], c[3] * cfg.opacity])
+            .collect();
+        let gradient = linear_gradient(x, y, w, h, cfg.gradient_angle, &stops);
+        cr.set_source(&gradient)?;
+    } else {
+        set_color(cr, cfg, cfg.background);
+    }
+    Ok(())
+}
+
+/// Sets a solid source, with its alpha scaled by `--opacity`.
+fn set_color(cr: &CairoContext, cfg: &Config, color: [f64; 4]) {
+    cr.set_source_rgba(color[0], color[1], color[2], color[3] * cfg.opacity);
+}
+
+thread_local! {
+    /// Decoded `--background-image` files (`None` if unreadable), so redraws
+    /// don't decode the PNG again.
+    static BACKGROUND_IMAGES: RefCell<HashMap<String, Option<ImageSurface>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Covers the current path with `--background-image`, scaled to fill the
+/// `x, y, w, h` box and centered; the path is left in place. A missing or
+/// broken file leaves the plain background.
+fn paint_background_image(
+    cr: &CairoContext,
+    cfg: &Config,
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+) -> Result<()> {
+    let Some(path) = cfg.background_image.as_deref() else {
+        return Ok(());
+    };
+    let image = BACKGROUND_IMAGES.with(|images| {
+        images
+            .borrow_mut()
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                let loaded = fs::File::open(path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|mut file| Ok(ImageSurface::create_from_png(&mut file)?));
+                loaded
+                    .map_err(|err| log_warn!("--background-image {}: {:#}", path, err))
+                    .ok()
+            })
+            .clone()
+    });
+    let Some(image) = image else {
+        return Ok(());
+    };
+    let (iw, ih) = (image.width() as f64, image.height() as f64);
+    if iw <= 0.0 || ih <= 0.0 {
+        return Ok(());
+    }
+    let scale = (w / iw).max(h / ih);
+    cr.save()?;
+    cr.clip_preserve();
+    cr.translate(x + (w - iw * scale) / 2.0, y + (h - ih * scale) / 2.0);
+    cr.scale(scale, scale);
+    cr.set_source_surface(&image, 0.0, 0.0)?;
+    // Keeps filtering at the image's edges from fading into transparency.
+    cr.source().set_extend(cairo::Extend::Pad);
+    cr.paint_with_alpha(cfg.opacity)?;
+    cr.restore()?;
+    Ok(())
+}
+
+fn linear_gradient(
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+    angle: f64,
+    stops: &[[f64; 4]],
+) -> cairo::LinearGradient {
+    let (dy, dx) = angle.to_radians().sin_cos();
+    let half = (w * dx.abs() + h * dy.abs()) / 2.0;
+    let cx = x + w / 2.0;
+    let cy = y + h / 2.0;
+    let gradient = cairo::LinearGradient::new(
+        cx - dx * half,
+        cy - dy * half,
+        cx + dx * half,
+        cy + dy * half,
+    );
+    let last = (stops.len() - 1).max(1) as f64;
+    for (i, c) in stops.iter().enumerate() {
+        gradient.add_color_stop_rgba(i as f64 / last, c[0], c[1], c[2], c[3]);
+    }
+    gradient
+}
+
+fn rounded_rect(cr: &CairoContext, x: f64, y: f64, w: f64, h: f64, r: f64, smoothing: f64) {
+    bubble_path(cr, x, y, w, h, [r; 4], smoothing, None);
+}
+
+/// How far a corner of radius `r` reaches along each edge once smoothed,
+/// capped at half the shorter side. Smoothing is given up first.
+fn corner_extent(r: f64, smoothing: f64, w: f64, h: f64) -> f64 {
+    ((1.0 + smoothing) * r).min(w.min(h) / 2.0).max(r)
+}
+
+/// One corner at `corner`, entered moving along `u` and left along `v`.
+/// Smoothing follows Figma's squircle: Bézier easings on either side of a
+/// shortened arc, so the curvature ramps up instead of jumping.
+fn corner_path(
+    cr: &CairoContext,
+    corner: (f64, f64),
+    u: (f64, f64),
+    v: (f64, f64),
+    r: f64,
+    p: f64,
+) {
+    let at = |along_u: f64, along_v: f64| {
+        (
+            corner.0 + u.0 * along_u + v.0 * along_v,
+            corner.1 + u.1 * along_u + v.1 * along_v,
+        )
+    };
+    let smoothing = if r > 0.0 { p / r - 1.0 } else { 0.0 };
+    let arc_measure = (90.0 * (1.0 - smoothing)).to_radians();
+    let arc_length = (arc_measure / 2.0).sin() * r * std::f64::consts::SQRT_2;
+    let alpha = (std::f64::consts::FRAC_PI_2 - arc_measure) / 2.0;
+    let beta = (45.0 * smoothing).to_radians();
+    let c = r * (alpha / 2.0).tan() * beta.cos();
+    let d = c * beta.tan();
+    let b = (p - arc_length - c - d) / 3.0;
+    let a = 2.0 * b;
+
+    let start = at(-p, 0.0);
+    cr.line_to(start.0, start.1);
+    let (c1, c2, arc_start) = (
+        at(-(p - a), 0.0),
+        at(-(p - a - b), 0.0),
+        at(-(arc_length + d), d),
+    );
+    cr.curve_to(c1.0, c1.1, c2.0, c2.1, arc_start.0, arc_start.1);
+    let center = at(-r, r);
+    let arc_end = at(-d, arc_length + d);
+    let angle = |point: (f64, f64)| (point.1 - center.1).atan2(point.0 - center.0);
+    let (from, mut to) = (angle(arc_start), angle(arc_end));
+    if to < from {
+        to += 2.0 * std::f64::consts::PI;
+    }
+    cr.arc(center.0, center.1, r, from, to);
+    let (c1, c2, end) = (at(0.0, p - a - b), at(0.0, p - a), at(0.0, p));
+    cr.curve_to(c1.0, c1.1, c2.0, c2.1, end.0, end.1);
+}
+
+/// A rounded rect whose outline, with a tail, detours out through a
+/// `TAIL_SIZE` triangle on that edge. The tail's offset is measured from the
+/// bubble's origin and kept clear of the corners.
+#[allow(clippy::too_many_arguments)]
+fn bubble_path(
+    cr: &CairoContext,
+    x: f64,
+    y: f64,
+    w: f64,
+    h: f64,
+    radii: [f64; 4],
+    smoothing: f64,
+    tail: Option<Tail>,
+) {
+    let [tl, tr, br, bl] = radii.map(|r| r.min(w / 2.0).min(h / 2.0).max(0.0));
+    let [tl_p, tr_p, br_p, bl_p] = [tl, tr, br, bl].map(|r| corner_extent(r, smoothing, w, h));
+    let size = TAIL_SIZE as f64;
+    let notch = |edge: TailEdge, start: f64, len: f64, before: f64, after: f64| {
+        let tail = tail.filter(|tail| tail.edge == edge)?;
+        let (lo, hi) = (start + before + size, start + len - after - size);
+        let center = if lo <= hi {
+            (tail.offset as f64).clamp(lo, hi)
+        } else {
+            start + len / 2.0
+        };
+        Some(center)
+    };
+    cr.new_sub_path();
+    corner_path(cr, (x + w, y), (1.0, 0.0), (0.0, 1.0), tr, tr_p);
+    if let Some(c) = notch(TailEdge::Right, y, h, tr_p, br_p) {
+        cr.line_to(x + w, c - size);
+        cr.line_to(x + w + size, c);
+        cr.line_to(x + w, c + size);
+    }
+    corner_path(cr, (x + w, y + h), (0.0, 1.0), (-1.0, 0.0), br, br_p);
+    if let Some(c) = notch(TailEdge::Bottom, x, w, bl_p, br_p) {
+        cr.line_to(c + size, y + h);
+        cr.line_to(c, y + h + size);
+        cr.line_to(c - size, y + h);
+    }
+    corner_path(cr, (x, y + h), (-1.0, 0.0), (0.0, -1.0), bl, bl_p);
+    if let Some(c) = notch(TailEdge::Left, y, h, tl_p, bl_p) {
+        cr.line_to(x, c + size);
+        cr.line_to(x - size, c);
+        cr.line_to(x, c - size);
+    }
+    corner_path(cr, (x, y), (0.0, -1.0), (1.0, 0.0), tl, tl_p);
+    if let Some(c) = notch(TailEdge::Top, x, w, tl_p, tr_p) {
+        cr.line_to(c - size, y);
+        cr.line_to(c, y - size);
+        cr.line_to(c + size, y);
+    }
+    cr.close_path();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+    static TEST_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+    fn make_temp_state_dir() -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time")
+            .as_nanos();
+        let seq = TEST_COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+        let uniq = format!("creak-test-{}-{}-{}", std::process::id(), nanos, seq);
+        let dir = env::temp_dir().join(uniq);
+        fs::create_dir_all(&dir).expect("create temp dir");
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn state_dir_override_keeps_runtime_files_together() {
+        let dir = make_temp_state_dir();
+        let paths = state_paths(Some(&dir)).expect("paths");
+        assert_eq!(paths.lock_path, format!("{}/stack.lock", dir));
+        assert_eq!(paths.socket_path, format!("{}/creak.sock", dir));
+        assert_eq!(paths.state_path, format!("{}/stack.json", dir));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    fn test_paths() -> StatePaths {
+        let dir = make_temp_state_dir();
+        state_paths(Some(&dir)).expect("state paths")
+    }
+
+    #[test]
+    fn parse_list_active_command() {
+        let tokens = vec![
+            "list".to_string(),
+            "active".to_string(),
+            "--state-dir".to_string(),
+            "/tmp/creak-test".to_string(),
+        ];
+        let (args, _) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        match args.command {
+            Command::ListActive => {}
+            _ => panic!("expected list active command"),
+        }
+        assert_eq!(args.state_dir.as_deref(), Some("/tmp/creak-test"));
+        assert_eq!(args.format, OutputFormat::Json);
+    }
+
+    #[test]
+    fn batch_file_overrides_config_per_item() {
+        let dir = make_temp_state_dir();
+        let path = format!("{}/batch.json", dir);
+        let batch = r##"[
+            {"summary": "build", "body": "ok", "position": "bottom", "timeout": 0},
+            {"summary": "mail", "background": "#00ff00", "class": "inbox"}
+        ]"##;
+        fs::write(&path, batch).expect("write batch");
+        let tokens = vec![
+            "batch".to_string(),
+            path.clone(),
+            "--concurrent".to_string(),
+        ];
+        let (args, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        assert!(matches!(args.command, Command::Batch(ref p) if *p == path));
+        assert!(args.concurrent);
+
+        let alerts = load_batch(&path, &cfg).expect("load batch");
+        assert_eq!(alerts.len(), 2);
+        let (first, first_cfg) = &alerts[0];
+        assert_eq!(first.message, "build\nok");
+        assert!(matches!(first.position, Position::Bottom));
+        assert_eq!(first_cfg.timeout_ms, 0);
+        let (second, second_cfg) = &alerts[1];
+        assert!(matches!(second.position, Position::Default));
+        assert_eq!(second.class.as_deref(), Some("inbox"));
+        assert_eq!(second_cfg.background, [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(second_cfg.timeout_ms, cfg.timeout_ms);
+
+        fs::write(&path, r#"[{"summary": "x", "position": "middle"}]"#).expect("write");
+        let err = load_batch(&path, &cfg).expect_err("bad position");
+        assert!(format!("{:#}", err).contains("invalid position: middle"));
+    }
+
+    #[test]
+    fn template_expands_known_placeholders() {
+        env::set_var("CREAK_TEMPLATE_TEST", "42");
+        let text = "{env:CREAK_TEMPLATE_TEST}% on {host} {{literal}} {nope}";
+        let expanded = expand_template(text, false).expect("expand");
+        let host = host_name().expect("hostname");
+        assert_eq!(expanded, format!("42% on {} {{literal}} {{nope}}", host));
+
+        let time = expand_template("{time}", true).expect("time");
+        assert_eq!(time.len(), 5);
+        assert_eq!(&time[2..3], ":");
+        assert_eq!(expand_template("a } b {", true).expect("stray"), "a } b {");
+        assert_eq!(
+            expand_template("{ {env:CREAK_TEMPLATE_TEST}", true).expect("nested"),
+            "{ 42"
+        );
+
+        let err = expand_template("{nope}", true).expect_err("strict");
+        assert!(err.to_string().contains("{nope}"));
+        let err = expand_template("{env:CREAK_TEMPLATE_UNSET}", true).expect_err("unset");
+        assert!(err.to_string().contains("env:CREAK_TEMPLATE_UNSET"));
+    }
+
+    #[test]
+    fn log_file_receives_leveled_lines() {
+        let dir = make_temp_state_dir();
+        let path = format!("{}/creak.log", dir);
+        let tokens = vec![
+            "--log-level".to_string(),
+            "info".to_string(),
+            format!("--log-file={}", path),
+            "hi".to_string(),
+        ];
+        let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        assert_eq!(cfg.log_level, Some(LogLevel::Info));
+        configure_logging(&cfg).expect("configure logging");
+        log_info!("shown {}", 1);
+        log_debug!("too chatty");
+
+        let contents = fs::read_to_string(&path).expect("read log");
+        assert!(contents.contains("Info: shown 1"));
+        assert!(!contents.contains("too chatty"));
+        assert!(parse_log_level("loud").is_err());
+    }
+
+    #[test]
+    fn parse_version_command() {
+        for flag in ["version", "--version", "-V"] {
+            let (args, _) =
+                parse_tokens(vec![flag.to_string()], default_config()).expect("parse tokens");
+            assert!(matches!(args.command, Command::Version));
+        }
+        assert!(version_string().starts_with(&format!("creak {}", env!("CARGO_PKG_VERSION"))));
+    }
+
+    #[test]
+    fn parse_list_active_ndjson_format() {
+        let tokens = vec![
+            "list".to_string(),
+            "active".to_string(),
+            "--format".to_string(),
+            "ndjson".to_string(),
+        ];
+        let (args, _) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        assert!(matches!(args.command, Command::ListActive));
+        assert_eq!(args.format, OutputFormat::Ndjson);
+
+        let bad = vec![
+            "list".to_string(),
+            "active".to_string(),
+            "--format=yaml".to_string(),
+        ];
+        assert!(parse_tokens(bad, default_config()).is_err());
+    }
+
+    #[test]
+    fn parse_action_and_hit_test_buttons() {
+        assert_eq!(
+            parse_action("open:Open file").expect("action"),
+            AlertAction {
+                id: "open".to_string(),
+                label: "Open file".to_string(),
+            }
+        );
+        assert_eq!(parse_action("ok").expect("action").label, "ok");
+        assert!(parse_action(":label").is_err());
+
+        let actions = vec![
+            parse_action("yes:Yes").expect("action"),
+            parse_action("no:No").expect("action"),
+        ];
+        let buttons = action_buttons(&default_config(), 350, 200, &actions).expect("buttons");
+        assert_eq!(buttons.len(), 2);
+        let right = &buttons[1];
+        assert_eq!(
+            action_at(&buttons, right.x + 1.0, right.y + 1.0),
+            Some("no")
+        );
+        assert_eq!(action_at(&buttons, 1.0, 1.0), None);
+    }
+
+    #[test]
+    fn action_keys_cycle_and_activate() {
+        assert_eq!(action_key(KEY_TAB, None, 3), Some(ActionKey::Select(0)));
+        assert_eq!(
+            action_key(KEY_RIGHT, Some(2), 3),
+            Some(ActionKey::Select(0))
+        );
+        assert_eq!(action_key(KEY_LEFT, None, 3), Some(ActionKey::Select(2)));
+        assert_eq!(action_key(KEY_LEFT, Some(0), 3), Some(ActionKey::Select(2)));
+        assert_eq!(action_key(KEY_ENTER, None, 3), None);
+        assert_eq!(
+            action_key(KEY_ENTER, Some(1), 3),
+            Some(ActionKey::Activate(1))
+        );
+        assert_eq!(action_key(KEY_1 + 2, None, 3), Some(ActionKey::Activate(2)));
+        assert_eq!(action_key(KEY_1 + 3, None, 3), None);
+        assert_eq!(action_key(KEY_TAB, None, 0), None);
+    }
+
+    #[test]
+    fn ellipsize_clamps_measured_height_to_one_line() {
+        let mut cfg = default_config();
+        let long = "word ".repeat(80);
+        let (_, wrapped) = measure_text(&cfg, &long).expect("measure");
+        cfg.ellipsize = parse_ellipsize("end").expect("ellipsize");
+        let (_, clamped) = measure_text(&cfg, &long).expect("measure");
+        let (_, single) = measure_text(&cfg, "word").expect("measure");
+        assert!(clamped < wrapped);
+        assert_eq!(clamped, single);
+        assert!(parse_ellipsize("sideways").is_err());
+    }
+
+    #[test]
+    fn max_lines_clamps_measured_height() {
+        let mut cfg = default_config();
+        let message = (1..=20)
+            .map(|i| format!("line {}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let (_, one) = measure_text(&cfg, "line 1").expect("measure");
+        let (_, three) = measure_text(&cfg, "line 1\nline 2\nline 3").expect("measure");
+        let (_, full) = measure_text(&cfg, &message).expect("measure");
+        cfg.max_lines = 3;
+        let (_, clamped) = measure_text(&cfg, &message).expect("measure");
+        let insets = cfg.insets();
+        let chrome = insets.top + insets.bottom;
+        let line = one - chrome;
+        assert!(clamped < full);
+        assert!((clamped - three).abs() <= line / 2);
+
+        let (_, wrapped) = measure_text(&cfg, &"word ".repeat(200)).expect("measure");
+        assert!((wrapped - three).abs() <= line / 2);
+    }
+
+    #[test]
+    fn fit_width_shrinks_short_messages() {
+        let mut cfg = default_config();
+        let (fixed, _) = measure_text(&cfg, "hi").expect("measure");
+        assert_eq!(fixed, cfg.width);
+
+        cfg.fit_width = true;
+        let (fitted, _) = measure_text(&cfg, "hi").expect("measure");
+        assert!(fitted < cfg.width);
+        let (long, _) = measure_text(&cfg, &"word ".repeat(80)).expect("measure");
+        assert_eq!(long, cfg.width);
+
+        cfg.min_width = 300;
+        let (floored, _) = measure_text(&cfg, "hi").expect("measure");
+        assert_eq!(floored, 300);
+    }
+
+    #[test]
+    fn parse_gradient_accepts_short_and_long_hex() {
+        let stops = parse_gradient("#222 #444444cc").expect("gradient");
+        assert_eq!(stops.len(), 2);
+        let grey = 0x22 as f64 / 255.0;
+        assert_eq!(stops[0], [grey, grey, grey, 1.0]);
+        assert_eq!(stops[1][3], 0xcc as f64 / 255.0);
+        assert!(parse_gradient("#222").is_err());
+        assert!(parse_gradient("#222 nope").is_err());
+        let stops = parse_gradient("rgb(0, 0, 0), hsl(0, 100%, 50%)").expect("gradient");
+        assert_eq!(stops, vec![[0.0, 0.0, 0.0, 1.0], [1.0, 0.0, 0.0, 1.0]]);
+    }
+
+    #[test]
+    fn functional_colors_parse_like_css() {
+        assert_eq!(parse_color("rgb(51, 102, 255)"), Some([0.2, 0.4, 1.0, 1.0]));
+        assert_eq!(parse_color("rgba(255,0,0,0.5)"), Some([1.0, 0.0, 0.0, 0.5]));
+        assert_eq!(
+            parse_color("rgba(100%, 0%, 0%, 25%)"),
+            Some([1.0, 0.0, 0.0, 0.25])
+        );
+        assert_eq!(
+            parse_color("hsl(120, 100%, 50%)"),
+            Some([0.0, 1.0, 0.0, 1.0])
+        );
+        assert_eq!(
+            parse_color("hsla(240deg, 100%, 50%, 0.5)"),
+            Some([0.0, 0.0, 1.0, 0.5])
+        );
+        let [r, g, b, _] = parse_color("hsl(210, 50%, 40%)").expect("hsl");
+        let hex = |c: f64| (c * 255.0).round() as u8;
+        assert_eq!((hex(r), hex(g), hex(b)), (51, 102, 153));
+        assert_eq!(parse_color("#fff"), parse_hex_color("#fff"));
+    }
+
+    #[test]
+    fn corner_smoothing_eases_into_the_corner() {
+        let surface = ImageSurface::create(Format::ARgb32, 1, 1).expect("surface");
+        let cr = CairoContext::new(&surface).expect("context");
+        let inside = |smoothing: f64, x: f64, y: f64| {
+            cr.new_path();
+            rounded_rect(&cr, 0.0, 0.0, 200.0, 200.0, 40.0, smoothing);
+            cr.in_fill(x, y).expect("in fill")
+        };
+        // Inside the circular corner, outside the smoothed one.
+        assert!(inside(0.0, 170.0, 2.0));
+        assert!(!inside(1.0, 170.0, 2.0));
+        for smoothing in [0.0, 0.5, 1.0] {
+            assert!(inside(smoothing, 100.0, 0.5));
+            assert!(!inside(smoothing, 2.0, 2.0));
+            cr.new_path();
+            rounded_rect(&cr, 0.0, 0.0, 200.0, 200.0, 40.0, smoothing);
+            let (x1, y1, x2, y2) = cr.fill_extents().expect("extents");
+            assert_eq!((x1, y1, x2, y2), (0.0, 0.0, 200.0, 200.0));
+        }
+        assert_eq!(corner_extent(40.0, 1.0, 200.0, 60.0), 40.0);
+        assert!(parse_corner_smoothing("1.5").is_err());
+    }
+
+    #[test]
+    fn multibyte_hex_colors_are_rejected() {
+        for bad in ["é1", "#aé1", "#ffé/50%", "#ffffé", "#+f+f+f"] {
+            assert_eq!(parse_color(bad), None, "{}", bad);
+        }
+        let tokens = ["--background", "#aé1", "hi"].map(String::from).to_vec();
+        assert!(parse_tokens(tokens, default_config()).is_err());
+    }
+
+    #[test]
+    fn hex_colors_take_an_alpha_suffix() {
+        assert_eq!(parse_color("#ff0000/50%"), Some([1.0, 0.0, 0.0, 0.5]));
+        assert_eq!(parse_color("#ff0000@0.25"), Some([1.0, 0.0, 0.0, 0.25]));
+        assert_eq!(parse_color("#f00 / 0%"), Some([1.0, 0.0, 0.0, 0.0]));
+        assert_eq!(parse_color("#ff000080@1"), Some([1.0, 0.0, 0.0, 1.0]));
+        for bad in [
+            "#ff0000/150%",
+            "#ff0000@1.5",
+            "#ff0000/",
+            "#ff0000@x",
+            "#zzz/50%",
+        ] {
+            assert_eq!(parse_color(bad), None, "{}", bad);
+        }
+        for bad in [
+            "rgb(1,2)",
+            "rgb(256,0,0)",
+            "rgba(0,0,0,2)",
+            "hsl(0,50,50)",
+            "hsl(nan,50%,50%)",
+            "hsl(1e400,50%,50%)",
+            "hsl(inf,50%,50%)",
+            "cmyk(0,0,0,0)",
+            "rgb(0,0,0",
+        ] {
+            assert_eq!(parse_color(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn border_sides_shorthand_and_insets() {
+        assert_eq!(
+            parse_sides("--border-size", "1 2 3").expect("sides"),
+            Margins {
+                top: 1,
+                right: 2,
+                bottom: 3,
+                left: 2,
+            }
+        );
+        assert!(parse_sides("--border-size", "1 2 3 4 5").is_err());
+
+        let tokens = vec![
+            "--border-size".to_string(),
+            "0".to_string(),
+            "--border-left".to_string(),
+            "4".to_string(),
+            "hi".to_string(),
+        ];
+        let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let insets = cfg.insets();
+        assert_eq!(insets.left, cfg.padding.left + 4);
+        assert_eq!(insets.top, cfg.padding.top);
+
+        let (_, accent) = measure_text(&cfg, "hi").expect("measure");
+        let (_, uniform) = measure_text(&default_config(), "hi").expect("measure");
+        assert_eq!(uniform - accent, 10);
+    }
+
+    #[test]
+    fn surface_scale_follows_entered_outputs() {
+        let mut state = State::default();
+        state.outputs.insert(1, OutputInfo::default());
+        state.outputs.insert(
+            2,
+            OutputInfo {
+                scale: 2,
+                transform: Transform::_90,
+                ..OutputInfo::default()
+            },
+        );
+        state.entered = vec![1];
+        state.update_outputs();
+        assert_eq!(state.scale, 1);
+        assert_eq!(state.transform, Transform::Normal);
+        state.entered.push(2);
+        state.update_outputs();
+        assert_eq!(state.scale, 2);
+        assert_eq!(state.transform, Transform::Normal);
+        state.entered.remove(0);
+        state.update_outputs();
+        assert_eq!(state.transform, Transform::_90);
+    }
+
+    #[test]
+    fn output_selector_matches_name_then_make_and_model() {
+        let output = |name: &str, make: &str, model: &str| OutputInfo {
+            name: Some(name.to_string()),
+            make: make.to_string(),
+            model: model.to_string(),
+            ..OutputInfo::default()
+        };
+        let infos = vec![
+            output("eDP-1", "BOE", "0x095F"),
+            output("DP-1", "Dell Inc.", "DELL U2720Q"),
+            output("DP-2", "Goldstar", "LG DP-1 Clone"),
+        ];
+        let select = |args: &[&str]| {
+            let tokens = args.iter().map(|arg| arg.to_string()).collect();
+            let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+            select_output(&infos, &cfg)
+        };
+        assert_eq!(select(&["--output", "DP-1", "hi"]), Some(1));
+        assert_eq!(select(&["--output=dell u2720", "hi"]), Some(1));
+        assert_eq!(select(&["--output", "lg", "hi"]), Some(2));
+        assert_eq!(select(&["--output", "HDMI-A-1", "hi"]), None);
+        assert_eq!(select(&["--output-index", "2", "hi"]), Some(2));
+        assert_eq!(select(&["--output-index=3", "hi"]), None);
+        assert_eq!(
+            select(&["--output", "DP-1", "--output-index=0", "hi"]),
+            Some(0)
+        );
+        assert_eq!(select(&["hi"]), None);
+    }
+
+    #[test]
+    fn percent_width_resolves_against_the_output() {
+        assert_eq!(parse_width("420").expect("px"), (420, None));
+        assert_eq!(parse_width("40%").expect("percent"), (40, Some(40.0)));
+        assert!(parse_width("0%").is_err());
+        assert!(parse_width("150%").is_err());
+
+        let mut state = State::default();
+        assert_eq!(state.output_width(), None);
+        state.outputs.insert(
+            7,
+            OutputInfo {
+                scale: 2,
+                mode: Some((3840, 2160)),
+                ..OutputInfo::default()
+            },
+        );
+        state.outputs.insert(
+            3,
+            OutputInfo {
+                transform: Transform::_90,
+                mode: Some((1920, 1080)),
+                ..OutputInfo::default()
+            },
+        );
+        // Not on an output yet: the lowest id, rotated to portrait.
+        assert_eq!(state.output_width(), Some(1080));
+        state.entered = vec![7];
+        assert_eq!(state.output_width(), Some(1920));
+        assert_eq!(percent_of(40.0, 1920), 768);
+    }
+
+    #[test]
+    fn font_fallbacks_extend_the_family_list() {
+        let tokens = vec![
+            "--font".to_string(),
+            "Noto Sans, SimSun 25".to_string(),
+            "--fallback-font=Noto Color Emoji".to_string(),
+            "hi".to_string(),
+        ];
+        let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let desc = font_description(&cfg);
+        assert_eq!(
+            desc.family().as_deref(),
+            Some("Noto Sans,SimSun,Noto Color Emoji")
+        );
+        assert_eq!(desc.size(), 25 * pango::SCALE);
+    }
+
+    #[test]
+    fn font_scale_multiplies_only_the_size() {
+        let tokens = ["--font", "Noto Sans Bold 10", "--font-scale=1.5", "hi"]
+            .map(String::from)
+            .to_vec();
+        let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let desc = font_description(&cfg);
+        assert_eq!(desc.size(), 15 * pango::SCALE);
+        assert_eq!(desc.family().as_deref(), Some("Noto Sans"));
+        assert_eq!(desc.weight(), pango::Weight::Bold);
+        assert!(parse_font_scale("0").is_err());
+        assert!(parse_font_scale("big").is_err());
+    }
+
+    #[test]
+    fn loop_intervals_parse_and_reject_zero() {
+        let tokens = [
+            "--reposition-interval-ms",
+            "500",
+            "--poll-interval-ms=40",
+            "hi",
+        ]
+        .map(String::from)
+        .to_vec();
+        let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        assert_eq!(cfg.reposition_interval_ms, 500);
+        assert_eq!(cfg.poll_interval_ms, 40);
+        let tokens = ["--poll-interval-ms=0", "hi"].map(String::from).to_vec();
+        assert!(parse_tokens(tokens, default_config()).is_err());
+    }
+
+    #[test]
+    fn spacing_options_change_measured_size() {
+        let text = "spacing\nspacing";
+        let mut cfg = default_config();
+        cfg.fit_width = true;
+        cfg.width = 2000;
+        let (base_width, base_height) = measure_text(&cfg, text).expect("measure");
+
+        cfg.line_spacing = 2.0;
+        let (_, tall) = measure_text(&cfg, text).expect("measure");
+        assert!(tall > base_height);
+
+        cfg.line_spacing = 1.0;
+        cfg.letter_spacing = 4;
+        let (wide, _) = measure_text(&cfg, text).expect("measure");
+        assert!(wide > base_width);
+    }
+
+    #[test]
+    fn wrap_none_keeps_paragraphs_on_one_line() {
+        let text = "a fairly long status line that would normally wrap";
+        let mut cfg = default_config();
+        cfg.width = 120;
+        let (_, wrapped) = measure_text(&cfg, text).expect("measure");
+        cfg.wrap = parse_wrap("none").expect("parse wrap");
+        let (_, single) = measure_text(&cfg, text).expect("measure");
+        assert!(single < wrapped);
+        assert_eq!(
+            parse_wrap("word").expect("word"),
+            Some(pango::WrapMode::Word)
+        );
+        assert!(parse_wrap("lines")
+            .expect_err("invalid")
+            .to_string()
+            .contains("expected word"));
+    }
+
+    #[test]
+    fn markup_helpers_round_trip_plain_text() {
+        assert_eq!(
+            strip_markup("<b>Song</b> &amp; <i>Artist</i> &lt;3 &#x1F3B5;&#33;"),
+            "Song & Artist <3 🎵!"
+        );
+        assert_eq!(strip_markup("a < b && c"), "a < b && c");
+        assert_eq!(escape_markup("<b>&'\""), "&lt;b&gt;&amp;&#39;&quot;");
+
+        let text = "1 < 2 & <b>not bold</b>";
+        let surface = ImageSurface::create(Format::ARgb32, 1, 1).expect("surface");
+        let layout = pangocairo::create_layout(&CairoContext::new(&surface).expect("context"));
+        configure_layout(&layout, &default_config(), text, 1000);
+        assert_eq!(layout.text().as_str(), text);
+    }
+
+    #[test]
+    fn buffer_transform_keeps_corners_inside_the_buffer() {
+        let (w, h) = (40.0, 10.0);
+        for transform in [
+            Transform::Normal,
+            Transform::_90,
+            Transform::_180,
+            Transform::_270,
+            Transform::Flipped,
+            Transform::Flipped90,
+            Transform::Flipped180,
+            Transform::Flipped270,
+        ] {
+            let (bw, bh) = if quarter_turn(transform) {
+                (h, w)
+            } else {
+                (w, h)
+            };
+            let matrix = buffer_transform_matrix(transform, w, h);
+            let mut corners: Vec<(f64, f64)> = [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)]
+                .iter()
+                .map(|&(x, y)| matrix.transform_point(x, y))
+                .collect();
+            corners.sort_by(|a, b| a.partial_cmp(b).expect("finite"));
+            assert_eq!(corners, vec![(0.0, 0.0), (0.0, bh), (bw, 0.0), (bw, bh)]);
+        }
+        // 90 degrees counter-clockwise: the top-right corner becomes top-left.
+        let matrix = buffer_transform_matrix(Transform::_90, w, h);
+        assert_eq!(matrix.transform_point(w, 0.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn parse_layer_surface_options() {
+        let tokens = vec![
+            "--layer".to_string(),
+            "top".to_string(),
+            "--namespace=creak-osd".to_string(),
+            "--keyboard".to_string(),
+            "on-demand".to_string(),
+            "hi".to_string(),
+        ];
+        let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        assert_eq!(cfg.layer, zwlr_layer_shell_v1::Layer::Top);
+        assert_eq!(cfg.namespace, "creak-osd");
+        assert_eq!(
+            cfg.keyboard,
+            zwlr_layer_surface_v1::KeyboardInteractivity::OnDemand
+        );
+        assert_eq!(cfg.exclusive_zone, Some(0));
+        assert_eq!(parse_exclusive_zone("auto").expect("auto"), None);
+        assert_eq!(parse_exclusive_zone("32").expect("px"), Some(32));
+        assert!(parse_exclusive_zone("tall").is_err());
+        assert!(parse_keyboard("grab").is_err());
+        assert!(parse_layer("floating").is_err());
+    }
+
+    #[test]
+    fn input_region_skips_rounded_corners_and_tail() {
+        let hit = |rects: &[(i32, i32, i32, i32)], px: i32, py: i32| {
+            rects
+                .iter()
+                .any(|&(x, y, w, h)| px >= x && px < x + w && py >= y && py < y + h)
+        };
+        let mut cfg = default_config();
+        cfg.border_radius = 10;
+        let rects = input_rects(&cfg, 100, 40);
+        assert!(!hit(&rects, 0, 0) && !hit(&rects, 99, 39));
+        assert!(hit(&rects, 50, 0) && hit(&rects, 0, 20) && hit(&rects, 5, 5));
+
+        cfg.tail = parse_tail("top:50").expect("tail");
+        let rects = input_rects(&cfg, 100, 40 + TAIL_SIZE);
+        assert!(!hit(&rects, 50, TAIL_SIZE - 1));
+        assert!(hit(&rects, 50, TAIL_SIZE) && hit(&rects, 50, 39 + TAIL_SIZE));
+
+        cfg.border_radius = 0;
+        cfg.tail = None;
+        assert_eq!(input_rects(&cfg, 100, 40), vec![(0, 0, 100, 40)]);
+    }
+
+    #[test]
+    fn dismiss_button_matches_configured_button() {
+        let any = parse_dismiss_button("any").expect("any").expect("some");
+        assert!(any.matches(BTN_LEFT) && any.matches(BTN_MIDDLE));
+        let right = parse_dismiss_button("right").expect("right").expect("some");
+        assert!(right.matches(BTN_RIGHT) && !right.matches(BTN_LEFT));
+        assert_eq!(parse_dismiss_button("none").expect("none"), None);
+        assert!(parse_dismiss_button("side").is_err());
+    }
+
+    #[test]
+    fn cursor_anchor_opens_toward_screen_center() {
+        let cfg = default_config();
+        let probe = PointerProbe {
+            x: 1800.0,
+            y: 100.0,
+            output_width: 1920,
+            output_height: 1080,
+        };
+        let (anchor, margins) = cursor_anchor(&cfg, probe, 350, 80);
+        assert_eq!(
+            anchor,
+            zwlr_layer_surface_v1::Anchor::Top | zwlr_layer_surface_v1::Anchor::Right
+        );
+        assert_eq!(margins.right, 120 + cfg.edge);
+        assert_eq!(margins.top, 100 + cfg.edge);
+
+        let corner = PointerProbe {
+            x: 5.0,
+            y: 1079.0,
+            ..probe
+        };
+        let (anchor, margins) = cursor_anchor(&cfg, corner, 350, 80);
+        assert_eq!(
+            anchor,
+            zwlr_layer_surface_v1::Anchor::Bottom | zwlr_layer_surface_v1::Anchor::Left
+        );
+        assert_eq!(margins.bottom, 1 + cfg.edge);
+    }
+
+    #[test]
+    fn timeout_bar_reserves_space_only_with_a_deadline() {
+        let mut cfg = default_config();
+        let (_, plain) = measure_text(&cfg, "hi").expect("measure");
+        cfg.timeout_bar = true;
+        let (_, with_bar) = measure_text(&cfg, "hi").expect("measure");
+        assert_eq!(with_bar - plain, TIMEOUT_BAR_HEIGHT);
+        cfg.timeout_ms = 0;
+        let (_, forever) = measure_text(&cfg, "hi").expect("measure");
+        assert_eq!(forever, plain);
+    }
+
+    #[test]
+    fn blink_alternates_every_period() {
+        assert!(!blink_phase(Duration::from_millis(0)));
+        assert!(!blink_phase(Duration::from_millis(499)));
+        assert!(blink_phase(Duration::from_millis(500)));
+        assert!(!blink_phase(Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn render_writes_png_without_wayland() {
+        let dir = make_temp_state_dir();
+        let out = format!("{}/preview.png", dir);
+        let tokens = vec![
+            "render".to_string(),
+            "--out".to_string(),
+            out.clone(),
+            "--scale".to_string(),
+            "2".to_string(),
+            "Title".to_string(),
+            "Body".to_string(),
+        ];
+        let (args, mut cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let Command::Render { alert, out: path } = args.command else {
+            panic!("expected render command");
+        };
+        assert_eq!(alert.message, "Title\nBody");
+        render_png(&alert, &mut cfg, &path, &mut std::io::sink()).expect("render");
+
+        let mut file = fs::File::open(&out).expect("open png");
+        let image = ImageSurface::create_from_png(&mut file).expect("decode png");
+        let (width, height) = measure_notification(&cfg, &alert).expect("measure");
+        assert_eq!(image.width(), width * 2);
+        assert_eq!(image.height(), height * 2);
+
+        let missing_out = vec!["render".to_string(), "hi".to_string()];
+        assert!(parse_tokens(missing_out, default_config()).is_err());
+
+        let tokens = ["render", "--out", "-", "hi"].map(String::from).to_vec();
+        let (args, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let mut stdout = Vec::new();
+        assert_eq!(
+            run_command(args, cfg, &mut stdout, None).expect("render"),
+            0
+        );
+        let image = ImageSurface::create_from_png(&mut stdout.as_slice()).expect("decode png");
+        assert!(image.width() > 0);
+    }
+
+    #[test]
+    fn padding_shorthand_and_sides_feed_insets() {
+        let tokens = vec![
+            "--border-size".to_string(),
+            "0".to_string(),
+            "--padding".to_string(),
+            "4 8".to_string(),
+            "--padding-top=20".to_string(),
+            "hi".to_string(),
+        ];
+        let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let insets = cfg.insets();
+        assert_eq!((insets.top, insets.right, insets.left), (20, 8, 8));
+        assert_eq!(insets.bottom, 4 + cfg.timeout_bar_height());
 
-fn list_active_entries(paths: &StatePaths) -> Result<Vec<StackEntry>> {
-    let _lock = lock_state(&paths.lock_path)?;
-    let mut state = load_state(&paths.state_path)?;
-    let now = now_millis();
-    let before = state.entries.len();
-    prune_entries(&mut state, now);
-    if state.entries.len() != before {
-        save_state(&paths.state_path, &state)?;
+        let (_, tall) = measure_text(&cfg, "hi").expect("measure");
+        let mut even = cfg.clone();
+        even.padding.top = 4;
+        let (_, short) = measure_text(&even, "hi").expect("measure");
+        assert_eq!(tall - short, 16);
     }
-    Ok(state.entries)
-}
 
-enum ClearSelector {
-    Id(u64),
-    Name(String),
-    Class(String),
-}
+    #[test]
+    fn tail_grows_the_surface_on_its_edge() {
+        let base = default_config();
+        let (_, plain) = measure_text(&base, "hi").expect("measure");
+        let tokens = vec![
+            "--tail".to_string(),
+            "left:12".to_string(),
+            "hi".to_string(),
+        ];
+        let (args, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        assert_eq!(
+            cfg.tail,
+            Some(Tail {
+                edge: TailEdge::Left,
+                offset: 12
+            })
+        );
+        let Command::Show(alert) = args.command else {
+            panic!("expected show command");
+        };
+        let (width, height) = measure_notification(&cfg, &alert).expect("measure");
+        assert_eq!((width, height), (cfg.width + TAIL_SIZE, plain));
 
-fn clear_matches(entry: &StackEntry, selector: &ClearSelector) -> bool {
-    match selector {
-        ClearSelector::Id(id) => entry.id == *id,
-        ClearSelector::Name(name) => entry.name.as_deref() == Some(name.as_str()),
-        ClearSelector::Class(class) => entry.class.as_deref() == Some(class.as_str()),
+        assert!(parse_tail("middle:3").is_err());
+        assert_eq!(parse_tail("none").expect("none"), None);
     }
-}
 
-fn send_sigterm(pid: u32) -> Result<()> {
-    if pid == 0 {
-        return Ok(());
+    #[test]
+    fn show_time_adds_a_line_outside_the_message() {
+        let now = 10 * 86_400_000;
+        assert_eq!(time_label(ShowTime::Relative, now - 5_000, now), "just now");
+        assert_eq!(time_label(ShowTime::Relative, now - 180_000, now), "3m ago");
+        assert_eq!(
+            time_label(ShowTime::Relative, now - 7_200_000, now),
+            "2h ago"
+        );
+        assert_eq!(
+            time_label(ShowTime::Relative, now - 3 * 86_400_000, now),
+            "3d ago"
+        );
+
+        let tokens = vec![
+            "--show-time".to_string(),
+            "relative".to_string(),
+            "--created-at=1".to_string(),
+            "hi".to_string(),
+        ];
+        let (args, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let Command::Show(alert) = args.command else {
+            panic!("expected show command");
+        };
+        assert_eq!(alert.message, "hi");
+        assert!(display_message(&cfg, &alert).starts_with("hi\n"));
+        assert!(display_message(&cfg, &alert).ends_with("d ago"));
     }
-    let rc = unsafe { libc::kill(pid as i32, libc::SIGTERM) };
-    if rc == 0 {
-        return Ok(());
+
+    #[test]
+    fn app_name_adds_a_label_line() {
+        let tokens = vec!["--app-name=mail".to_string(), "hi".to_string()];
+        let (args, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let Command::Show(mut alert) = args.command else {
+            panic!("expected show command");
+        };
+        assert_eq!(alert.app_name.as_deref(), Some("mail"));
+        let (_, labeled) = measure_notification(&cfg, &alert).expect("measure");
+        let label = app_name_height(&cfg, Some("mail")).expect("label");
+        assert!(label > 0);
+        alert.app_name = None;
+        let (_, plain) = measure_notification(&cfg, &alert).expect("measure");
+        assert_eq!(labeled - plain, label);
     }
-    let code = std::io::Error::last_os_error().raw_os_error();
-    if code == Some(libc::ESRCH) {
-        return Ok(());
+
+    #[test]
+    fn measure_command_parses_sizing_options() {
+        let tokens = vec![
+            "measure".to_string(),
+            "--width".to_string(),
+            "240".to_string(),
+            "--padding".to_string(),
+            "8".to_string(),
+            "Title".to_string(),
+            "Body".to_string(),
+        ];
+        let (args, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let Command::Measure(alert) = args.command else {
+            panic!("expected measure command");
+        };
+        assert_eq!(alert.message, "Title\nBody");
+        let (width, height) = measure_notification(&cfg, &alert).expect("measure");
+        assert_eq!(width, 240);
+        assert!(height > 2 * 8 + 2 * 5);
     }
-    Err(anyhow!("failed to SIGTERM pid {}: {:?}", pid, code))
-}
 
-fn clear_active_entries(paths: &StatePaths, selector: ClearSelector) -> Result<usize> {
-    let _lock = lock_state(&paths.lock_path)?;
-    let mut state = load_state(&paths.state_path)?;
-    let now = now_millis();
-    prune_entries(&mut state, now);
+    #[test]
+    fn bench_reports_timing_percentiles() {
+        let tokens = ["bench", "--iterations=3"].map(String::from).to_vec();
+        let (args, mut cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let Command::Bench { alert, iterations } = args.command else {
+            panic!("expected bench command");
+        };
+        assert_eq!(alert.message, BENCH_MESSAGE);
+        let report = run_bench(&alert, &mut cfg, iterations).expect("bench");
+        assert_eq!(report["iterations"], 3);
+        assert!(report["p99_us"].as_f64().unwrap() >= report["median_us"].as_f64().unwrap());
+        let times: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&times, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&times, 99.0), Duration::from_millis(99));
+        let tokens = ["bench", "--iterations", "0"].map(String::from).to_vec();
+        assert!(parse_tokens(tokens, default_config()).is_err());
+    }
 
-    let mut removed = 0usize;
-    let mut keep = Vec::with_capacity(state.entries.len());
-    for entry in state.entries.into_iter() {
-        if clear_matches(&entry, &selector) {
-            send_sigterm(entry.pid)?;
-            removed += 1;
-            continue;
+    #[test]
+    fn subcommand_words_are_message_text_after_the_first_positional() {
+        for word in [
+            "list", "clear", "update", "snooze", "tick", "history", "dnd", "pause", "resume",
+            "batch", "config", "serve", "render", "measure", "bench", "version",
+        ] {
+            let tokens = ["Title", word, "more"].map(String::from).to_vec();
+            let (args, _) = parse_tokens(tokens, default_config()).expect(word);
+            let Command::Show(alert) = args.command else {
+                panic!("{} was taken as a command", word);
+            };
+            assert_eq!(alert.message, format!("Title\n{} more", word));
         }
-        keep.push(entry);
     }
-    state.entries = keep;
-    save_state(&paths.state_path, &state)?;
-    Ok(removed)
-}
-
-fn reserve_stack_slot(
-    paths: &StatePaths,
-    position: Position,
-    height: i32,
-    gap: i32,
-    timeout_ms: u64,
-    name: Option<String>,
-    class: Option<String>,
-    summary: String,
-) -> Result<(i32, StackGuard)> {
-    let _lock = lock_state(&paths.lock_path)?;
-    let mut state = load_state(&paths.state_path)?;
-    let now = now_millis();
-    prune_entries(&mut state, now);
 
-    let key = position_key(position);
-    let mut offset = 0;
-    for entry in state.entries.iter().filter(|entry| entry.position == key) {
-        offset += entry.height + entry.gap;
+    #[test]
+    fn extract_style_arg_splits_cli_tokens() {
+        let tokens = vec![
+            "--style".to_string(),
+            "hi".to_string(),
+            "--timeout".to_string(),
+            "10".to_string(),
+            "hello".to_string(),
+        ];
+        let (style, rest) = extract_style_arg(tokens).expect("extract style");
+        assert_eq!(style.as_deref(), Some("hi"));
+        assert_eq!(rest, vec!["--timeout", "10", "hello"]);
     }
 
-    let id = state.next_id;
-    state.next_id += 1;
-    let expires_at = if timeout_ms == 0 {
-        0
-    } else {
-        now.saturating_add(timeout_ms)
-    };
-    state.entries.push(StackEntry {
-        id,
-        position: key.to_string(),
-        height,
-        gap,
-        expires_at,
-        created_at: now,
-        pid: std::process::id(),
-        name,
-        class,
-        summary,
-    });
-    save_state(&paths.state_path, &state)?;
-
-    Ok((
-        offset,
-        StackGuard {
-            id,
-            position: key.to_string(),
-            state_path: paths.state_path.clone(),
-            lock_path: paths.lock_path.clone(),
-        },
-    ))
-}
+    #[test]
+    fn class_config_layers_between_base_and_cli() {
+        let dir = make_temp_state_dir();
+        let base = vec!["--timeout".to_string(), "10".to_string()];
+        let cli = vec!["--class=music".to_string(), "song".to_string()];
+        let class = last_arg(base.iter().chain(cli.iter()), "--class").expect("class");
+        assert_eq!(class, "music");
+        assert_eq!(config_path_for_class(&dir, "../up"), None);
 
-fn stack_offset_for_id(guard: &StackGuard) -> Result<i32> {
-    let _lock = lock_state(&guard.lock_path)?;
-    let state = load_state(&guard.state_path)?;
-    let mut offset = 0;
-    for entry in state.entries.iter() {
-        if entry.position != guard.position {
-            continue;
-        }
-        if entry.id == guard.id {
-            break;
-        }
-        offset += entry.height + entry.gap;
+        let path = config_path_for_class(&dir, &class).expect("class path");
+        fs::write(&path, "--timeout 20\n--background \"#102030\"\n").expect("write");
+        let mut tokens = base;
+        tokens.extend(load_config_file(&path).expect("load"));
+        tokens.extend(["--background".to_string(), "#ffffff".to_string()]);
+        tokens.extend(cli);
+        let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        assert_eq!(cfg.timeout_ms, 20);
+        assert_eq!(cfg.background, [1.0, 1.0, 1.0, 1.0]);
+        let _ = fs::remove_dir_all(dir);
     }
-    Ok(offset)
-}
 
-fn measure_text(cfg: &Config, text: &str) -> Result<(i32, i32)> {
-    let surface = ImageSurface::create(Format::ARgb32, cfg.width.max(1), 1)?;
-    let cr = CairoContext::new(&surface)?;
-    let layout = pangocairo::create_layout(&cr);
-    layout.set_text(text);
+    #[test]
+    fn config_include_splices_relative_files_and_stops_cycles() {
+        let dir = make_temp_state_dir();
+        fs::create_dir_all(format!("{}/shared", dir)).expect("mkdir");
+        let config = format!("{}/config", dir);
+        fs::write(
+            &config,
+            "--width 300\ninclude shared/colors\n--timeout 10\n",
+        )
+        .expect("write");
+        fs::write(
+            format!("{}/shared/colors", dir),
+            "--background \"#000000\"\n",
+        )
+        .expect("write");
+        assert_eq!(
+            load_config_file(&config).expect("load"),
+            vec![
+                "--width",
+                "300",
+                "--background",
+                "#000000",
+                "--timeout",
+                "10"
+            ]
+        );
 
-    let font_desc = pango::FontDescription::from_string(&cfg.font);
-    layout.set_font_description(Some(&font_desc));
-    layout.set_width(cfg.width * pango::SCALE);
-    layout.set_alignment(pango::Alignment::Center);
-    layout.set_wrap(pango::WrapMode::WordChar);
+        fs::write(format!("{}/shared/colors", dir), "include ../config\n").expect("write");
+        let err = load_config_file(&config).expect_err("cycle");
+        assert!(err.to_string().contains("include cycle"), "{:#}", err);
+        let _ = fs::remove_dir_all(dir);
+    }
 
-    let (text_width, text_height) = layout.pixel_size();
-    let height = text_height + cfg.padding * 2 + cfg.border_size * 2;
-    Ok((text_width, height))
-}
+    #[test]
+    fn config_check_reports_every_bad_line() {
+        let dir = make_temp_state_dir();
+        let config = format!("{}/config", dir);
+        fs::write(
+            &config,
+            "--width 300\n--background nope\ninclude missing\n--font \"unterminated\n",
+        )
+        .expect("write");
+        let problems = check_config(&config);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with(&format!("{}:2: --background nope:", config)));
+        assert!(problems[1].starts_with(&format!("{}:3: include", config)));
+        assert!(problems[2].starts_with(&format!("{}:4:", config)));
 
-struct Buffer {
-    _mmap: MmapMut,
-    wl_buffer: wayland_client::protocol::wl_buffer::WlBuffer,
-    stride: i32,
-}
+        fs::write(&config, "--width 300\n").expect("write");
+        assert!(check_config(&config).is_empty());
 
-fn create_buffer(shm: &WlShm, qh: &QueueHandle<State>, width: i32, height: i32) -> Result<Buffer> {
-    let stride = width * 4;
-    let size = stride * height;
+        let (args, _) = parse_cli(vec![
+            "--style".to_string(),
+            config.clone(),
+            "config".to_string(),
+            "check".to_string(),
+        ])
+        .expect("parse");
+        assert!(matches!(args.command, Command::ConfigCheck(ref path) if *path == config));
 
-    let memfd = MemfdOptions::default().create("creak")?;
-    memfd.as_file().set_len(size as u64)?;
+        fs::write(&config, "--bogus\n").expect("write");
+        let tokens = [
+            "--log-level",
+            "debug",
+            "--json-errors",
+            "--state-dir=/tmp/x",
+            "--style",
+            &config,
+            "config",
+            "check",
+        ];
+        let (args, _) = parse_cli(tokens.map(String::from).to_vec()).expect("parse");
+        assert!(matches!(args.command, Command::ConfigCheck(ref path) if *path == config));
+        let _ = fs::remove_dir_all(dir);
+    }
 
-    let mmap = unsafe { MmapMut::map_mut(memfd.as_file())? };
+    #[test]
+    fn unknown_options_suggest_the_closest_known_one() {
+        let source = include_str!("main.rs");
+        for (_, rest) in source
+            .match_indices("arg == \"--")
+            .map(|(i, _)| source.split_at(i + 8))
+        {
+            let option = &rest[..rest.find('"').expect("closing quote")];
+            assert!(OPTIONS.contains(&option), "{} missing from OPTIONS", option);
+        }
 
-    let pool = shm.create_pool(memfd.as_file().as_fd(), size, qh, ());
-    let wl_buffer = pool.create_buffer(
-        0,
-        width,
-        height,
-        stride,
-        wayland_client::protocol::wl_shm::Format::Argb8888,
-        qh,
-        (),
-    );
-    pool.destroy();
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(suggest_option("--widht"), Some("--width"));
+        let err =
+            parse_tokens(vec!["--backgroud=#fff".to_string()], default_config()).expect_err("typo");
+        assert_eq!(
+            err.to_string(),
+            "unknown option: --backgroud=#fff; did you mean --background?"
+        );
+        let err =
+            parse_tokens(vec!["--colour".to_string()], default_config()).expect_err("unknown");
+        assert_eq!(err.to_string(), "unknown option: --colour");
+    }
 
-    Ok(Buffer {
-        _mmap: mmap,
-        wl_buffer,
-        stride,
-    })
-}
+    #[test]
+    fn json_errors_carry_message_and_kind() {
+        let missing = fs::read_to_string("/nonexistent/creak")
+            .context("read batch")
+            .expect_err("missing file");
+        assert_eq!(error_kind(&missing), "io");
+        let line: serde_json::Value =
+            serde_json::from_str(&json_error(error_kind(&missing), &missing)).expect("json");
+        assert_eq!(line["kind"], "io");
+        assert!(line["error"]
+            .as_str()
+            .expect("error")
+            .starts_with("read batch: "));
+        assert_eq!(error_kind(&anyhow!("usage: creak list active")), "other");
+    }
 
-fn draw_notification(
-    buffer: &mut Buffer,
-    pixel_width: i32,
-    pixel_height: i32,
-    logical_width: i32,
-    logical_height: i32,
-    cfg: &Config,
-    text: &str,
-) -> Result<()> {
-    let data = buffer._mmap.as_mut();
-    for b in data.iter_mut() {
-        *b = 0;
+    #[test]
+    fn config_dir_override_wins_over_xdg_config_home() {
+        let env = |key: &str| match key {
+            "XDG_CONFIG_HOME" => Some("/tmp/xdg".to_string()),
+            "CREAK_CONFIG_DIR" => Some("/tmp/profile".to_string()),
+            _ => None,
+        };
+        assert_eq!(config_dir(None, env), "/tmp/profile");
+        assert_eq!(config_dir(Some("/tmp/flag"), env), "/tmp/flag");
+        let xdg_only = |key: &str| (key == "XDG_CONFIG_HOME").then(|| "/tmp/xdg".to_string());
+        assert_eq!(config_dir(None, xdg_only), "/tmp/xdg/creak");
+        let home_only = |key: &str| (key == "HOME").then(|| "/home/u".to_string());
+        assert_eq!(config_dir(None, home_only), "/home/u/.config/creak");
+
+        let (dir, rest) = extract_arg(
+            vec!["--config-dir=/tmp/a".to_string(), "hi".to_string()],
+            "--config-dir",
+        )
+        .expect("extract");
+        assert_eq!(dir.as_deref(), Some("/tmp/a"));
+        assert_eq!(rest, vec!["hi"]);
     }
 
-    let surface = unsafe {
-        ImageSurface::create_for_data_unsafe(
-            data.as_mut_ptr(),
-            Format::ARgb32,
-            pixel_width,
-            pixel_height,
-            buffer.stride,
-        )?
-    };
+    #[test]
+    fn profile_sits_between_config_and_cli() {
+        let dir = make_temp_state_dir();
+        fs::create_dir_all(format!("{}/profiles", dir)).expect("mkdir");
+        fs::write(format!("{}/config", dir), "--timeout 5000\n--width 300\n").expect("write");
+        fs::write(
+            format!("{}/profiles/osd", dir),
+            "--center --timeout 800 --no-stack --class osd\n",
+        )
+        .expect("write");
+        fs::write(format!("{}/class.osd", dir), "--timeout 100 --width 200\n").expect("write");
+        let cli = |extra: &[&str]| {
+            let mut tokens = vec!["--config-dir", &dir, "--profile", "osd"];
+            tokens.extend(extra);
+            parse_cli(tokens.iter().map(|t| t.to_string()).collect())
+        };
 
-    let cr = CairoContext::new(&surface)?;
-    let scale = cfg.output_scale.max(1) as f64;
-    cr.scale(scale, scale);
+        let (args, cfg) = cli(&["Vol 50%"]).expect("parse");
+        let Command::Show(alert) = args.command else {
+            panic!("expected show command");
+        };
+        assert!(matches!(alert.position, Position::Center));
+        assert_eq!(alert.class.as_deref(), Some("osd"));
+        assert_eq!((cfg.timeout_ms, cfg.width, cfg.stack), (800, 200, false));
 
-    let radius = cfg.border_radius as f64;
-    let border = cfg.border_size as f64;
-
-    let x = border / 2.0;
-    let y = border / 2.0;
-    let w = logical_width as f64 - border;
-    let h = logical_height as f64 - border;
-
-    rounded_rect(&cr, x, y, w, h, radius);
-    cr.set_source_rgba(
-        cfg.background[0],
-        cfg.background[1],
-        cfg.background[2],
-        cfg.background[3],
-    );
-    cr.fill_preserve()?;
+        let (_, cfg) = cli(&["--timeout", "50", "Vol 60%"]).expect("parse");
+        assert_eq!(cfg.timeout_ms, 50);
 
-    if cfg.border_size > 0 {
-        cr.set_line_width(border);
-        cr.set_source_rgba(cfg.border[0], cfg.border[1], cfg.border[2], cfg.border[3]);
-        cr.stroke()?;
-    } else {
-        cr.new_path();
+        let missing = vec![
+            "--config-dir".to_string(),
+            dir.clone(),
+            "--profile=nope".to_string(),
+        ];
+        assert!(parse_cli(missing).is_err());
+        let _ = fs::remove_dir_all(dir);
     }
 
-    let layout = pangocairo::create_layout(&cr);
-    layout.set_text(text);
-    let font_desc = pango::FontDescription::from_string(&cfg.font);
-    layout.set_font_description(Some(&font_desc));
-    layout.set_width((logical_width - 2 * (cfg.padding + cfg.border_size)) * pango::SCALE);
-    layout.set_alignment(pango::Alignment::Center);
-    layout.set_wrap(pango::WrapMode::WordChar);
-
-    if cfg.text_antialias.is_some() || cfg.text_hint.is_some() || cfg.text_hint_metrics.is_some() {
-        if let Ok(mut opts) = FontOptions::new() {
-            if let Some(aa) = cfg.text_antialias {
-                opts.set_antialias(aa);
-            }
-            if let Some(hint) = cfg.text_hint {
-                opts.set_hint_style(hint);
-            }
-            if let Some(metrics) = cfg.text_hint_metrics {
-                opts.set_hint_metrics(metrics);
-            }
-            cr.set_font_options(&opts);
-            let context = layout.context();
-            pangocairo::context_set_font_options(&context, Some(&opts));
+    #[test]
+    fn background_image_covers_the_bubble() {
+        let dir = make_temp_state_dir();
+        let png = format!("{}/red.png", dir);
+        let red = ImageSurface::create(Format::ARgb32, 4, 2).expect("surface");
+        {
+            let cr = CairoContext::new(&red).expect("context");
+            cr.set_source_rgb(1.0, 0.0, 0.0);
+            cr.paint().expect("paint");
         }
-    }
+        red.write_to_png(&mut fs::File::create(&png).expect("create"))
+            .expect("write png");
 
-    cr.set_source_rgba(cfg.text[0], cfg.text[1], cfg.text[2], cfg.text[3]);
-    cr.move_to(
-        (cfg.padding + cfg.border_size) as f64,
-        (cfg.padding + cfg.border_size) as f64,
-    );
-    pangocairo::show_layout(&cr, &layout);
+        let mut cfg = default_config();
+        cfg.output_scale = 1;
+        cfg.border_size = parse_sides("--border-size", "0").expect("sides");
+        cfg.border_radius = 0;
+        let content = Content {
+            text: "",
+            app_name: None,
+            actions: &[],
+        };
+        let pixel = |cfg: &Config| {
+            let mut surface = ImageSurface::create(Format::ARgb32, 40, 20).expect("surface");
+            render_notification(&surface, 40, 20, cfg, &content, &Frame::default())
+                .expect("render");
+            let data = surface.data().expect("data");
+            // ARGB32 is native-endian BGRA on little-endian hosts.
+            u32::from_ne_bytes([data[0], data[1], data[2], data[3]])
+        };
+        let plain = pixel(&cfg);
+        cfg.background_image = Some(png);
+        assert_eq!(pixel(&cfg), 0xffff0000);
+        cfg.background_image = Some(format!("{}/missing.png", dir));
+        assert_eq!(pixel(&cfg), plain);
+        let _ = fs::remove_dir_all(dir);
+    }
 
-    surface.flush();
-    if env::var("CREAK_DEBUG").is_ok() {
-        if data.len() >= 4 {
-            eprintln!(
-                "creak pixel0 argb bytes: {:02x} {:02x} {:02x} {:02x}",
-                data[0], data[1], data[2], data[3]
-            );
-        }
-        let px = 10i32;
-        let py = 10i32;
-        let offset = (py * buffer.stride + px * 4) as usize;
-        if data.len() >= offset + 4 {
-            eprintln!(
-                "creak pixel10,10 argb bytes: {:02x} {:02x} {:02x} {:02x}",
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3]
-            );
-        }
+    #[test]
+    fn render_follows_the_surface_scale() {
+        // A copy on a 2x output draws at 2x whatever the main surface's scale.
+        let mut cfg = default_config();
+        cfg.output_scale = 1;
+        cfg.border_radius = 0;
+        let content = Content {
+            text: "",
+            app_name: None,
+            actions: &[],
+        };
+        let mut surface = ImageSurface::create(Format::ARgb32, 80, 40).expect("surface");
+        render_notification(&surface, 40, 20, &cfg, &content, &Frame::default()).expect("render");
+        let stride = surface.stride() as usize;
+        let data = surface.data().expect("data");
+        let alpha = |x: usize, y: usize| data[y * stride + x * 4 + 3];
+        assert_ne!(alpha(78, 20), 0);
+        assert_ne!(alpha(40, 38), 0);
     }
-    Ok(())
-}
 
-fn rounded_rect(cr: &CairoContext, x: f64, y: f64, w: f64, h: f64, r: f64) {
-    let r = r.min(w / 2.0).min(h / 2.0);
-    cr.new_sub_path();
-    cr.arc(
-        x + w - r,
-        y + r,
-        r,
-        -90.0_f64.to_radians(),
-        0.0_f64.to_radians(),
-    );
-    cr.arc(
-        x + w - r,
-        y + h - r,
-        r,
-        0.0_f64.to_radians(),
-        90.0_f64.to_radians(),
-    );
-    cr.arc(
-        x + r,
-        y + h - r,
-        r,
-        90.0_f64.to_radians(),
-        180.0_f64.to_radians(),
-    );
-    cr.arc(
-        x + r,
-        y + r,
-        r,
-        180.0_f64.to_radians(),
-        270.0_f64.to_radians(),
-    );
-    cr.close_path();
-}
+    #[test]
+    fn urgency_config_layers_under_class_and_cli() {
+        let dir = make_temp_state_dir();
+        let cli = |extra: &[&str]| {
+            let mut tokens = vec!["--config-dir".to_string(), dir.clone()];
+            tokens.extend(extra.iter().map(|t| t.to_string()));
+            tokens.push("hi".to_string());
+            parse_cli(tokens).expect("parse cli").1
+        };
+        let cfg = cli(&["--urgency", "critical"]);
+        assert_eq!(cfg.urgency, Urgency::Critical);
+        assert_eq!((cfg.timeout_ms, cfg.allow_persistent), (0, true));
+        assert_eq!(Some(cfg.border), parse_hex_color("#ff4040"));
+
+        fs::write(format!("{}/urgency.low", dir), "--timeout 1000\n").expect("write");
+        fs::write(format!("{}/class.quiet", dir), "--timeout 2000\n").expect("write");
+        assert_eq!(cli(&["--urgency=low"]).timeout_ms, 1000);
+        assert_eq!(cli(&["--urgency=low", "--class", "quiet"]).timeout_ms, 2000);
+        assert_eq!(
+            cli(&["--urgency=low", "--timeout", "3000"]).timeout_ms,
+            3000
+        );
+        assert_eq!(cli(&[]).timeout_ms, 5000);
+        let tokens = ["--config-dir", &dir, "--urgency=urgent", "hi"];
+        assert!(parse_cli(tokens.map(String::from).to_vec()).is_err());
+        let _ = fs::remove_dir_all(dir);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
-    static TEST_COUNTER: AtomicU64 = AtomicU64::new(1);
+    #[test]
+    fn only_io_errors_count_as_disconnects() {
+        let hangup = std::io::Error::from(ErrorKind::ConnectionReset);
+        let err = anyhow::Error::new(WaylandError::Io(hangup)).context("wayland read error");
+        assert!(is_disconnect(&err));
+        let hangup = std::io::Error::from(ErrorKind::BrokenPipe);
+        let err = anyhow::Error::new(DispatchError::Backend(WaylandError::Io(hangup)));
+        assert!(is_disconnect(&err));
+        assert!(!is_disconnect(&anyhow!("bind wl_compositor")));
+    }
 
-    fn make_temp_state_dir() -> String {
-        let nanos = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("system time")
-            .as_nanos();
-        let seq = TEST_COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
-        let uniq = format!("creak-test-{}-{}-{}", std::process::id(), nanos, seq);
-        let dir = env::temp_dir().join(uniq);
-        fs::create_dir_all(&dir).expect("create temp dir");
-        dir.to_string_lossy().into_owned()
+    #[test]
+    fn timeout_clamps_and_persistence() {
+        let mut cfg = default_config();
+        cfg.timeout_ms = 0;
+        assert_eq!(cfg.effective_timeout_ms(), 0);
+        cfg.min_timeout_ms = 1000;
+        cfg.max_timeout_ms = 60_000;
+        assert_eq!(cfg.effective_timeout_ms(), 60_000);
+        cfg.allow_persistent = true;
+        assert_eq!(cfg.effective_timeout_ms(), 0);
+        cfg.timeout_ms = 10;
+        assert_eq!(cfg.effective_timeout_ms(), 1000);
+        cfg.timeout_ms = 3_600_000;
+        assert_eq!(cfg.effective_timeout_ms(), 60_000);
     }
 
-    fn test_paths() -> StatePaths {
-        let dir = make_temp_state_dir();
-        state_paths(Some(&dir)).expect("state paths")
+    #[test]
+    fn auto_text_contrasts_with_background() {
+        let parse = |args: &[&str]| {
+            let tokens = args.iter().map(|arg| arg.to_string()).collect();
+            parse_tokens(tokens, default_config())
+                .expect("parse tokens")
+                .1
+        };
+        let black = [0.0, 0.0, 0.0, 1.0];
+        let white = [1.0, 1.0, 1.0, 1.0];
+        assert_eq!(
+            text_color(&parse(&["--background", "#ffd700", "--text", "auto", "hi"])),
+            black
+        );
+        assert_eq!(
+            text_color(&parse(&["--background", "#202040", "--text=auto", "hi"])),
+            white
+        );
+        let gradient = parse(&[
+            "--text=auto",
+            "--background-gradient",
+            "#ffffff #000000",
+            "hi",
+        ]);
+        assert_eq!(text_color(&gradient), black);
+        let explicit = parse(&["--text", "auto", "--text", "#ff0000", "hi"]);
+        assert_eq!(text_color(&explicit), [1.0, 0.0, 0.0, 1.0]);
     }
 
     #[test]
-    fn parse_list_active_command() {
-        let tokens = vec![
-            "list".to_string(),
-            "active".to_string(),
-            "--state-dir".to_string(),
-            "/tmp/creak-test".to_string(),
-        ];
-        let (args, _) = parse_tokens(tokens, default_config()).expect("parse tokens");
-        match args.command {
-            Command::ListActive => {}
-            _ => panic!("expected list active command"),
-        }
-        assert_eq!(args.state_dir.as_deref(), Some("/tmp/creak-test"));
+    fn text_outline_widens_the_insets() {
+        let tokens = ["--text-outline", "2", "--text-outline-color=#fff", "hi"]
+            .map(String::from)
+            .to_vec();
+        let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let plain = default_config();
+        assert_eq!(cfg.insets().left, plain.insets().left + 2);
+        assert_eq!(cfg.insets().bottom, plain.insets().bottom + 2);
+        let (_, outlined) = measure_text(&cfg, "hi").expect("measure");
+        let (_, bare) = measure_text(&plain, "hi").expect("measure");
+        assert_eq!(outlined, bare + 4);
+        assert_eq!(cfg.text_outline_color, [1.0, 1.0, 1.0, 1.0]);
     }
 
     #[test]
-    fn extract_style_arg_splits_cli_tokens() {
-        let tokens = vec![
-            "--style".to_string(),
-            "hi".to_string(),
-            "--timeout".to_string(),
-            "10".to_string(),
-            "hello".to_string(),
-        ];
-        let (style, rest) = extract_style_arg(tokens).expect("extract style");
-        assert_eq!(style.as_deref(), Some("hi"));
-        assert_eq!(rest, vec!["--timeout", "10", "hello"]);
+    fn separator_splits_title_from_body() {
+        let mut cfg = default_config();
+        assert_eq!(text_pieces(&cfg, "title\nbody"), vec!["title\nbody"]);
+        let (_, plain) = measure_text(&cfg, "title\nbody").expect("measure");
+        cfg.separator = true;
+        assert_eq!(text_pieces(&cfg, "title\nbody"), vec!["title", "body"]);
+        assert_eq!(text_pieces(&cfg, "title\n "), vec!["title\n "]);
+        assert_eq!(text_pieces(&cfg, "title"), vec!["title"]);
+        let (_, separated) = measure_text(&cfg, "title\nbody").expect("measure");
+        assert!(separated > plain);
+    }
+
+    #[test]
+    fn opacity_scales_every_alpha() {
+        let mut cfg = default_config();
+        cfg.output_scale = 1;
+        cfg.background = [1.0, 1.0, 1.0, 1.0];
+        let content = Content {
+            text: "",
+            app_name: None,
+            actions: &[],
+        };
+        let alpha = |cfg: &Config| {
+            let mut surface = ImageSurface::create(Format::ARgb32, 40, 40).expect("surface");
+            render_notification(&surface, 40, 40, cfg, &content, &Frame::default())
+                .expect("render");
+            let data = surface.data().expect("data");
+            let (border, inside) = (4 * 20, 20 * 160 + 4 * 20);
+            (data[border + 3], data[inside + 3])
+        };
+        assert_eq!(alpha(&cfg), (255, 255));
+        let tokens = ["--opacity", "0.5", "hi"].map(String::from).to_vec();
+        let (_, faded) = parse_tokens(tokens, cfg).expect("parse tokens");
+        assert_eq!(alpha(&faded), (128, 128));
+        let tokens = ["--opacity=1.5", "hi"].map(String::from).to_vec();
+        assert!(parse_tokens(tokens, default_config()).is_err());
     }
 
     #[test]
     fn config_path_for_style_resolves_name_and_path() {
-        let xdg = "/tmp/xdg";
+        let xdg = "/tmp/xdg/creak";
         assert_eq!(
             config_path_for_style(xdg, None),
             "/tmp/xdg/creak/config".to_string()
@@ -1655,6 +9147,7 @@ mod tests {
                     name: Some("water".to_string()),
                     class: Some("reminder".to_string()),
                     summary: "hydrate".to_string(),
+                    ..StackEntry::default()
                 },
                 StackEntry {
                     id: 2,
@@ -1667,8 +9160,10 @@ mod tests {
                     name: Some("other".to_string()),
                     class: Some("reminder".to_string()),
                     summary: "other".to_string(),
+                    ..StackEntry::default()
                 },
             ],
+            ..StackState::default()
         };
         save_state(&paths.state_path, &state).expect("save");
 
@@ -1698,6 +9193,7 @@ mod tests {
                     name: Some("alive".to_string()),
                     class: Some("class".to_string()),
                     summary: "alive".to_string(),
+                    ..StackEntry::default()
                 },
                 StackEntry {
                     id: 2,
@@ -1710,6 +9206,7 @@ mod tests {
                     name: Some("expired".to_string()),
                     class: Some("class".to_string()),
                     summary: "expired".to_string(),
+                    ..StackEntry::default()
                 },
                 StackEntry {
                     id: 3,
@@ -1722,8 +9219,10 @@ mod tests {
                     name: Some("dead-pid".to_string()),
                     class: Some("class".to_string()),
                     summary: "dead".to_string(),
+                    ..StackEntry::default()
                 },
             ],
+            ..StackState::default()
         };
         save_state(&paths.state_path, &state).expect("save");
 
@@ -1733,22 +9232,701 @@ mod tests {
     }
 
     #[test]
-    fn reserve_stack_slot_timeout_zero_is_non_expiring() {
+    fn prune_drops_entries_whose_pid_was_recycled() {
+        let pid = std::process::id();
+        let start_time = process_start_time(pid).expect("own start time");
+        let entry = |id, start_time| StackEntry {
+            id,
+            position: "top".to_string(),
+            pid,
+            start_time,
+            ..StackEntry::default()
+        };
+        let mut state = StackState {
+            entries: vec![entry(1, start_time), entry(2, start_time + 1), entry(3, 0)],
+            ..StackState::default()
+        };
+        prune_entries(&mut state, now_millis());
+        let ids: Vec<u64> = state.entries.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+
+        // Old state files without a start time still load.
+        let old = r#"{"id":1,"position":"top","height":10,"gap":2,"expires_at":0}"#;
+        let entry: StackEntry = serde_json::from_str(old).expect("parse old entry");
+        assert_eq!(entry.start_time, 0);
+    }
+
+    #[test]
+    fn lock_state_times_out_when_held() {
+        let paths = test_paths();
+        let held = lock_state(&paths.lock_path, paths.lock_timeout).expect("first lock");
+        let started = Instant::now();
+        let err = lock_state(&paths.lock_path, Duration::from_millis(50)).expect_err("contended");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(err.to_string().contains("timed out"));
+        drop(held);
+        lock_state(&paths.lock_path, Duration::from_millis(50)).expect("lock after release");
+    }
+
+    #[test]
+    fn state_watch_sees_saves_but_not_locks() {
+        let paths = test_paths();
+        let watch = StateWatch::new(&paths.state_path).expect("inotify");
+        drop(lock_state(&paths.lock_path, paths.lock_timeout).expect("lock"));
+        assert!(!watch.changed());
+        save_state(&paths.state_path, &StackState::default()).expect("save");
+        assert!(watch.changed());
+        assert!(!watch.changed());
+    }
+
+    #[test]
+    fn pause_holds_expiry_until_resume() {
+        let paths = test_paths();
+        let now = now_millis();
+        let state = StackState {
+            entries: vec![StackEntry {
+                id: 1,
+                pid: std::process::id(),
+                start_time: process_start_time(std::process::id()).unwrap_or(0),
+                expires_at: now + 50,
+                ..StackEntry::default()
+            }],
+            ..StackState::default()
+        };
+        save_state(&paths.state_path, &state).expect("save");
+
+        set_paused(&paths, true).expect("pause");
+        let paused = load_state(&paths.state_path).expect("load");
+        assert!(paused.paused_since.is_some());
+        std::thread::sleep(Duration::from_millis(80));
+        assert_eq!(list_active_entries(&paths).expect("list").len(), 1);
+
+        set_paused(&paths, false).expect("resume");
+        let state = load_state(&paths.state_path).expect("load");
+        assert_eq!(state.paused_since, None);
+        assert!(state.entries[0].expires_at >= now + 50 + 80);
+        assert_eq!(list_active_entries(&paths).expect("list").len(), 1);
+    }
+
+    #[test]
+    fn unversioned_state_migrates_to_current_version() {
+        let paths = test_paths();
+        let old = r#"{"next_id":2,"entries":[{"id":1,"position":"top","height":10,"gap":2,"expires_at":0}]}"#;
+        fs::write(&paths.state_path, old).expect("write old state");
+
+        let state = load_state(&paths.state_path).expect("load");
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(state.entries[0].count, 1);
+
+        save_state(&paths.state_path, &state).expect("save");
+        let raw: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&paths.state_path).expect("read"))
+                .expect("parse");
+        assert_eq!(raw["version"], STATE_VERSION);
+    }
+
+    #[test]
+    fn corrupt_state_is_backed_up() {
+        let paths = test_paths();
+        fs::write(&paths.state_path, "{not json").expect("write garbage");
+        let state = load_state(&paths.state_path).expect("load");
+        assert!(state.entries.is_empty());
+        assert!(fs::metadata(&paths.state_path).is_err());
+
+        let dir = std::path::Path::new(&paths.state_path)
+            .parent()
+            .expect("state dir");
+        let backups: Vec<String> = fs::read_dir(dir)
+            .expect("read dir")
+            .map(|entry| {
+                entry
+                    .expect("dir entry")
+                    .file_name()
+                    .into_string()
+                    .expect("utf8")
+            })
+            .filter(|name| name.starts_with("stack.json.corrupt-"))
+            .collect();
+        assert_eq!(backups.len(), 1);
+        let kept = fs::read_to_string(dir.join(&backups[0])).expect("read backup");
+        assert_eq!(kept, "{not json");
+    }
+
+    #[test]
+    fn history_rotates_and_reads_last_lines() {
+        let paths = test_paths();
+        for i in 0..5 {
+            let entry = HistoryEntry {
+                created_at: i,
+                summary: format!("alert {}", i),
+                name: None,
+                class: None,
+                position: "top".to_string(),
+                pid: 0,
+                suppressed: false,
+            };
+            append_history(&paths, &entry, 150).expect("append");
+        }
+        assert!(fs::metadata(format!("{}.1", paths.history_path)).is_ok());
+
+        let lines = read_history(&paths, 2).expect("read");
+        assert_eq!(lines.len(), 2);
+        let last: HistoryEntry = serde_json::from_str(&lines[1]).expect("parse");
+        assert_eq!(last.summary, "alert 4");
+    }
+
+    #[test]
+    fn clear_leaves_served_entries_to_the_daemon() {
+        let paths = test_paths();
+        let now = now_millis();
+        let state = StackState {
+            next_id: 2,
+            entries: vec![StackEntry {
+                id: 1,
+                position: "top".to_string(),
+                height: 10,
+                gap: 2,
+                expires_at: now + 60_000,
+                created_at: now,
+                // Our own pid: a SIGTERM here would kill the test run.
+                pid: std::process::id(),
+                name: Some("served".to_string()),
+                class: None,
+                summary: "served".to_string(),
+                served: true,
+                ..StackEntry::default()
+            }],
+            ..StackState::default()
+        };
+        save_state(&paths.state_path, &state).expect("save");
+
+        let removed =
+            clear_active_entries(&paths, ClearSelector::Name("served".to_string())).expect("clear");
+        assert_eq!(removed, 1);
+        assert!(load_state(&paths.state_path)
+            .expect("reload")
+            .entries
+            .is_empty());
+
+        // Without --socket, serve falls back to the runtime-dir socket.
+        let serve = vec!["serve".to_string()];
+        let (args, _) = parse_tokens(serve, default_config()).expect("parse serve");
+        assert!(matches!(args.command, Command::Serve));
+        assert!(args.socket.is_none());
+    }
+
+    #[test]
+    fn reply_writer_frames_output_lines() {
+        let (server, client) = UnixStream::pair().expect("socket pair");
+        let mut out = ReplyWriter {
+            stream: &server,
+            pending: Vec::new(),
+        };
+        write!(out, "7\nyes").expect("write");
+        out.flush().expect("flush");
+        send_reply(&server, &Reply::Exit(2)).expect("reply");
+        drop(server);
+
+        let lines: Vec<String> = BufReader::new(client)
+            .lines()
+            .map(|line| line.expect("line"))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![r#"{"out":"7"}"#, r#"{"out":"yes"}"#, r#"{"exit":2}"#]
+        );
+    }
+
+    fn burst_request(name: &str, rate_limit: u32, coalesce: bool) -> SlotRequest {
+        SlotRequest {
+            position: Position::Top,
+            height: 24,
+            gap: 5,
+            slot_height: 0,
+            order: StackOrder::Fifo,
+            priority: 0,
+            max_stack: 0,
+            on_full: OnFull::Drop,
+            timeout_ms: 60_000,
+            name: Some(name.to_string()),
+            class: None,
+            app_name: None,
+            message: format!("{} alert", name),
+            served: false,
+            rate_limit,
+            coalesce,
+            group_by: None,
+            bypass_dnd: false,
+            argv: Vec::new(),
+            replace_id: None,
+            summary_length: 120,
+            ellipsis: "…".to_string(),
+        }
+    }
+
+    #[test]
+    fn dnd_blocks_slots_unless_bypassed() {
+        let paths = test_paths();
+        assert!(!set_dnd(&paths, DndAction::Status).expect("status"));
+        assert!(set_dnd(&paths, DndAction::Toggle).expect("toggle"));
+        assert!(dnd_enabled(&paths));
+
+        let blocked = reserve_stack_slot(&paths, burst_request("quiet", 0, false));
+        assert!(matches!(blocked, Ok(Reservation::DoNotDisturb)));
+        let mut urgent = burst_request("urgent", 0, false);
+        urgent.bypass_dnd = true;
+        assert!(matches!(
+            reserve_stack_slot(&paths, urgent),
+            Ok(Reservation::Slot(..))
+        ));
+
+        assert!(!set_dnd(&paths, DndAction::Off).expect("off"));
+        assert!(!dnd_enabled(&paths));
+    }
+
+    #[test]
+    fn snooze_queues_the_original_arguments() {
+        let paths = test_paths();
+        let mut request = burst_request("later", 0, false);
+        request.served = true;
+        request.argv = vec![
+            "--top".to_string(),
+            "--name".to_string(),
+            "later".to_string(),
+        ];
+        let Ok(Reservation::Slot(_, _later)) = reserve_stack_slot(&paths, request) else {
+            panic!("expected a slot");
+        };
+        let mut plain = burst_request("plain", 0, false);
+        plain.served = true;
+        let Ok(Reservation::Slot(_, _plain)) = reserve_stack_slot(&paths, plain) else {
+            panic!("expected a slot");
+        };
+
+        let by_name = |name: &str| ClearSelector::Name(name.to_string());
+        assert_eq!(
+            snooze_entries(&paths, by_name("later"), 0).expect("snooze"),
+            1
+        );
+        assert_eq!(
+            snooze_entries(&paths, by_name("plain"), 60_000).expect("snooze"),
+            1
+        );
+        assert!(load_state(&paths.state_path)
+            .expect("load")
+            .entries
+            .is_empty());
+
+        let due = take_due_snoozes(&paths).expect("due");
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].argv, vec!["--top", "--name", "later"]);
+        assert!(due[0].created_at > 0);
+        assert_eq!(
+            due[0].reshow_argv()[0],
+            format!("--created-at={}", due[0].created_at)
+        );
+        assert!(take_due_snoozes(&paths).expect("due").is_empty());
+        let pending = load_pending(&paths.pending_path).expect("pending");
+        assert_eq!(
+            pending[0].argv,
+            vec!["--top", "--name", "plain", "plain alert"]
+        );
+    }
+
+    #[test]
+    fn group_by_class_merges_across_positions() {
+        let paths = test_paths();
+        let mut first = burst_request("disk", 0, false);
+        first.class = Some("storage".to_string());
+        let Ok(Reservation::Slot(_, guard)) = reserve_stack_slot(&paths, first) else {
+            panic!("expected a slot");
+        };
+        let mut second = burst_request("backup", 0, false);
+        second.position = Position::Bottom;
+        second.class = Some("storage".to_string());
+        second.group_by = Some(GroupBy::Class);
+        match reserve_stack_slot(&paths, second).expect("reserve") {
+            Reservation::Coalesced(id) => assert_eq!(id, guard.id),
+            _ => panic!("expected the class group to merge"),
+        }
+        let mut other = burst_request("cpu", 0, false);
+        other.class = Some("load".to_string());
+        other.group_by = Some(GroupBy::Class);
+        let Ok(Reservation::Slot(_, _other)) = reserve_stack_slot(&paths, other) else {
+            panic!("another class gets its own slot");
+        };
+
+        let state = load_state(&paths.state_path).expect("load");
+        assert_eq!(state.entries.len(), 2);
+        assert_eq!(state.entries[0].count, 2);
+        assert_eq!(state.entries[0].message.as_deref(), Some("backup alert"));
+    }
+
+    #[test]
+    fn update_replaces_message_and_bumps_revision() {
         let paths = test_paths();
-        let (_offset, _guard) = reserve_stack_slot(
+        let Ok(Reservation::Slot(_, guard)) =
+            reserve_stack_slot(&paths, burst_request("download", 0, false))
+        else {
+            panic!("expected a slot");
+        };
+        let updated = update_entries(
             &paths,
-            Position::Top,
-            24,
-            5,
-            0,
-            Some("forever".to_string()),
-            Some("test".to_string()),
-            "forever alert".to_string(),
+            ClearSelector::Id(guard.id),
+            "download\n42%",
+            120,
+            "…",
+        );
+        assert_eq!(updated.expect("update"), 1);
+        assert_eq!(
+            update_entries(&paths, ClearSelector::Id(guard.id + 1), "x", 120, "…").expect("update"),
+            0
+        );
+
+        let (_, entry) = stack_offset_for_id(&guard).expect("offset").expect("entry");
+        assert_eq!(entry.revision, 1);
+        assert_eq!(entry.summary, "download");
+        assert_eq!(
+            coalesced_message(entry.message.as_deref().expect("message"), entry.count),
+            "download\n42%"
+        );
+    }
+
+    #[test]
+    fn negative_stack_gap_overlaps_without_going_off_screen() {
+        let (_, cfg) = parse_tokens(
+            vec![
+                "--stack-gap".to_string(),
+                "-5".to_string(),
+                "hi".to_string(),
+            ],
+            default_config(),
+        )
+        .expect("parse tokens");
+        assert_eq!(cfg.stack_gap, -5);
+
+        let paths = test_paths();
+        let mut guards = Vec::new();
+        let mut offsets = Vec::new();
+        for (i, gap) in [-5, -5, -100, -5].into_iter().enumerate() {
+            let mut request = burst_request(&format!("card-{}", i), 0, false);
+            request.gap = gap;
+            let Ok(Reservation::Slot(offset, guard)) = reserve_stack_slot(&paths, request) else {
+                panic!("expected a slot");
+            };
+            offsets.push(offset);
+            guards.push(guard);
+        }
+        assert_eq!(offsets, vec![0, 19, 38, 38]);
+        for (guard, offset) in guards.iter().zip(offsets.iter()) {
+            let (live, _) = stack_offset_for_id(guard).expect("offset").expect("entry");
+            assert_eq!(live, *offset);
+        }
+
+        let margins = apply_stack_offset(Margins::default(), Position::Top, -10);
+        assert_eq!(margins.top, 0);
+    }
+
+    #[test]
+    fn rate_limit_bounds_a_burst() {
+        let paths = test_paths();
+        let mut guards = Vec::new();
+        let mut limited = 0;
+        for i in 0..20 {
+            let request = burst_request(&format!("loop-{}", i), 5, false);
+            match reserve_stack_slot(&paths, request).expect("reserve") {
+                Reservation::Slot(_, guard) => guards.push(guard),
+                Reservation::Limited => limited += 1,
+                Reservation::Coalesced(_) | Reservation::DoNotDisturb => {
+                    panic!("nothing to coalesce or suppress")
+                }
+            }
+        }
+        assert_eq!(guards.len(), 5);
+        assert_eq!(limited, 15);
+        assert_eq!(
+            load_state(&paths.state_path).expect("load").entries.len(),
+            5
+        );
+    }
+
+    #[test]
+    fn coalesce_merges_a_burst_into_one_entry() {
+        let paths = test_paths();
+        let Reservation::Slot(_, guard) =
+            reserve_stack_slot(&paths, burst_request("build", 0, true)).expect("reserve")
+        else {
+            panic!("expected a slot");
+        };
+        for _ in 0..9 {
+            match reserve_stack_slot(&paths, burst_request("build", 0, true)).expect("reserve") {
+                Reservation::Coalesced(id) => assert_eq!(id, guard.id),
+                _ => panic!("expected coalesce"),
+            }
+        }
+        let state = load_state(&paths.state_path).expect("load");
+        assert_eq!(state.entries.len(), 1);
+        assert_eq!(state.entries[0].count, 10);
+        assert_eq!(state.entries[0].revision, 9);
+        assert_eq!(
+            coalesced_message("build\ndone", state.entries[0].count),
+            "build (x10)\ndone"
+        );
+
+        // A different name in between breaks the run.
+        let Reservation::Slot(_, _other) =
+            reserve_stack_slot(&paths, burst_request("other", 0, true)).expect("reserve")
+        else {
+            panic!("expected a slot");
+        };
+        assert!(matches!(
+            reserve_stack_slot(&paths, burst_request("build", 0, true)).expect("reserve"),
+            Reservation::Slot(..)
+        ));
+    }
+
+    #[test]
+    fn dry_run_previews_the_slot_without_taking_it() {
+        let paths = test_paths();
+        let dir = Path::new(&paths.state_path)
+            .parent()
+            .expect("state dir")
+            .to_string_lossy()
+            .into_owned();
+        let Ok(Reservation::Slot(_, _guard)) =
+            reserve_stack_slot(&paths, burst_request("first", 0, false))
+        else {
+            panic!("expected a slot");
+        };
+        let before = fs::read_to_string(&paths.state_path).expect("state");
+
+        let tokens = ["--state-dir", &dir, "--dry-run", "--top", "hi"];
+        let (args, cfg) = parse_tokens(
+            tokens.iter().map(|t| t.to_string()).collect(),
+            default_config(),
         )
-        .expect("reserve");
+        .expect("parse tokens");
+        let mut out = Vec::new();
+        assert_eq!(run_command(args, cfg, &mut out, None).expect("dry run"), 0);
+        let preview: serde_json::Value = serde_json::from_slice(&out).expect("json");
+        assert_eq!(preview["outcome"], "show");
+        assert_eq!(preview["position"], "top");
+        assert_eq!(preview["id"], 2);
+        assert_eq!(preview["offset"], 24 + 5);
+        assert_eq!(
+            fs::read_to_string(&paths.state_path).expect("state"),
+            before
+        );
+    }
+
+    #[test]
+    fn lifo_puts_new_alerts_nearest_the_edge() {
+        let paths = test_paths();
+        let reserve = |request| match reserve_stack_slot(&paths, request).expect("reserve") {
+            Reservation::Slot(offset, guard) => (offset, guard),
+            _ => panic!("expected a slot"),
+        };
+        let (_, first) = reserve(burst_request("first", 0, false));
+        let mut request = burst_request("second", 0, false);
+        request.order = StackOrder::Lifo;
+        let (offset, second) = reserve(request);
+        assert_eq!(offset, 0);
+        let moved = stack_offset_for_id(&first).expect("offset").map(|(o, _)| o);
+        assert_eq!(moved, Some(24 + 5));
+        assert_eq!(
+            stack_offset_for_id(&second)
+                .expect("offset")
+                .map(|(o, _)| o),
+            Some(0)
+        );
+        assert!(parse_stack_order("random").is_err());
+    }
+
+    #[test]
+    fn higher_priority_stacks_nearer_the_edge() {
+        let paths = test_paths();
+        let reserve = |name: &str, priority| {
+            let mut request = burst_request(name, 0, false);
+            request.priority = priority;
+            match reserve_stack_slot(&paths, request).expect("reserve") {
+                Reservation::Slot(offset, guard) => (offset, guard),
+                _ => panic!("expected a slot"),
+            }
+        };
+        let step = 24 + 5;
+        let (_, low) = reserve("low", 0);
+        let (offset, high) = reserve("high", 5);
+        assert_eq!(offset, 0);
+        let (offset, _later) = reserve("later", 0);
+        assert_eq!(offset, 2 * step);
+        let offset_of =
+            |guard: &StackGuard| stack_offset_for_id(guard).expect("offset").map(|(o, _)| o);
+        assert_eq!((offset_of(&high), offset_of(&low)), (Some(0), Some(step)));
+    }
+
+    #[test]
+    fn offset_moves_away_from_the_anchored_edge() {
+        let tokens = ["--edge", "20", "--offset=40", "hi"]
+            .map(String::from)
+            .to_vec();
+        let (_, cfg) = parse_tokens(tokens, default_config()).expect("parse tokens");
+        let margins = |position| position_to_anchor(&cfg, position).1;
+        let top = margins(Position::Top);
+        assert_eq!((top.top, top.bottom), (60, 0));
+        let corner = margins(Position::BottomRight);
+        assert_eq!((corner.bottom, corner.right), (60, 20));
+        assert_eq!(margins(Position::Left).left, 60);
+        assert_eq!(margins(Position::Default).top, 250 + 40);
+        assert_eq!(margins(Position::Center), Margins::default());
+    }
+
+    #[test]
+    fn slot_height_stacks_on_a_fixed_grid() {
+        let paths = test_paths();
+        let reserve = |name: &str, height| {
+            let mut request = burst_request(name, 0, false);
+            request.height = height;
+            request.slot_height = 80;
+            match reserve_stack_slot(&paths, request).expect("reserve") {
+                Reservation::Slot(offset, guard) => (offset, guard),
+                _ => panic!("expected a slot"),
+            }
+        };
+        let (first, _a) = reserve("tall", 60);
+        let (second, _b) = reserve("short", 20);
+        let (third, _c) = reserve("taller", 70);
+        assert_eq!((first, second, third), (0, 80, 160));
+    }
+
+    #[test]
+    fn summary_cuts_cjk_on_char_boundary() {
+        let paths = test_paths();
+        let mut request = burst_request("cjk", 0, false);
+        request.message = "漢字のテキストが長すぎる\nsecond line".to_string();
+        request.summary_length = 5;
+        let _guard = match reserve_stack_slot(&paths, request).expect("reserve") {
+            Reservation::Slot(_, guard) => guard,
+            _ => panic!("expected a slot"),
+        };
+        let state = load_state(&paths.state_path).expect("state");
+        assert_eq!(state.entries[0].summary, "漢字のテ…");
+        assert_eq!(message_summary("短い", 5, "…"), "短い");
+        assert_eq!(message_summary("  padded  ", 0, ""), "");
+        assert_eq!(message_summary("abcdefgh", 6, "..."), "abc...");
+    }
+
+    #[test]
+    fn long_emoji_summary_does_not_panic() {
+        let message = "🎉".repeat(200);
+        let summary = message_summary(&message, 120, "");
+        assert_eq!(summary.chars().count(), 120);
+        assert!(summary.chars().all(|c| c == '🎉'));
+        assert!(std::str::from_utf8(summary.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn full_stack_expires_oldest_or_drops() {
+        let paths = test_paths();
+        let capped = |name: &str, on_full| {
+            let mut request = burst_request(name, 0, false);
+            request.max_stack = 2;
+            request.on_full = on_full;
+            reserve_stack_slot(&paths, request).expect("reserve")
+        };
+        let slot = |reservation| match reservation {
+            Reservation::Slot(_, guard) => guard,
+            _ => panic!("expected a slot"),
+        };
+        let oldest = slot(capped("oldest", OnFull::Drop));
+        let _middle = slot(capped("middle", OnFull::Drop));
+        assert!(matches!(
+            capped("dropped", OnFull::Drop),
+            Reservation::Limited
+        ));
+
+        let newest = slot(capped("newest", OnFull::ExpireOldest));
+        let ids: Vec<u64> = load_state(&paths.state_path)
+            .expect("state")
+            .entries
+            .iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert!(!ids.contains(&oldest.id));
+        assert!(ids.contains(&newest.id));
+        assert_eq!(ids.len(), 2);
+        assert!(parse_on_full("expire-newest").is_err());
+    }
+
+    #[test]
+    fn replace_id_takes_over_slot_and_id() {
+        let paths = test_paths();
+        let reserve = |request| match reserve_stack_slot(&paths, request).expect("reserve") {
+            Reservation::Slot(offset, guard) => (offset, guard),
+            _ => panic!("expected a slot"),
+        };
+        let (_, first) = reserve(burst_request("first", 0, false));
+        let (second_offset, second) = reserve(burst_request("second", 0, false));
+        let mut request = burst_request("third", 0, false);
+        request.replace_id = Some(first.id);
+        let (offset, third) = reserve(request);
+        assert_eq!((offset, third.id), (0, first.id));
+
+        drop(first);
+        let state = load_state(&paths.state_path).expect("state");
+        let names: Vec<_> = state.entries.iter().map(|e| e.name.clone()).collect();
+        assert_eq!(
+            names,
+            [Some("third".to_string()), Some("second".to_string())]
+        );
+        assert_eq!(
+            stack_offset_for_id(&second)
+                .expect("offset")
+                .map(|(o, _)| o),
+            Some(second_offset)
+        );
+
+        let mut request = burst_request("fourth", 0, false);
+        request.replace_id = Some(999);
+        let (_, fourth) = reserve(request);
+        assert_ne!(fourth.id, 999);
+    }
+
+    #[test]
+    fn reserve_stack_slot_timeout_zero_is_non_expiring() {
+        let paths = test_paths();
+        let request = SlotRequest {
+            position: Position::Top,
+            height: 24,
+            gap: 5,
+            slot_height: 0,
+            order: StackOrder::Fifo,
+            priority: 0,
+            max_stack: 0,
+            on_full: OnFull::Drop,
+            timeout_ms: 0,
+            name: Some("forever".to_string()),
+            class: Some("test".to_string()),
+            app_name: Some("cron".to_string()),
+            message: "forever alert".to_string(),
+            served: false,
+            rate_limit: 0,
+            coalesce: false,
+            group_by: None,
+            bypass_dnd: false,
+            argv: Vec::new(),
+            replace_id: None,
+            summary_length: 120,
+            ellipsis: "…".to_string(),
+        };
+        let Reservation::Slot(_offset, _guard) =
+            reserve_stack_slot(&paths, request).expect("reserve")
+        else {
+            panic!("expected a slot");
+        };
 
         let state = load_state(&paths.state_path).expect("load state");
         assert_eq!(state.entries.len(), 1);
         assert_eq!(state.entries[0].expires_at, 0);
+        assert_eq!(state.entries[0].app_name.as_deref(), Some("cron"));
     }
 }